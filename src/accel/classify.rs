@@ -0,0 +1,224 @@
+//! # classify
+//! Turning the direction of gravity into a rotation (and telling whether
+//! the device is lying flat).
+
+use super::*;
+
+/// The option used to pick how an accelerometer's readings are classified
+pub const CLASSIFIER_OPT: &str = "classifier";
+
+/// How far (in degrees) the screen can tip back from upright before the
+/// angle classifier stops picking a rotation
+pub const DEFAULT_TILT_LIMIT: f64 = 65.0;
+
+/// How far (in degrees) past the 45° boundary between rotations the angle
+/// classifier sticks with the current one
+pub const DEFAULT_BAND: f64 = 10.0;
+
+/// The option used to set how flat counts as lying flat
+pub const FLAT_LIMIT_OPT: &str = "flat_limit";
+
+/// How far (in degrees) the screen has to tip back (or forward) from upright
+/// to be lying flat
+pub const DEFAULT_FLAT_LIMIT: f64 = 75.0;
+
+/// How an accelerometer's readings become a rotation.
+pub enum Classifier {
+    /// Whichever of x and y is bigger, as long as the difference beats
+    /// a threshold set by z and `--sensitivity`
+    Legacy,
+    Angle(AngleClassifier),
+}
+
+impl Classifier {
+    /// Get the classifier from the `classifier` option (and its own
+    /// options): `legacy` (the default) or `angle`.
+    pub fn from_opts(opts: &HashMap<String, String>) -> Result<Classifier, IoError> {
+        let bad = |opt: &str, value: &str| IoError::new(IoErrorKind::InvalidInput,
+                                                        format!("bad {} '{}'", opt, value));
+        let degrees = |opt: &str, default: f64, ok: fn(f64) -> bool| match opts.get(opt) {
+            Some(v) => match v.trim().parse::<f64>() {
+                Ok(d) if ok(d)  => Ok(d),
+                _   => Err(bad(opt, v)),
+            },
+            None    => Ok(default),
+        };
+        match opts.get(CLASSIFIER_OPT).map(|c| c.trim()) {
+            None | Some("legacy")   => Ok(Classifier::Legacy),
+            Some("angle")   => Ok(Classifier::Angle(AngleClassifier::new(
+                        degrees("tilt_limit", DEFAULT_TILT_LIMIT, |d| (0.0..=90.0).contains(&d))?,
+                        degrees("band", DEFAULT_BAND, |d| (0.0..45.0).contains(&d))?))),
+            Some(c) => Err(bad(CLASSIFIER_OPT, c)),
+        }
+    }
+
+    /// The rotation `acc` (the acceleration, which is mostly gravity)
+    /// points to, if any.
+    pub fn classify(&mut self, acc: AccelerationVector<f64>) -> Option<Rotation> {
+        match self {
+            Classifier::Legacy  => legacy(acc),
+            Classifier::Angle(c)    => c.classify(acc),
+        }
+    }
+}
+
+/// How far (in degrees) the screen is tipped back (or forward) from
+/// upright, if there's any acceleration to go by.
+fn tilt(acc: AccelerationVector<f64>) -> Option<f64> {
+    let magnitude = acc.norm();
+    if 0.0 == magnitude {
+        return None;
+    }
+    Some((acc.z.abs() / magnitude).asin().to_degrees())
+}
+
+/// Whether `acc` means the device is lying flat (tipped more than
+/// `flat_limit` degrees from upright), and which way up.
+pub fn posture(acc: AccelerationVector<f64>, flat_limit: f64) -> Option<Posture> {
+    match tilt(acc) {
+        Some(t) if t > flat_limit   => if acc.z < 0.0 {
+            Some(Posture::FaceUp)
+        } else {
+            Some(Posture::FaceDown)
+        },
+        Some(_) => Some(Posture::Upright),
+        None    => None,
+    }
+}
+
+fn legacy(acc: AccelerationVector<f64>) -> Option<Rotation> {
+    if (acc.x.abs() - acc.y.abs()).abs() > acc.z.abs() / sensitivity() + 1.4715 {
+        if acc.x.abs() > acc.y.abs() {
+            if acc.x < 0.0 {
+                trace!("rot: {}; accel: {}", Rotation::Right, acc);
+                Some(Rotation::Right)
+            } else {
+                trace!("rot: {}; accel: {}", Rotation::Left, acc);
+                Some(Rotation::Left)
+            }
+        } else {
+            if acc.y < 0.0 {
+                trace!("rot: {}; accel: {}", Rotation::Normal, acc);
+                Some(Rotation::Normal)
+            } else {
+                trace!("rot: {}; accel: {}", Rotation::Inverted, acc);
+                Some(Rotation::Inverted)
+            }
+        }
+    } else {
+        trace!("rot: {}; accel: {}", "None (dxy too low)", acc);
+        None
+    }
+}
+
+/// Picks the rotation from the angle gravity makes in the plane of the
+/// screen, like Android's WindowOrientationListener.
+///
+/// Nothing is picked while the screen is tipped back (or forward) more
+/// than `tilt_limit` from upright, since the angle means little when it's
+/// nearly flat. Around each 45° boundary there's a band of `band` degrees
+/// where the current rotation is kept, so it doesn't flicker between two.
+pub struct AngleClassifier {
+    tilt_limit: f64,
+    band: f64,
+    current: Option<Rotation>,
+}
+
+impl AngleClassifier {
+    pub fn new(tilt_limit: f64, band: f64) -> AngleClassifier {
+        AngleClassifier {
+            tilt_limit,
+            band,
+            current: None,
+        }
+    }
+
+    pub fn classify(&mut self, acc: AccelerationVector<f64>) -> Option<Rotation> {
+        let tilt = tilt(acc)?;
+        if tilt > self.tilt_limit {
+            trace!("rot: None (tilted {:.0}°); accel: {}", tilt, acc);
+            return None;
+        }
+        // 0° is upright, going round the same way as the rotations
+        let angle = acc.x.atan2(-acc.y).to_degrees().rem_euclid(360.0);
+        let rotation = match self.current {
+            Some(c) if angle_between(angle, degrees(c)) <= 45.0 + self.band    => c,
+            _   => nearest(angle),
+        };
+        trace!("rot: {} (at {:.0}°, tilted {:.0}°); accel: {}", rotation, angle, tilt, acc);
+        self.current = Some(rotation);
+        Some(rotation)
+    }
+}
+
+/// Where gravity points (in the plane of the screen) when the screen is
+/// the right way up for `rotation`.
+fn degrees(rotation: Rotation) -> f64 {
+    match rotation {
+        Rotation::Normal    => 0.0,
+        Rotation::Left      => 90.0,
+        Rotation::Inverted  => 180.0,
+        Rotation::Right     => 270.0,
+    }
+}
+
+/// The rotation closest to `angle`.
+fn nearest(angle: f64) -> Rotation {
+    match ((angle / 90.0).round() as i32).rem_euclid(4) {
+        0   => Rotation::Normal,
+        1   => Rotation::Left,
+        2   => Rotation::Inverted,
+        _   => Rotation::Right,
+    }
+}
+
+/// How far apart two angles are, going the short way round.
+fn angle_between(a: f64, b: f64) -> f64 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gravity pointing `angle` degrees round from upright, with the
+    /// screen tipped back by `tilt` degrees
+    fn gravity(angle: f64, tilt: f64) -> AccelerationVector<f64> {
+        let (a, t) = (angle.to_radians(), tilt.to_radians());
+        AccelerationVector {
+            x: 9.81 * t.cos() * a.sin(),
+            y: -9.81 * t.cos() * a.cos(),
+            z: -9.81 * t.sin(),
+        }
+    }
+
+    #[test]
+    fn sticks_with_the_current_rotation() {
+        let mut c = AngleClassifier::new(DEFAULT_TILT_LIMIT, 10.0);
+        assert_eq!(Some(Rotation::Normal), c.classify(gravity(10.0, 20.0)));
+        assert_eq!(Some(Rotation::Normal), c.classify(gravity(50.0, 20.0)));
+        assert_eq!(Some(Rotation::Left), c.classify(gravity(56.0, 20.0)));
+        assert_eq!(Some(Rotation::Left), c.classify(gravity(40.0, 20.0)));
+        assert_eq!(Some(Rotation::Normal), c.classify(gravity(-10.0, 20.0)));
+        assert_eq!(Some(Rotation::Right), c.classify(gravity(260.0, 20.0)));
+        assert_eq!(Some(Rotation::Inverted), c.classify(gravity(180.0, 0.0)));
+    }
+
+    #[test]
+    fn lying_flat() {
+        assert_eq!(Some(Posture::FaceUp), posture(gravity(0.0, 80.0), DEFAULT_FLAT_LIMIT));
+        assert_eq!(Some(Posture::FaceDown), posture(gravity(0.0, -80.0), DEFAULT_FLAT_LIMIT));
+        assert_eq!(Some(Posture::Upright), posture(gravity(90.0, 70.0), DEFAULT_FLAT_LIMIT));
+        assert_eq!(None, posture(AccelerationVector::default(), DEFAULT_FLAT_LIMIT));
+    }
+
+    #[test]
+    fn too_flat_is_nothing() {
+        let mut c = AngleClassifier::new(60.0, DEFAULT_BAND);
+        assert_eq!(None, c.classify(gravity(90.0, 61.0)));
+        assert_eq!(Some(Rotation::Left), c.classify(gravity(90.0, 59.0)));
+        assert_eq!(None, c.classify(AccelerationVector::default()));
+    }
+}
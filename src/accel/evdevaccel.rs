@@ -0,0 +1,519 @@
+//! evdevaccel.rs
+//!
+//! A module for representing an accelerometer exposed through the input
+//! subsystem (`/dev/input/eventN` with `INPUT_PROP_ACCELEROMETER`).
+
+use super::AccelerationVector as AVector;
+use super::{MountMatrix, STANDARD_GRAVITY};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::fs::{File,OpenOptions};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use glob::glob;
+use libc;
+
+type IoResult<T> = Result<T, IoError>;
+
+pub const DEFAULT_EVDEVACCEL_PATH: &str = "/dev/input/event*";
+
+// From linux/input-event-codes.h
+const INPUT_PROP_ACCELEROMETER: usize = 0x06;
+const INPUT_PROP_CNT: usize = 0x20;
+const EV_SYN: u16 = 0x00;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const SYN_DROPPED: u16 = 3;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_Z: u16 = 0x02;
+const ABS_CNT: usize = 0x40;
+
+// From linux/input.h and asm-generic/ioctl.h
+const IOC_READ: libc::c_ulong = 2;
+
+/// Build a read ioctl request number for the evdev ('E') ioctls.
+fn eviocr(nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+    (IOC_READ << 30) | ((size as libc::c_ulong) << 16) | ((b'E' as libc::c_ulong) << 8) | nr
+}
+
+/// `EVIOCGPROP(len)`: get the device's property bitmap
+fn eviocgprop(len: usize) -> libc::c_ulong { eviocr(0x09, len) }
+/// `EVIOCGBIT(ev, len)`: get the event bitmap for event type `ev`
+fn eviocgbit(ev: u16, len: usize) -> libc::c_ulong { eviocr(0x20 + ev as libc::c_ulong, len) }
+/// `EVIOCGABS(abs)`: get the state of absolute axis `abs`
+fn eviocgabs(abs: u16) -> libc::c_ulong {
+    eviocr(0x40 + abs as libc::c_ulong, mem::size_of::<libc::input_absinfo>())
+}
+
+/// Whether bit `bit` is set in a kernel bitmap
+fn test_bit(bits: &[u8], bit: usize) -> bool {
+    bits.get(bit / 8).is_some_and(|b| 0 != b & (1 << (bit % 8)))
+}
+
+/// Fill `buf` with the bitmap returned by ioctl `req`.
+fn get_bits(dev: &File, req: libc::c_ulong, buf: &mut [u8]) -> IoResult<()> {
+    if unsafe { libc::ioctl(dev.as_raw_fd(), req, buf.as_mut_ptr()) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+/// Get the current state of absolute axis `abs`.
+fn get_absinfo(dev: &File, abs: u16) -> IoResult<libc::input_absinfo> {
+    let mut info: libc::input_absinfo = unsafe { mem::zeroed() };
+    if unsafe { libc::ioctl(dev.as_raw_fd(), eviocgabs(abs), &mut info) } < 0 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(info)
+}
+
+/// Check whether an open event device is a 3-axis accelerometer.
+fn is_accelerometer(dev: &File) -> IoResult<bool> {
+    let mut props = [0u8; INPUT_PROP_CNT / 8];
+    get_bits(dev, eviocgprop(props.len()), &mut props)?;
+    if !test_bit(&props, INPUT_PROP_ACCELEROMETER) {
+        return Ok(false);
+    }
+    let mut abs = [0u8; ABS_CNT / 8];
+    get_bits(dev, eviocgbit(EV_ABS, abs.len()), &mut abs)?;
+    Ok(is_accelerometer_bits(&props, &abs))
+}
+
+/// Whether a device with these property and absolute axis bitmaps is a
+/// 3-axis accelerometer.
+fn is_accelerometer_bits(props: &[u8], abs: &[u8]) -> bool {
+    test_bit(props, INPUT_PROP_ACCELEROMETER)
+        && [ABS_X, ABS_Y, ABS_Z].iter().all(|a| test_bit(abs, *a as usize))
+}
+
+/// The scale for a device: the `scale` option if it's given, otherwise
+/// worked out from the x axis's resolution (accelerometer resolutions are
+/// given in units per g).
+fn scale_for(opt: Option<&String>, resolution: i32, path: &Path) -> IoResult<f64> {
+    match opt {
+        Some(s) => s.parse::<f64>().map_err(|e| IoError::new(
+                IoErrorKind::InvalidInput, format!("bad scale '{}': {}", s, e))),
+        None if resolution > 0  => Ok(STANDARD_GRAVITY / resolution as f64),
+        None    => Err(IoError::new(IoErrorKind::InvalidData, format!(
+                    "{} doesn't report a resolution; set the scale option",
+                    path.to_string_lossy()))),
+    }
+}
+
+/// Puts axis events together into complete readings.
+#[derive(Debug)]
+struct Events {
+    /// The last complete (SYN_REPORTed) state of the axes
+    current: AVector<i32>,
+    /// Axis values seen since the last SYN_REPORT
+    pending: AVector<i32>,
+    /// Whether we're dropping events until the next SYN_REPORT
+    dropped: bool,
+}
+
+impl Events {
+    fn new(current: AVector<i32>) -> Events {
+        Events {
+            current,
+            pending: current,
+            dropped: false,
+        }
+    }
+
+    /// Take in one event. Returns whether the axes have to be read straight
+    /// from the device, since some were dropped.
+    fn handle(&mut self, type_: u16, code: u16, value: i32) -> bool {
+        match (type_, code) {
+            (EV_SYN, SYN_DROPPED)   => self.dropped = true,
+            (EV_SYN, SYN_REPORT) if self.dropped    => {
+                self.dropped = false;
+                return true;
+            },
+            (EV_SYN, SYN_REPORT)    => self.current = self.pending,
+            _ if self.dropped   => {},
+            (EV_ABS, ABS_X) => self.pending.x = value,
+            (EV_ABS, ABS_Y) => self.pending.y = value,
+            (EV_ABS, ABS_Z) => self.pending.z = value,
+            _   => {},
+        }
+        false
+    }
+
+    /// Start again from axes read straight from the device.
+    fn resync(&mut self, current: AVector<i32>) {
+        self.current = current;
+        self.pending = current;
+    }
+}
+
+/// Open an event device without blocking on reads.
+fn open_event_device(path: &PathBuf) -> IoResult<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
+
+/// Find the first event device that is an accelerometer, the way
+/// `fsaccel::guess_path` looks for `accel_3d`.
+fn guess_path(base_path: &str) -> IoResult<(PathBuf, File)> {
+    for p in glob(base_path).map_err(|e|
+            IoError::new(IoErrorKind::InvalidInput, e.msg))?.flatten() {
+        match open_event_device(&p).and_then(|f| is_accelerometer(&f).map(|a| (a, f))) {
+            Ok((true, f))   => return Ok((p, f)),
+            Ok((false, _))  => {},
+            Err(e)  => trace!("Skipping {}: {}", p.to_string_lossy(), e),
+        }
+    }
+    Err(IoError::new(IoErrorKind::AddrNotAvailable, format!("No accelerometer found within {}!", base_path)))
+}
+
+#[derive(Debug)]
+pub struct EvdevAccelerometer {
+    path: PathBuf,
+    dev: File,
+    scale: f64,
+    mount: MountMatrix,
+    events: Events,
+}
+
+impl EvdevAccelerometer {
+    /// Creates a new EvdevAccelerometer with the specified options.
+    pub fn from_opts(opts: &mut HashMap<String, String>) -> IoResult<EvdevAccelerometer> {
+        debug!("Creating EvdevAccelerometer with the following options: {:?}", opts);
+        let (path, dev) = match opts.get("path") {
+            Some(p) => {
+                let path = PathBuf::from(p);
+                let dev = open_event_device(&path)?;
+                if !is_accelerometer(&dev)? {
+                    warn!("{} doesn't say it's an accelerometer; using it anyway", p);
+                }
+                (path, dev)
+            },
+            None    => {
+                let (path, dev) = guess_path(DEFAULT_EVDEVACCEL_PATH)?;
+                opts.insert("path".into(), path.to_string_lossy().into_owned());
+                (path, dev)
+            },
+        };
+        debug!("EvdevAccel path is {}", &path.to_string_lossy());
+
+        let x = get_absinfo(&dev, ABS_X)?;
+        let y = get_absinfo(&dev, ABS_Y)?;
+        let z = get_absinfo(&dev, ABS_Z)?;
+        let scale = scale_for(opts.get("scale"), x.resolution, &path)?;
+        debug!("Scale is {}", &scale);
+        // The input subsystem has no mount matrix of its own; udev's
+        // ACCEL_MOUNT_MATRIX has to be passed in as an option.
+        let mount = MountMatrix::from_opts(opts, None)?;
+        Ok(EvdevAccelerometer {
+            path,
+            dev,
+            scale,
+            mount,
+            events: Events::new(AVector::<i32> { x: x.value, y: y.value, z: z.value }),
+        })
+    }
+
+    /// Re-read the axes straight from the device (after a SYN_DROPPED).
+    fn resync(&mut self) -> IoResult<()> {
+        debug!("Events dropped on {}; resyncing", self.path.to_string_lossy());
+        self.events.resync(AVector::<i32> {
+            x: get_absinfo(&self.dev, ABS_X)?.value,
+            y: get_absinfo(&self.dev, ABS_Y)?.value,
+            z: get_absinfo(&self.dev, ABS_Z)?.value,
+        });
+        Ok(())
+    }
+
+    /// Process every event waiting on the device.
+    fn update(&mut self) -> IoResult<AVector<i32>> {
+        const EV_SIZE: usize = mem::size_of::<libc::input_event>();
+        let mut events: [libc::input_event; 32] = unsafe { mem::zeroed() };
+        loop {
+            let n = unsafe {
+                libc::read(self.dev.as_raw_fd(),
+                           events.as_mut_ptr() as *mut libc::c_void,
+                           EV_SIZE * events.len())
+            };
+            if n < 0 {
+                let e = IoError::last_os_error();
+                match e.kind() {
+                    IoErrorKind::WouldBlock => break,
+                    IoErrorKind::Interrupted    => continue,
+                    _   => return Err(IoError::new(e.kind(), format!(
+                                "couldn't read events from {}: {}", self.path.to_string_lossy(), e))),
+                }
+            }
+            let n = n as usize / EV_SIZE;
+            for ev in events[..n].iter() {
+                if self.events.handle(ev.type_, ev.code, ev.value) {
+                    self.resync()?;
+                }
+            }
+            if n < events.len() { break }
+        }
+        Ok(self.events.current)
+    }
+}
+
+impl super::Accelerometer for EvdevAccelerometer {
+    fn read(&mut self) -> IoResult<AVector<f64>> {
+        let av = self.update()?;
+        Ok(self.mount.rotate(AVector::<f64> {
+            x: { av.x as f64 * self.scale },
+            y: { av.y as f64 * self.scale },
+            z: { av.z as f64 * self.scale },
+        }))
+    }
+    fn read_raw(&mut self) -> IoResult<AVector<i32>> {
+        let av = self.update()?;
+        Ok(self.mount.rotate_raw(av))
+    }
+    fn get_scale(&self) -> f64 {
+        self.scale
+    }
+    fn event_fd(&self) -> Option<RawFd> {
+        Some(self.dev.as_raw_fd())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Accelerometer;
+    use std::ffi::CStr;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    // From linux/uinput.h
+    const IOC_WRITE: libc::c_ulong = 1;
+    const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+    const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+    const UI_SET_EVBIT: libc::c_ulong = 100;
+    const UI_SET_ABSBIT: libc::c_ulong = 103;
+    const UI_SET_PROPBIT: libc::c_ulong = 110;
+
+    /// Counts per g of the fake accelerometer
+    const RESOLUTION: i32 = 1024;
+
+    /// Build a uinput ('U') ioctl request number.
+    fn uioc(dir: libc::c_ulong, nr: libc::c_ulong, size: usize) -> libc::c_ulong {
+        (dir << 30) | ((size as libc::c_ulong) << 16) | ((b'U' as libc::c_ulong) << 8) | nr
+    }
+
+    /// A virtual accelerometer made through `/dev/uinput`, destroyed on
+    /// drop.
+    struct VirtualAccel {
+        uinput: File,
+        /// The event device the kernel made for it
+        node: PathBuf,
+    }
+
+    impl VirtualAccel {
+        /// Make a virtual accelerometer.
+        fn new() -> VirtualAccel {
+            let uinput = OpenOptions::new().write(true)
+                .custom_flags(libc::O_NONBLOCK).open("/dev/uinput")
+                .expect("can't open /dev/uinput");
+            let fd = uinput.as_raw_fd();
+            let int = mem::size_of::<libc::c_int>();
+            unsafe {
+                assert!(0 <= libc::ioctl(fd, uioc(IOC_WRITE, UI_SET_PROPBIT, int),
+                                         INPUT_PROP_ACCELEROMETER as libc::c_int));
+                assert!(0 <= libc::ioctl(fd, uioc(IOC_WRITE, UI_SET_EVBIT, int),
+                                         EV_ABS as libc::c_int));
+                for &abs in &[ABS_X, ABS_Y, ABS_Z] {
+                    assert!(0 <= libc::ioctl(fd, uioc(IOC_WRITE, UI_SET_ABSBIT, int),
+                                             abs as libc::c_int));
+                    let mut setup: libc::uinput_abs_setup = mem::zeroed();
+                    setup.code = abs;
+                    setup.absinfo.minimum = -4 * RESOLUTION;
+                    setup.absinfo.maximum = 4 * RESOLUTION;
+                    setup.absinfo.resolution = RESOLUTION;
+                    assert!(0 <= libc::ioctl(fd, uioc(IOC_WRITE, 4,
+                            mem::size_of::<libc::uinput_abs_setup>()), &setup));
+                }
+                let mut setup: libc::uinput_setup = mem::zeroed();
+                setup.id.bustype = 0x06; // BUS_VIRTUAL
+                for (d, s) in setup.name.iter_mut().zip(b"spinnrd test accelerometer") {
+                    *d = *s as libc::c_char;
+                }
+                assert!(0 <= libc::ioctl(fd, uioc(IOC_WRITE, 3,
+                        mem::size_of::<libc::uinput_setup>()), &setup));
+                assert!(0 <= libc::ioctl(fd, UI_DEV_CREATE));
+            }
+
+            let mut sysname = [0 as libc::c_char; 64];
+            assert!(0 <= unsafe {
+                libc::ioctl(fd, uioc(IOC_READ, 44, sysname.len()), sysname.as_mut_ptr())
+            });
+            let sysname = unsafe { CStr::from_ptr(sysname.as_ptr()) }.to_string_lossy().into_owned();
+            let class = PathBuf::from("/sys/devices/virtual/input").join(&sysname);
+            // udev (or devtmpfs) may take a moment to make the node.
+            for _ in 0..50 {
+                let event = fs::read_dir(&class).ok().and_then(|d| d.flatten()
+                    .map(|e| e.file_name().to_string_lossy().into_owned())
+                    .find(|n| n.starts_with("event")));
+                if let Some(event) = event {
+                    let node = PathBuf::from("/dev/input").join(event);
+                    if node.exists() {
+                        return VirtualAccel { uinput, node };
+                    }
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            panic!("no event device showed up for {}", sysname);
+        }
+
+        /// Send a single event.
+        fn emit(&self, type_: u16, code: u16, value: i32) {
+            let mut ev: libc::input_event = unsafe { mem::zeroed() };
+            ev.type_ = type_;
+            ev.code = code;
+            ev.value = value;
+            let size = mem::size_of::<libc::input_event>();
+            assert_eq!(size as isize, unsafe {
+                libc::write(self.uinput.as_raw_fd(), &ev as *const _ as *const libc::c_void, size)
+            });
+        }
+
+        /// Send a full reading.
+        fn report(&self, x: i32, y: i32, z: i32) {
+            self.emit(EV_ABS, ABS_X, x);
+            self.emit(EV_ABS, ABS_Y, y);
+            self.emit(EV_ABS, ABS_Z, z);
+            self.emit(EV_SYN, SYN_REPORT, 0);
+        }
+
+        fn open(&self, mount_matrix: Option<&str>) -> EvdevAccelerometer {
+            let mut opts = HashMap::new();
+            opts.insert("path".to_owned(), self.node.to_string_lossy().into_owned());
+            if let Some(m) = mount_matrix {
+                opts.insert(super::super::MOUNT_MATRIX_OPT.to_owned(), m.to_owned());
+            }
+            EvdevAccelerometer::from_opts(&mut opts).unwrap()
+        }
+    }
+
+    impl Drop for VirtualAccel {
+        fn drop(&mut self) {
+            unsafe { libc::ioctl(self.uinput.as_raw_fd(), UI_DEV_DESTROY); }
+        }
+    }
+
+    fn close(expected: f64, got: f64) -> bool {
+        (expected - got).abs() < 1e-9
+    }
+
+    fn vector(x: i32, y: i32, z: i32) -> AVector<i32> {
+        AVector::<i32> { x, y, z }
+    }
+
+    fn axes(v: &AVector<i32>) -> (i32, i32, i32) {
+        (v.x, v.y, v.z)
+    }
+
+    #[test]
+    fn recognises_accelerometers() {
+        let mut props = [0u8; INPUT_PROP_CNT / 8];
+        let mut abs = [0u8; ABS_CNT / 8];
+        abs[0] = 0b111;
+        assert!(!is_accelerometer_bits(&props, &abs));
+        props[0] = 1 << INPUT_PROP_ACCELEROMETER;
+        assert!(is_accelerometer_bits(&props, &abs));
+        // Two axes won't do.
+        abs[0] = 0b011;
+        assert!(!is_accelerometer_bits(&props, &abs));
+        assert!(!is_accelerometer_bits(&props, &[]));
+    }
+
+    #[test]
+    fn picks_scale() {
+        let path = PathBuf::from("/dev/input/event9");
+        assert!(close(STANDARD_GRAVITY / 256.0, scale_for(None, 256, &path).unwrap()));
+        assert!(close(0.5, scale_for(Some(&"0.5".to_owned()), 256, &path).unwrap()));
+        assert!(close(0.5, scale_for(Some(&"0.5".to_owned()), 0, &path).unwrap()));
+        assert_eq!(IoErrorKind::InvalidInput,
+                   scale_for(Some(&"lots".to_owned()), 256, &path).unwrap_err().kind());
+        assert_eq!(IoErrorKind::InvalidData, scale_for(None, 0, &path).unwrap_err().kind());
+    }
+
+    #[test]
+    fn events_make_readings() {
+        let mut events = Events::new(vector(1, 2, 3));
+        assert!(!events.handle(EV_ABS, ABS_X, 10));
+        assert!(!events.handle(EV_ABS, ABS_Z, 30));
+        // Nothing counts until the report...
+        assert_eq!((1, 2, 3), axes(&events.current));
+        assert!(!events.handle(EV_SYN, SYN_REPORT, 0));
+        assert_eq!((10, 2, 30), axes(&events.current));
+        // ...and other events are ignored.
+        assert!(!events.handle(EV_ABS, 0x28, 5));
+        assert!(!events.handle(EV_SYN, SYN_REPORT, 0));
+        assert_eq!((10, 2, 30), axes(&events.current));
+    }
+
+    #[test]
+    fn events_resync_after_drop() {
+        let mut events = Events::new(vector(1, 2, 3));
+        assert!(!events.handle(EV_ABS, ABS_X, 10));
+        assert!(!events.handle(EV_SYN, SYN_DROPPED, 0));
+        // Everything up to the next report is thrown away...
+        assert!(!events.handle(EV_ABS, ABS_Y, 20));
+        assert!(events.handle(EV_SYN, SYN_REPORT, 0));
+        assert_eq!((1, 2, 3), axes(&events.current));
+        // ...and it starts again from what the device says.
+        events.resync(vector(7, 8, 9));
+        assert!(!events.handle(EV_ABS, ABS_Y, 80));
+        assert!(!events.handle(EV_SYN, SYN_REPORT, 0));
+        assert_eq!((7, 80, 9), axes(&events.current));
+    }
+
+    #[test]
+    #[ignore = "needs /dev/uinput"]
+    fn scales_by_resolution() {
+        let dev = VirtualAccel::new();
+        let mut accel = dev.open(None);
+        assert!(close(STANDARD_GRAVITY / RESOLUTION as f64, accel.get_scale()));
+        dev.report(0, -RESOLUTION, RESOLUTION / 2);
+        let av = accel.read().unwrap();
+        assert!(close(0.0, av.x) && close(-STANDARD_GRAVITY, av.y) && close(STANDARD_GRAVITY / 2.0, av.z),
+                "got {}", av);
+    }
+
+    #[test]
+    #[ignore = "needs /dev/uinput"]
+    fn applies_mount_matrix() {
+        let dev = VirtualAccel::new();
+        let mut accel = dev.open(Some("0, 1, 0; -1, 0, 0; 0, 0, 1"));
+        dev.report(100, 200, 300);
+        let av = accel.read_raw().unwrap();
+        assert_eq!((200, -100, 300), (av.x, av.y, av.z));
+    }
+
+    #[test]
+    #[ignore = "needs /dev/uinput"]
+    fn resyncs_after_dropped_events() {
+        let dev = VirtualAccel::new();
+        let mut accel = dev.open(None);
+        // Far more than the client's buffer holds, so the kernel has to
+        // drop some and tell us with SYN_DROPPED.
+        for i in 1..=500 {
+            dev.report(i, -i, i % 7);
+        }
+        let av = accel.read_raw().unwrap();
+        assert_eq!((500, -500, 500 % 7), (av.x, av.y, av.z));
+        dev.report(1, 2, 3);
+        let av = accel.read_raw().unwrap();
+        assert_eq!((1, 2, 3), (av.x, av.y, av.z));
+    }
+}
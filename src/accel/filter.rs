@@ -0,0 +1,374 @@
+//! # filter
+//! Filters for smoothing accelerometer readings, which can be chained
+//! together (see `FilterChain`).
+
+use super::AccelerationVector;
+
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::fmt::Result as FmtResult;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The option used to pick a backend's filters
+pub const FILTER_OPT: &str = "filter";
+
+/// The process noise for `kalman` if it isn't given ((m/s^2)^2 per second)
+pub const DEFAULT_KALMAN_Q: f64 = 1.0;
+
+/// The measurement noise for `kalman` if it isn't given ((m/s^2)^2)
+pub const DEFAULT_KALMAN_R: f64 = 1.0;
+
+type Vector = AccelerationVector<f64>;
+
+#[derive(Debug)]
+pub enum FilterError {
+    /// There's no filter by that name
+    NoSuchFilter(String),
+    /// A filter was given the wrong number of parameters
+    WrongParams(String),
+    /// A parameter couldn't be used
+    BadParam(String, String),
+}
+
+impl Display for FilterError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            FilterError::NoSuchFilter(f)    => write!(fmt, "there's no filter called '{}'", f),
+            FilterError::WrongParams(f) => {
+                write!(fmt, "wrong number of parameters for filter '{}'", f)
+            },
+            FilterError::BadParam(f, p) => {
+                write!(fmt, "bad parameter '{}' for filter '{}'", p, f)
+            },
+        }
+    }
+}
+
+impl std::error::Error for FilterError {
+    fn description(&self) -> &str {
+        "bad filter"
+    }
+}
+
+/// One stage of filtering.
+pub trait Filter {
+    /// Start over from `reading`, taken at `at`.
+    fn reset(&mut self, reading: Vector, at: Instant);
+
+    /// Take in `reading`, taken at `at`, and give the filtered output.
+    fn filter(&mut self, reading: Vector, at: Instant) -> Vector;
+}
+
+/// An exponential moving average (a single-pole low-pass filter).
+///
+/// It goes by how long it's actually been between readings, so its time
+/// constant (how long it takes to get about 63% of the way to a new
+/// reading) holds however often (or irregularly) it's read.
+#[derive(Debug, Clone)]
+pub struct Ema {
+    /// The time constant, in seconds
+    time_constant: f64,
+    current: Vector,
+    updated: Instant,
+}
+
+impl Ema {
+    pub fn new(time_constant: Duration) -> Ema {
+        Ema {
+            time_constant: time_constant.as_secs_f64(),
+            current: Vector::default(),
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl Filter for Ema {
+    fn reset(&mut self, reading: Vector, at: Instant) {
+        self.current = reading;
+        self.updated = at;
+    }
+
+    fn filter(&mut self, reading: Vector, at: Instant) -> Vector {
+        let elapsed = at.saturating_duration_since(self.updated).as_secs_f64();
+        // How far a first-order low-pass filter gets in `elapsed`, exactly
+        // (rather than assuming readings are a fixed, short time apart)
+        let mult = if 0.0 < self.time_constant {
+            1.0 - (-elapsed / self.time_constant).exp()
+        } else {
+            1.0
+        };
+        self.current += (reading - self.current) * mult;
+        self.updated = at;
+        self.current
+    }
+}
+
+/// The median of the last few readings (of each axis), which gets rid of
+/// one-off spikes without smearing real changes.
+#[derive(Debug, Clone)]
+pub struct Median {
+    window: Window,
+}
+
+impl Filter for Median {
+    fn reset(&mut self, reading: Vector, _: Instant) {
+        self.window.reset(reading);
+    }
+
+    fn filter(&mut self, reading: Vector, _: Instant) -> Vector {
+        self.window.push(reading);
+        let median = |axis: fn(&Vector) -> f64| {
+            let mut values: Vec<f64> = self.window.readings.iter().map(axis).collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+            let mid = values.len() / 2;
+            if values.len().is_multiple_of(2) {
+                (values[mid - 1] + values[mid]) / 2.0
+            } else {
+                values[mid]
+            }
+        };
+        Vector {
+            x: median(|v| v.x),
+            y: median(|v| v.y),
+            z: median(|v| v.z),
+        }
+    }
+}
+
+/// The average of the last few readings.
+#[derive(Debug, Clone)]
+pub struct Mean {
+    window: Window,
+}
+
+impl Filter for Mean {
+    fn reset(&mut self, reading: Vector, _: Instant) {
+        self.window.reset(reading);
+    }
+
+    fn filter(&mut self, reading: Vector, _: Instant) -> Vector {
+        self.window.push(reading);
+        let mut sum = Vector::default();
+        for &r in &self.window.readings {
+            sum += r;
+        }
+        sum / self.window.readings.len() as f64
+    }
+}
+
+/// The last few readings
+#[derive(Debug, Clone)]
+struct Window {
+    size: usize,
+    readings: VecDeque<Vector>,
+}
+
+impl Window {
+    fn new(size: usize) -> Window {
+        Window {
+            size,
+            readings: VecDeque::with_capacity(size),
+        }
+    }
+
+    fn reset(&mut self, reading: Vector) {
+        self.readings.clear();
+        self.readings.push_back(reading);
+    }
+
+    fn push(&mut self, reading: Vector) {
+        if self.readings.len() >= self.size {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(reading);
+    }
+}
+
+/// A Kalman filter on each axis, assuming the acceleration stays put
+/// except for noise.
+///
+/// `q` is how much the real acceleration wanders ((m/s^2)^2 per second) and
+/// `r` is how noisy readings are ((m/s^2)^2); the higher `q` is compared to
+/// `r`, the more it trusts new readings.
+#[derive(Debug, Clone)]
+pub struct Kalman {
+    q: f64,
+    r: f64,
+    estimate: Vector,
+    /// The variance of `estimate` (the same on every axis, since they all
+    /// get the same updates)
+    variance: f64,
+    updated: Instant,
+}
+
+impl Kalman {
+    pub fn new(q: f64, r: f64) -> Kalman {
+        Kalman {
+            q,
+            r,
+            estimate: Vector::default(),
+            variance: r,
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl Filter for Kalman {
+    fn reset(&mut self, reading: Vector, at: Instant) {
+        self.estimate = reading;
+        self.variance = self.r;
+        self.updated = at;
+    }
+
+    fn filter(&mut self, reading: Vector, at: Instant) -> Vector {
+        let elapsed = at.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = at;
+        let predicted = self.variance + self.q * elapsed;
+        let gain = if 0.0 < predicted + self.r {
+            predicted / (predicted + self.r)
+        } else {
+            1.0
+        };
+        self.estimate += (reading - self.estimate) * gain;
+        self.variance = (1.0 - gain) * predicted;
+        self.estimate
+    }
+}
+
+pub enum FilterKind {
+    Ema(Ema),
+    Median(Median),
+    Mean(Mean),
+    Kalman(Kalman),
+}
+
+impl Filter for FilterKind {
+    fn reset(&mut self, reading: Vector, at: Instant) {
+        match self {
+            FilterKind::Ema(f)  => f.reset(reading, at),
+            FilterKind::Median(f)   => f.reset(reading, at),
+            FilterKind::Mean(f) => f.reset(reading, at),
+            FilterKind::Kalman(f)   => f.reset(reading, at),
+        }
+    }
+
+    fn filter(&mut self, reading: Vector, at: Instant) -> Vector {
+        match self {
+            FilterKind::Ema(f)  => f.filter(reading, at),
+            FilterKind::Median(f)   => f.filter(reading, at),
+            FilterKind::Mean(f) => f.filter(reading, at),
+            FilterKind::Kalman(f)   => f.filter(reading, at),
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = FilterError;
+
+    /// Parse a filter: `ema:<time constant in ms>`, `median:<readings>`,
+    /// `mean:<readings>`, or `kalman[:<q>:<r>]`.
+    fn from_str(s: &str) -> Result<FilterKind, FilterError> {
+        let mut parts = s.trim().split(':').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let params: Vec<&str> = parts.collect();
+        let bad = |p: &str| FilterError::BadParam(name.to_owned(), p.to_owned());
+        let window = |params: &[&str]| match params {
+            [n] => match n.parse::<usize>() {
+                Ok(n) if 0 < n  => Ok(Window::new(n)),
+                _   => Err(bad(n)),
+            },
+            _   => Err(FilterError::WrongParams(name.to_owned())),
+        };
+        match name {
+            "ema"   => match params[..] {
+                [t] => t.parse::<u64>()
+                    .map(|t| FilterKind::Ema(Ema::new(Duration::from_millis(t))))
+                    .map_err(|_| bad(t)),
+                _   => Err(FilterError::WrongParams(name.to_owned())),
+            },
+            "median"    => Ok(FilterKind::Median(Median { window: window(&params)? })),
+            "mean"  => Ok(FilterKind::Mean(Mean { window: window(&params)? })),
+            "kalman"    => {
+                let noise = |p: &str| match p.parse::<f64>() {
+                    Ok(n) if 0.0 <= n   => Ok(n),
+                    _   => Err(bad(p)),
+                };
+                match params[..] {
+                    []  => Ok(FilterKind::Kalman(Kalman::new(DEFAULT_KALMAN_Q, DEFAULT_KALMAN_R))),
+                    [q, r]  => Ok(FilterKind::Kalman(Kalman::new(noise(q)?, noise(r)?))),
+                    _   => Err(FilterError::WrongParams(name.to_owned())),
+                }
+            },
+            _   => Err(FilterError::NoSuchFilter(name.to_owned())),
+        }
+    }
+}
+
+/// Filters that are applied one after the other.
+///
+/// Written as the filters separated by `+` (e.g. `median:5+ema:1000`);
+/// `none` (or nothing) is no filtering at all.
+#[derive(Default)]
+pub struct FilterChain(Vec<FilterKind>);
+
+impl FilterChain {
+    /// Just an exponential moving average with a time constant of
+    /// `hysteresis`.
+    pub fn ema(hysteresis: Duration) -> FilterChain {
+        FilterChain(vec![FilterKind::Ema(Ema::new(hysteresis))])
+    }
+}
+
+impl Filter for FilterChain {
+    fn reset(&mut self, reading: Vector, at: Instant) {
+        for f in &mut self.0 {
+            f.reset(reading, at);
+        }
+    }
+
+    fn filter(&mut self, reading: Vector, at: Instant) -> Vector {
+        self.0.iter_mut().fold(reading, |r, f| f.filter(r, at))
+    }
+}
+
+impl FromStr for FilterChain {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<FilterChain, FilterError> {
+        if s.trim().is_empty() || "none" == s.trim() {
+            return Ok(FilterChain::default());
+        }
+        s.split('+').map(str::parse).collect::<Result<_, _>>().map(FilterChain)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v(y: f64) -> Vector {
+        Vector { x: 0.0, y, z: 0.0 }
+    }
+
+    #[test]
+    fn median_ignores_spikes() {
+        let mut chain: FilterChain = "median:3".parse().unwrap();
+        let now = Instant::now();
+        chain.reset(v(-9.81), now);
+        assert_eq!(-9.81, chain.filter(v(-9.81), now).y);
+        assert_eq!(-9.81, chain.filter(v(40.0), now).y);
+        assert_eq!(-9.81, chain.filter(v(-9.81), now).y);
+    }
+
+    #[test]
+    fn parses_chains() {
+        for chain in &["median:5+ema:1000", "mean:4", "kalman", "kalman:0.5:2", "none", ""] {
+            assert!(chain.parse::<FilterChain>().is_ok(), "couldn't parse '{}'", chain);
+        }
+        for chain in &["median", "median:0", "ema:fast", "kalman:1", "lowpass:3", "ema:100+"] {
+            assert!(chain.parse::<FilterChain>().is_err(), "parsed '{}'", chain);
+        }
+    }
+}
@@ -3,6 +3,7 @@
 //! A module for representing an accelerometer based on data from the filesystem.
 
 use super::AccelerationVector as AVector;
+use super::MountMatrix;
 
 use std::collections::HashMap;
 use std::path::{Path,PathBuf};
@@ -10,13 +11,19 @@ use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
 use std::io::SeekFrom;
+use std::num::{ParseIntError,ParseFloatError};
 use std::ops::Deref;
+use std::str::FromStr;
+use std::fmt::{Display, Formatter};
+use std::fmt::Result as FmtResult;
 
 use regex::Regex;
 use glob::*;
 
 type IoResult<T> = Result<T, IoError>;
+type FsAccelResult<T> = Result<T, FsAccelError>;
 
 const DEFAULT_FSACCEL_ENDIANNESS: Endian = Endian::Little;
 const DEFAULT_FSACCEL_SIGNED: Signed = Signed::Unsigned;
@@ -34,6 +41,96 @@ pub const DEFAULT_DESCR_SUFFIX: &str = "_type";
 pub const DEFAULT_FIX_SIGN:     &str = "false";
 
 
+/// Represents an error setting up or reading a filesystem accelerometer
+#[derive(Debug)]
+pub enum FsAccelError {
+    /// Some other I/O error
+    Io(IoError),
+    /// Couldn't open or read a file
+    File(PathBuf, IoError),
+    /// A scan type (`_type` file) couldn't be understood
+    BadScanType(String, &'static str),
+    /// A channel value didn't match its scan type
+    BadValue(String, ParseIntError),
+    /// A channel value was too big for its scan type's bits
+    ValueTooBig(String, u8),
+    /// The scale file didn't hold a number (and there was no `defscale`)
+    BadScaleFile(PathBuf, ParseFloatError),
+    /// An option had a value that couldn't be used
+    BadOption(&'static str, String, String),
+}
+
+impl Display for FsAccelError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            FsAccelError::Io(e) => write!(fmt, "{}", e),
+            FsAccelError::File(p, e)    => {
+                write!(fmt, "couldn't read {} ({})", p.to_string_lossy(), e)
+            },
+            FsAccelError::BadScanType(t, why)   => {
+                write!(fmt, "bad scan type '{}' ({})", t, why)
+            },
+            FsAccelError::BadValue(v, e)    => {
+                write!(fmt, "bad channel value '{}' ({})", v, e)
+            },
+            FsAccelError::ValueTooBig(v, bits)  => {
+                write!(fmt, "bad channel value '{}' (doesn't fit in {} bits)", v, bits)
+            },
+            FsAccelError::BadScaleFile(p, e)    => {
+                write!(fmt, "couldn't parse scale file {} ({}); try setting defscale",
+                       p.to_string_lossy(), e)
+            },
+            FsAccelError::BadOption(o, v, why)  => {
+                write!(fmt, "bad value '{}' for option {} ({})", v, o, why)
+            },
+        }
+    }
+}
+
+impl std::error::Error for FsAccelError {
+    fn description(&self) -> &str {
+        "filesystem accelerometer error"
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FsAccelError::Io(e) => Some(e),
+            FsAccelError::File(_, e)    => Some(e),
+            FsAccelError::BadScanType(_, _) => None,
+            FsAccelError::BadValue(_, e)    => Some(e),
+            FsAccelError::ValueTooBig(_, _) => None,
+            FsAccelError::BadScaleFile(_, e)    => Some(e),
+            FsAccelError::BadOption(_, _, _)    => None,
+        }
+    }
+}
+
+impl From<IoError> for FsAccelError {
+    fn from(e: IoError) -> FsAccelError {
+        FsAccelError::Io(e)
+    }
+}
+
+impl From<FsAccelError> for IoError {
+    fn from(e: FsAccelError) -> IoError {
+        match e {
+            FsAccelError::Io(e) => e,
+            FsAccelError::File(_, ref ie)   => IoError::new(ie.kind(), e.to_string()),
+            _   => IoError::new(IoErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+/// Read a whole file, saying which one if it fails.
+fn read_file(path: &Path) -> FsAccelResult<String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| FsAccelError::File(path.to_owned(), e))?;
+    Ok(contents)
+}
+
+
 #[derive(Debug)]
 pub enum Endian {
     Big,
@@ -80,7 +177,7 @@ pub struct ScanType {
     // storagebytes: u8,
     repeat: u8,
     rshift: u8,
-    conversion: (&'static str, fn(&str,u8) -> i64),
+    conversion: (&'static str, fn(&str,u8) -> FsAccelResult<i64>),
     fix_sign: bool,
 }
 
@@ -103,26 +200,29 @@ impl std::fmt::Debug for ScanType {
 
 impl ScanType {
     /// Create a ScanType from the contents of a file
-    pub fn from_file<P: AsRef<Path>>(path: P, fix_sign: bool) -> IoResult<ScanType> {
+    pub fn from_file<P: AsRef<Path>>(path: P, fix_sign: bool) -> FsAccelResult<ScanType> {
         debug!("Creating scan type (fixing sign: {}) from file {}", fix_sign, path.as_ref().to_string_lossy());
-        let mut contents = String::new();
-        {
-            let mut file = {File::open(path)?};
-            file.read_to_string(&mut contents)?;
-        }
+        let contents = read_file(path.as_ref())?;
         ScanType::from_str(contents.as_str(), fix_sign)
     }
 
     /// Create a ScanType from the contents of a string
-    pub fn from_str<'s, S: Into<&'s str>>(contents: S, fix_sign: bool) -> IoResult<ScanType> {
+    pub fn from_str<'s, S: Into<&'s str>>(contents: S, fix_sign: bool) -> FsAccelResult<ScanType> {
         lazy_static!{
             static ref SCANTYPE_RE: Regex = Regex::new(SCANTYPE_RE_STR).unwrap();
         }
         let conts = contents.into();
         debug!("Creating scan type (fixing sign: {}) from string {}", fix_sign, &conts);
-        let caps = SCANTYPE_RE.captures(conts).unwrap();
-        let bits = caps["bit"].parse::<u8>().unwrap();
-        let sbits = caps["sto"].parse::<u8>().unwrap();
+        let bad = |why| FsAccelError::BadScanType(conts.trim().to_owned(), why);
+        let caps = SCANTYPE_RE.captures(conts).ok_or_else(|| bad("not a scan type"))?;
+        let bits = caps["bit"].parse::<u8>().map_err(|_| bad("too many bits"))?;
+        let sbits = caps["sto"].parse::<u8>().map_err(|_| bad("too many storage bits"))?;
+        if 0 == bits || bits > 64 {
+            return Err(bad("bits must be between 1 and 64"));
+        }
+        if bits > sbits || sbits > 64 {
+            return Err(bad("storage bits must be between bits and 64"));
+        }
         let sign = match caps.name("sign") {
             Some(s)   => Signed::from_str(s.as_str()),
             _           => DEFAULT_FSACCEL_SIGNED,
@@ -178,62 +278,108 @@ impl ScanType {
 
     /// Convert the given number to a signed int.
     ///
-    /// # Examples
-    /// ```
-    /// use fsaccel::ScanType;
-    /// assert_eq!(ScanType::from_str("s8/32>>0").convert(255), -1)
-    /// assert_eq!(ScanType::from_str("u6/16>>0").convert(17), -15)
-    /// ```
-    pub fn convert(&self, num: &str) -> i64 {
+    /// Unsigned channels are taken to be offset by half their range, so
+    /// e.g. 17 as a `u6` is -15.
+    pub fn convert(&self, num: &str) -> FsAccelResult<i64> {
         (self.conversion.1)(num, self.bits)
     }
+
+    /// The number of bytes one element takes up in a buffered scan.
+    pub fn storagebytes(&self) -> usize {
+        (self.storagebits as usize + 7) / 8
+    }
+
+    /// The number of bytes this channel takes up in a buffered scan,
+    /// counting repeats.
+    pub fn scan_bytes(&self) -> usize {
+        self.storagebytes() * ::std::cmp::max(1, self.repeat as usize)
+    }
+
+    /// Decode one element of binary buffer data (as read from
+    /// `/dev/iio:deviceN`) using the endianness, storage bits, shift and
+    /// sign of this ScanType.
+    ///
+    /// `bytes` must be at least `storagebytes()` long.
+    pub fn decode(&self, bytes: &[u8]) -> i64 {
+        let len = self.storagebytes();
+        let mut raw = 0u64;
+        match self.endianness {
+            Endian::Big => for b in bytes[..len].iter() {
+                raw = (raw << 8) | *b as u64;
+            },
+            Endian::Little  => for b in bytes[..len].iter().rev() {
+                raw = (raw << 8) | *b as u64;
+            },
+        }
+        raw = raw.checked_shr(self.rshift as u32).unwrap_or(0);
+        if self.bits >= 64 {
+            return raw as i64;
+        }
+        raw &= (1u64 << self.bits) - 1;
+        match self.sign {
+            Signed::Signed if self.bits > 0 && 0 != raw & 1 << (self.bits - 1) => {
+                (raw | !((1u64 << self.bits) - 1)) as i64
+            },
+            _   => raw as i64,
+        }
+    }
+}
+
+/// Parse a channel value as a `T`.
+fn parse<T: FromStr<Err = ParseIntError>>(n: &str) -> FsAccelResult<T> {
+    n.parse::<T>().map_err(|e| FsAccelError::BadValue(n.to_owned(), e))
 }
 
-fn _parse8(n: &str, _s: u8) -> i64 {
-    n.parse::<i8>().expect(&format!("Number parsing failed! ({})", n)) as i64
+fn _parse8(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<i8>(n).map(|n| n as i64)
 }
-fn _parse16(n: &str, _s: u8) -> i64 {
-    n.parse::<i16>().expect(&format!("Number parsing failed! ({})", n)) as i64
+fn _parse16(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<i16>(n).map(|n| n as i64)
 }
-fn _parse32(n: &str, _s: u8) -> i64 {
-    n.parse::<i32>().expect(&format!("Number parsing failed! ({})", n)) as i64
+fn _parse32(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<i32>(n).map(|n| n as i64)
 }
-fn _parse64(n: &str, _s: u8) -> i64 {
-    n.parse::<i64>().expect(&format!("Number parsing failed! ({})", n))
+fn _parse64(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<i64>(n)
 }
-fn _parse128(n: &str, _s: u8) -> i64 {
-    (n.parse::<i128>().expect(&format!("Number parsing failed! ({})", n)) >>1) as i64
+fn _parse128(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<i128>(n).map(|n| (n >> 1) as i64)
 }
-fn _convus8(n: &str, _s: u8) -> i64 {
-    n.parse::<u8>().expect(&format!("Number parsing failed! ({})", n)) as i8 as i64
+fn _convus8(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<u8>(n).map(|n| n as i8 as i64)
 }
-fn _convus16(n: &str, _s: u8) -> i64 {
-    n.parse::<u16>().expect(&format!("Number parsing failed! ({})", n)) as i16 as i64
+fn _convus16(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<u16>(n).map(|n| n as i16 as i64)
 }
-fn _convus32(n: &str, _s: u8) -> i64 {
-    n.parse::<u32>().expect(&format!("Number parsing failed! ({})", n)) as i32 as i64
+fn _convus32(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<u32>(n).map(|n| n as i32 as i64)
 }
-fn _convus64(n: &str, _s: u8) -> i64 {
-    n.parse::<u64>().expect(&format!("Number parsing failed! ({})", n)) as i64
+fn _convus64(n: &str, _s: u8) -> FsAccelResult<i64> {
+    parse::<u64>(n).map(|n| n as i64)
 }
-fn _conv_unsigned_signed(num: &str, s: u8) -> i64 {
-    let n = num.parse::<u64>().expect(&format!("Number parsing failed! (i{} -> i64 '{}')", s, num));
-    // Assuming 2's complement.
-    if 0 == n & 1<<(s-1) {
-        n as i64
-    } else {
-        (n ^ ((1<<(64-s))-1)) as i64
+/// Parse an unsigned value of `s` bits, along with `2^s`.
+///
+/// Done in `i128`s, so nothing overflows for any `s` up to 64.
+fn parse_bits(num: &str, s: u8) -> FsAccelResult<(i128, i128)> {
+    let n = parse::<u64>(num)? as i128;
+    match 1i128.checked_shl(s as u32) {
+        Some(span) if 0 < s && s <= 64 && n < span  => Ok((n, span)),
+        _   => Err(FsAccelError::ValueTooBig(num.to_owned(), s)),
     }
 }
-fn _conv_unsigned(num: &str, s: u8) -> i64 {
-    let n = num.parse::<u64>().expect(&format!("Number parsing failed! (u{} -> i64 '{}') ", s, num));
-    if n >= 1<<(s-1) {
-        (n - 1<<(s-1)) as i64
+fn _conv_unsigned_signed(num: &str, s: u8) -> FsAccelResult<i64> {
+    let (n, span) = parse_bits(num, s)?;
+    // Assuming 2's complement.
+    if n < span / 2 {
+        Ok(n as i64)
     } else {
-        // Assuming 2's complement.
-        0i64 - (1u64<<(s-1) as u64 - n) as i64
+        Ok((n - span) as i64)
     }
 }
+fn _conv_unsigned(num: &str, s: u8) -> FsAccelResult<i64> {
+    let (n, span) = parse_bits(num, s)?;
+    Ok((n - span / 2) as i64)
+}
 
 #[derive(Debug)]
 pub struct Channel {
@@ -245,22 +391,21 @@ pub struct Channel {
 impl Channel {
     /// Create a new Channel at the given path, loading the ScanType from 
     /// the given file.
-    pub fn new_from_file<P: AsRef<Path>,Q: AsRef<Path>>(id: &str, data_file: P, descr_file: Q, fix_sign: bool) -> IoResult<Channel> {
+    pub fn new_from_file<P: AsRef<Path>,Q: AsRef<Path>>(id: &str, data_file: P, descr_file: Q, fix_sign: bool) -> FsAccelResult<Channel> {
         debug!("Creating new Channel {} from files:\ndata_file: {}\ndescr_file: {}\nfix_sign: {}", id, data_file.as_ref().to_string_lossy(), descr_file.as_ref().to_string_lossy(), fix_sign);
         Ok(Channel {
             id: id.to_owned(),
             scan: ScanType::from_file(descr_file.as_ref(), fix_sign)?,
-            reader: BufReader::new(File::open(data_file)?),
+            reader: BufReader::new(File::open(data_file.as_ref())
+                .map_err(|e| FsAccelError::File(data_file.as_ref().to_owned(), e))?),
         })
     }
 
     /// Read the current value of the channel
-    pub fn read(&mut self) -> i64 {
+    pub fn read(&mut self) -> FsAccelResult<i64> {
         let mut astr = String::new();
-        { self.reader.seek(SeekFrom::Start(0)).expect("Seek failure!"); }
-        {
-            self.reader.read_to_string(&mut astr).unwrap();
-        }
+        self.reader.seek(SeekFrom::Start(0))?;
+        self.reader.read_to_string(&mut astr)?;
         self.scan.convert(astr.trim())
     }
 }
@@ -292,7 +437,7 @@ macro_rules! unmapvars {
         debug!("{} = {}", stringify!($var), &$var);
     };
 }
-pub fn build_channels(chans: (&str, &str, &str), opts: &HashMap<String, String>) -> IoResult<(Channel, Channel, Channel)> {
+pub fn build_channels(chans: (&str, &str, &str), opts: &HashMap<String, String>) -> FsAccelResult<(Channel, Channel, Channel)> {
     debug!("Building channels {:?}", &chans);
     unmapvars![opts:
         data_prefix, DEFAULT_DATA_PREFIX;
@@ -303,7 +448,8 @@ pub fn build_channels(chans: (&str, &str, &str), opts: &HashMap<String, String>)
         descr_suffix, DEFAULT_DESCR_SUFFIX;
         fix_sign, DEFAULT_FIX_SIGN;
     ];
-    let fs: bool = fix_sign.parse().expect("fix_sign must be 'true' or 'false'.");
+    let fs: bool = fix_sign.parse().map_err(|_| FsAccelError::BadOption(
+            "fix_sign", fix_sign.to_owned(), "must be 'true' or 'false'".to_owned()))?;
     let path = PathBuf::from(opts.get("path").unwrap_or(&DEFAULT_FSACCEL_PATH.to_owned()));
     debug!("fs = {}", fs);
     macro_rules! newchan {
@@ -326,11 +472,12 @@ pub fn build_channels(chans: (&str, &str, &str), opts: &HashMap<String, String>)
 pub struct FsAccelerometer {
     scale: f64,
     channels: (Channel, Channel, Channel),
+    mount: MountMatrix,
 }
 
 impl FsAccelerometer {
     /// Creates a new FsAccelerometer with the specified options.
-    pub fn from_opts(opts: &mut HashMap<String, String>) -> IoResult<FsAccelerometer> {
+    pub fn from_opts(opts: &mut HashMap<String, String>) -> FsAccelResult<FsAccelerometer> {
         debug!("Creating FsAccelerometer with the following options: {:?}", opts);
         let haskey = opts.contains_key("path");
         let path = match haskey {
@@ -343,43 +490,46 @@ impl FsAccelerometer {
         };
         debug!("FsAccel path is {}", &path.to_string_lossy());
         let scale: f64 = match opts.get("scale") {
-            //TODO: Log before aborting
-            Some(s) => s.parse::<f64>().expect("Scale must be a number"),
+            Some(s) => parse_scale("scale", s)?,
             None    => {
-                let mut scales = String::new();
                 let def_scalef = DEFAULT_SCALE_FILE.to_owned();
                 let scalef = path.join(opts.get("scalefile").unwrap_or(&def_scalef));
-                { f2s!(&scalef, scales); }
-                scales.trim().parse::<f64>().unwrap_or_else(|e| {
-                    opts.get("defscale").unwrap_or_else(||
-                            panic!(format!("Couldn't parse scale file {}: {}", &scalef.to_string_lossy(), e)))
-                        .parse::<f64>().expect("default scale must be a number")
-                })
+                let scale = read_file(&scalef).and_then(|s| s.trim().parse::<f64>()
+                    .map_err(|e| FsAccelError::BadScaleFile(scalef.clone(), e)));
+                match (scale, opts.get("defscale")) {
+                    (Ok(s), _)  => s,
+                    (Err(e), Some(d))   => {
+                        warn!("{}; using defscale", e);
+                        parse_scale("defscale", d)?
+                    },
+                    (Err(e), None)  => return Err(e),
+                }
             }
         };
         debug!("Scale is {}", &scale);
         Ok(FsAccelerometer {
             scale: scale,
             channels: build_channels(("x","y","z"), opts)?,
+            mount: MountMatrix::from_opts(opts, Some(&path))?,
         })
     }
 }
 
 impl super::Accelerometer for FsAccelerometer {
 
-    fn read(&mut self) -> AVector<f64> {
-        AVector::<f64> {
-            x: { self.channels.0.read() as f64 * self.scale },
-            y: { self.channels.1.read() as f64 * self.scale },
-            z: { self.channels.2.read() as f64 * self.scale },
-        }
+    fn read(&mut self) -> IoResult<AVector<f64>> {
+        Ok(self.mount.rotate(AVector::<f64> {
+            x: { self.channels.0.read()? as f64 * self.scale },
+            y: { self.channels.1.read()? as f64 * self.scale },
+            z: { self.channels.2.read()? as f64 * self.scale },
+        }))
     }
-    fn read_raw(&mut self) -> AVector<i32> {
-        AVector::<i32> {
-            x: { self.channels.0.read() as i32 },
-            y: { self.channels.1.read() as i32 },
-            z: { self.channels.2.read() as i32 },
-        }
+    fn read_raw(&mut self) -> IoResult<AVector<i32>> {
+        Ok(self.mount.rotate_raw(AVector::<i32> {
+            x: { self.channels.0.read()? as i32 },
+            y: { self.channels.1.read()? as i32 },
+            z: { self.channels.2.read()? as i32 },
+        }))
     }
     fn get_scale(&self) -> f64 {
         return self.scale;
@@ -387,8 +537,16 @@ impl super::Accelerometer for FsAccelerometer {
 }
 
 
-fn guess_path(base_path: &str) -> IoResult<PathBuf> {
-    for entry in glob(base_path).unwrap() {
+/// Parse the value of a scale option.
+fn parse_scale(name: &'static str, value: &str) -> FsAccelResult<f64> {
+    value.parse::<f64>()
+        .map_err(|e| FsAccelError::BadOption(name, value.to_owned(), e.to_string()))
+}
+
+/// Find the first IIO device under `base_path` that calls itself an
+/// `accel_3d`.
+pub fn guess_path(base_path: &str) -> IoResult<PathBuf> {
+    for entry in glob(base_path).map_err(|e| IoError::new(IoErrorKind::InvalidInput, e.msg))? {
         if let Ok(p) = entry {
             if p.deref().join("name").deref().is_file() {
                 let mut name = String::new();
@@ -403,3 +561,145 @@ fn guess_path(base_path: &str) -> IoResult<PathBuf> {
 }
 
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use accel::Accelerometer;
+    use std::fs;
+    use testutil::TempDir;
+
+    /// Make a fake sysfs device directory, with x, y and z reading 10, -20
+    /// and 30.
+    fn fixture(scantype: &str, scale: Option<&str>) -> TempDir {
+        let dir = TempDir::new("fsaccel");
+        fs::create_dir_all(dir.join("scan_elements")).unwrap();
+        for &(axis, value) in &[("x", "10"), ("y", "-20"), ("z", "30")] {
+            fs::write(dir.join(format!("in_accel_{}_raw", axis)), format!("{}\n", value)).unwrap();
+            fs::write(dir.join(format!("scan_elements/in_accel_{}_type", axis)),
+                      format!("{}\n", scantype)).unwrap();
+        }
+        if let Some(scale) = scale {
+            fs::write(dir.join(DEFAULT_SCALE_FILE), format!("{}\n", scale)).unwrap();
+        }
+        dir
+    }
+
+    fn open(dir: &TempDir, opts: &[(&str, &str)]) -> FsAccelResult<FsAccelerometer> {
+        let mut opts: HashMap<String, String> = opts.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned())).collect();
+        opts.insert("path".to_owned(), dir.path().to_string_lossy().into_owned());
+        FsAccelerometer::from_opts(&mut opts)
+    }
+
+    fn too_big(r: FsAccelResult<i64>) -> bool {
+        matches!(r, Err(FsAccelError::ValueTooBig(_, _)))
+    }
+
+    #[test]
+    fn parses_signed() {
+        assert_eq!(-5, _parse8("-5", 8).unwrap());
+        assert_eq!(-300, _parse16("-300", 12).unwrap());
+        assert_eq!(i64::MIN, _parse64("-9223372036854775808", 64).unwrap());
+        assert!(matches!(_parse8("200", 8), Err(FsAccelError::BadValue(_, _))));
+        assert!(matches!(_parse32("", 32), Err(FsAccelError::BadValue(_, _))));
+        assert_eq!(-1, _convus8("255", 8).unwrap());
+        assert_eq!(-1, _convus16("65535", 16).unwrap());
+        assert_eq!(-1, _convus32("4294967295", 32).unwrap());
+        assert_eq!(-1, _convus64("18446744073709551615", 64).unwrap());
+        assert!(matches!(_convus8("-1", 8), Err(FsAccelError::BadValue(_, _))));
+    }
+
+    #[test]
+    fn converts_odd_widths() {
+        assert_eq!(2047, _conv_unsigned_signed("2047", 12).unwrap());
+        assert_eq!(-2048, _conv_unsigned_signed("2048", 12).unwrap());
+        assert_eq!(-1, _conv_unsigned_signed("4095", 12).unwrap());
+        assert_eq!(-1, _conv_unsigned_signed("1", 1).unwrap());
+        assert_eq!(-1, _conv_unsigned_signed("18446744073709551615", 64).unwrap());
+        assert!(too_big(_conv_unsigned_signed("4096", 12)));
+        assert!(too_big(_conv_unsigned_signed("0", 0)));
+
+        assert_eq!(-32, _conv_unsigned("0", 6).unwrap());
+        assert_eq!(-27, _conv_unsigned("5", 6).unwrap());
+        assert_eq!(-15, _conv_unsigned("17", 6).unwrap());
+        assert_eq!(0, _conv_unsigned("32", 6).unwrap());
+        assert_eq!(31, _conv_unsigned("63", 6).unwrap());
+        assert_eq!(i64::MIN, _conv_unsigned("0", 64).unwrap());
+        assert_eq!(i64::MAX, _conv_unsigned("18446744073709551615", 64).unwrap());
+        assert!(too_big(_conv_unsigned("64", 6)));
+        assert!(too_big(_conv_unsigned("0", 0)));
+        assert!(matches!(_conv_unsigned("-1", 6), Err(FsAccelError::BadValue(_, _))));
+    }
+
+    #[test]
+    fn scan_type_picks_conversion() {
+        let convert = |t: &str, fix_sign, n: &str| ScanType::from_str(t, fix_sign).unwrap().convert(n);
+        assert_eq!(-1, convert("le:s8/32>>0", true, "255").unwrap());
+        assert_eq!(-1, convert("le:s12/16>>4", true, "4095").unwrap());
+        assert_eq!(-15, convert("le:u6/16>>0", false, "17").unwrap());
+        assert_eq!(-7, convert("le:s12/16>>4", false, "-7").unwrap());
+        assert!(convert("le:s12/16>>4", false, "x").is_err());
+    }
+
+    #[test]
+    fn decodes_buffer_data() {
+        let decode = |t: &str, bytes: &[u8]| ScanType::from_str(t, false).unwrap().decode(bytes);
+        assert_eq!(-1, decode("le:s12/16>>4", &[0xf0, 0xff]));
+        assert_eq!(2047, decode("le:s12/16>>4", &[0xf0, 0x7f]));
+        assert_eq!(17, decode("be:u8/16>>0", &[0x00, 0x11]));
+        assert_eq!(-2, decode("be:s16/16>>0", &[0xff, 0xfe]));
+        assert_eq!(-1, decode("le:s32/32>>0", &[0xff, 0xff, 0xff, 0xff]));
+        // Anything past the element is ignored.
+        assert_eq!(1, decode("le:u8/8>>0", &[0x01, 0xff]));
+    }
+
+    #[test]
+    fn bad_scan_types() {
+        for bad in &["", "nonsense", "le:s0/16>>0", "le:s65/64>>0", "le:s16/8>>0",
+                     "le:s300/16>>0", "le:s12/400>>0"] {
+            assert!(matches!(ScanType::from_str(*bad, false), Err(FsAccelError::BadScanType(_, _))),
+                    "accepted '{}'", bad);
+        }
+        let dir = fixture("le:s12/8>>0", Some("0.5"));
+        assert!(matches!(open(&dir, &[]), Err(FsAccelError::BadScanType(_, _))));
+    }
+
+    #[test]
+    fn reads_scaled() {
+        let dir = fixture("le:s12/16>>4", Some("0.5"));
+        let mut accel = open(&dir, &[]).unwrap();
+        assert_eq!(0.5, accel.get_scale());
+        let v = accel.read().unwrap();
+        assert_eq!((5.0, -10.0, 15.0), (v.x, v.y, v.z));
+
+        let mut accel = open(&dir, &[("scale", "2")]).unwrap();
+        assert_eq!(2.0, accel.get_scale());
+        let v = accel.read_raw().unwrap();
+        assert_eq!((10, -20, 30), (v.x, v.y, v.z));
+    }
+
+    #[test]
+    fn bad_scales() {
+        let dir = fixture("le:s12/16>>4", Some("lots"));
+        assert!(matches!(open(&dir, &[("scale", "big")]),
+                         Err(FsAccelError::BadOption("scale", _, _))));
+        assert!(matches!(open(&dir, &[]), Err(FsAccelError::BadScaleFile(_, _))));
+        assert!(matches!(open(&dir, &[("defscale", "small")]),
+                         Err(FsAccelError::BadOption("defscale", _, _))));
+        assert_eq!(0.25, open(&dir, &[("defscale", "0.25")]).unwrap().get_scale());
+
+        let dir = fixture("le:s12/16>>4", None);
+        assert!(matches!(open(&dir, &[]), Err(FsAccelError::File(_, _))));
+        assert_eq!(0.25, open(&dir, &[("defscale", "0.25")]).unwrap().get_scale());
+    }
+
+    #[test]
+    fn bad_fix_sign() {
+        let dir = fixture("le:s12/16>>4", Some("1"));
+        assert!(matches!(open(&dir, &[("fix_sign", "maybe")]),
+                         Err(FsAccelError::BadOption("fix_sign", _, _))));
+        assert!(open(&dir, &[("fix_sign", "true")]).is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+//! iioaccel.rs
+//!
+//! A module for representing an accelerometer read through the IIO
+//! buffered character device (`/dev/iio:deviceN`).
+//!
+//! Instead of re-reading the `_raw` sysfs files every poll, this enables
+//! the accelerometer's scan elements and a trigger, turns the buffer on,
+//! and decodes the binary scans the kernel pushes to the character device
+//! using the layout parsed from `scan_elements/*_type`.
+
+use super::AccelerationVector as AVector;
+use super::MountMatrix;
+use super::fsaccel::{ScanType, guess_path, DEFAULT_FSACCEL_PATH, DEFAULT_SCALE_FILE};
+
+use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+use std::fs::{File,OpenOptions};
+use std::io::prelude::*;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use glob::glob;
+use libc;
+
+type IoResult<T> = Result<T, IoError>;
+
+pub const DEFAULT_IIOACCEL_DEVDIR: &str = "/dev";
+pub const DEFAULT_BUFFER_LENGTH:   &str = "16";
+pub const DEFAULT_CHANNEL_PREFIX:  &str = "in_accel_";
+pub const DEFAULT_FIRST_TIMEOUT:   &str = "1000";
+
+/// Where a single channel lives within a scan.
+#[derive(Debug)]
+struct ScanSlot {
+    /// Offset of the channel from the start of the scan, in bytes
+    offset: usize,
+    scan: ScanType,
+}
+
+/// The layout of one scan, as built from the enabled scan elements.
+#[derive(Debug)]
+struct ScanLayout {
+    /// Total size of one scan, in bytes (including padding)
+    size: usize,
+    channels: (ScanSlot, ScanSlot, ScanSlot),
+}
+
+impl ScanLayout {
+    /// Build the layout of a scan from every enabled scan element in
+    /// `path`, picking out the three `chans`.
+    ///
+    /// Elements are laid out in index order, each aligned to its own
+    /// length (storage size times repeat), and the whole scan is padded
+    /// to the largest alignment, just like the kernel's
+    /// `iio_compute_scan_bytes` does.
+    fn from_dir(path: &Path, prefix: &str, chans: (&str, &str, &str)) -> IoResult<ScanLayout> {
+        let mut elements: Vec<(u32, String, ScanType)> = Vec::new();
+        let pattern = path.join("scan_elements/*_en");
+        for entry in glob(&pattern.to_string_lossy()).map_err(|e|
+                IoError::new(IoErrorKind::InvalidInput, e.msg))? {
+            let enfile = match entry {
+                Ok(p)   => p,
+                Err(e)  => { warn!("Skipping scan element: {}", e); continue },
+            };
+            if "1" != read_trimmed(&enfile)? { continue }
+            let fname = enfile.file_name().unwrap().to_string_lossy().into_owned();
+            let name = fname[..fname.len() - "_en".len()].to_owned();
+            let index = read_trimmed(path.join(format!("scan_elements/{}_index", name)))?
+                .parse::<u32>()
+                .map_err(|e| IoError::new(IoErrorKind::InvalidData,
+                                          format!("bad index for {}: {}", name, e)))?;
+            let scan = ScanType::from_file(
+                path.join(format!("scan_elements/{}_type", name)), false)?;
+            elements.push((index, name, scan));
+        }
+        elements.sort_by_key(|e| e.0);
+        debug!("Enabled scan elements: {:?}", elements);
+
+        let mut offset = 0usize;
+        let mut align = 1usize;
+        let (mut x, mut y, mut z) = (None, None, None);
+        for (_, name, scan) in elements {
+            let len = scan.scan_bytes();
+            offset = offset.div_ceil(len) * len;
+            align = ::std::cmp::max(align, len);
+            let slot = Some(ScanSlot { offset, scan });
+            if name == format!("{}{}", prefix, chans.0) { x = slot; }
+            else if name == format!("{}{}", prefix, chans.1) { y = slot; }
+            else if name == format!("{}{}", prefix, chans.2) { z = slot; }
+            offset += len;
+        }
+        offset = offset.div_ceil(align) * align;
+        match (x, y, z) {
+            (Some(x), Some(y), Some(z)) => Ok(ScanLayout {
+                size: offset,
+                channels: (x, y, z),
+            }),
+            _   => Err(IoError::new(IoErrorKind::NotFound, format!(
+                        "not all of {:?} are enabled in {}", chans, path.to_string_lossy()))),
+        }
+    }
+
+    /// Decode the three channels out of a single scan.
+    fn decode(&self, scan: &[u8]) -> AVector<i64> {
+        AVector::<i64> {
+            x: self.channels.0.scan.decode(&scan[self.channels.0.offset..]),
+            y: self.channels.1.scan.decode(&scan[self.channels.1.offset..]),
+            z: self.channels.2.scan.decode(&scan[self.channels.2.offset..]),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct IioAccelerometer {
+    /// The sysfs directory of the device
+    path: PathBuf,
+    /// The buffered character device
+    dev: File,
+    scale: f64,
+    mount: MountMatrix,
+    layout: ScanLayout,
+    /// Scratch space for reading scans
+    buf: Vec<u8>,
+    /// The most recently decoded scan
+    last: Option<AVector<i64>>,
+    /// How long to wait for the very first scan (in ms)
+    first_timeout: i32,
+}
+
+impl IioAccelerometer {
+    /// Creates a new IioAccelerometer with the specified options.
+    pub fn from_opts(opts: &mut HashMap<String, String>) -> IoResult<IioAccelerometer> {
+        debug!("Creating IioAccelerometer with the following options: {:?}", opts);
+        let path = match opts.get("path") {
+            Some(p) => PathBuf::from(p),
+            None    => {
+                let path = guess_path(DEFAULT_FSACCEL_PATH)?;
+                opts.insert("path".into(), path.to_string_lossy().into_owned());
+                path
+            },
+        };
+        debug!("IioAccel path is {}", &path.to_string_lossy());
+
+        let scale: f64 = match opts.get("scale") {
+            Some(s) => s.parse::<f64>().map_err(|e| IoError::new(
+                    IoErrorKind::InvalidInput, format!("bad scale '{}': {}", s, e)))?,
+            None    => {
+                let scalef = path.join(opts.get("scalefile").map(|s| s.as_str())
+                                       .unwrap_or(DEFAULT_SCALE_FILE));
+                read_trimmed(&scalef)?.parse::<f64>().map_err(|e| IoError::new(
+                        IoErrorKind::InvalidData,
+                        format!("couldn't parse scale file {}: {}", scalef.to_string_lossy(), e)))?
+            },
+        };
+        debug!("Scale is {}", &scale);
+        let mount = MountMatrix::from_opts(opts, Some(&path))?;
+
+        let prefix = opts.get("channel_prefix").map(|s| s.as_str())
+            .unwrap_or(DEFAULT_CHANNEL_PREFIX).to_owned();
+        let devnode = match opts.get("devnode") {
+            Some(d) => PathBuf::from(d),
+            None    => Path::new(DEFAULT_IIOACCEL_DEVDIR).join(
+                path.file_name().ok_or_else(|| IoError::new(IoErrorKind::InvalidInput,
+                    format!("can't get device name from {}", path.to_string_lossy())))?),
+        };
+        let first_timeout = opts.get("first_timeout").map(|s| s.as_str())
+            .unwrap_or(DEFAULT_FIRST_TIMEOUT).parse::<i32>().map_err(|e| IoError::new(
+                IoErrorKind::InvalidInput, format!("bad first_timeout: {}", e)))?;
+
+        // The buffer has to be off while we change what's in it.
+        write_sysfs(path.join("buffer/enable"), "0")?;
+        for chan in ["x", "y", "z"].iter() {
+            write_sysfs(path.join(format!("scan_elements/{}{}_en", prefix, chan)), "1")?;
+        }
+        let layout = ScanLayout::from_dir(&path, &prefix, ("x", "y", "z"))?;
+        debug!("Scan layout: {:?}", layout);
+
+        if let Some(freq) = opts.get("sampling_frequency") {
+            let freqf = path.join(format!("{}sampling_frequency", prefix));
+            write_sysfs(if freqf.exists() { freqf } else { path.join("sampling_frequency") },
+                        freq)?;
+        }
+        match opts.get("trigger").cloned().map_or_else(|| guess_trigger(&path), |t| Ok(Some(t)))? {
+            Some(ref t) if !t.is_empty() => {
+                debug!("Using trigger {}", t);
+                write_sysfs(path.join("trigger/current_trigger"), t)?;
+            },
+            // Some devices (e.g. with hardware FIFOs) don't need one.
+            _   => debug!("No trigger for {}", path.to_string_lossy()),
+        }
+        write_sysfs(path.join("buffer/length"),
+                    opts.get("buffer_length").map(|s| s.as_str()).unwrap_or(DEFAULT_BUFFER_LENGTH))?;
+        write_sysfs(path.join("buffer/enable"), "1")?;
+
+        let dev = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&devnode)?;
+        debug!("Reading scans of {} bytes from {}", layout.size, devnode.to_string_lossy());
+        Ok(IioAccelerometer {
+            buf: vec![0u8; layout.size * 16],
+            path,
+            dev,
+            scale,
+            mount,
+            layout,
+            last: None,
+            first_timeout,
+        })
+    }
+
+    /// Drain every scan currently in the buffer, keeping the newest.
+    ///
+    /// If we haven't seen a single scan yet, wait (up to `first_timeout`)
+    /// for one to show up; if none does, it's a `WouldBlock` error.
+    fn update(&mut self) -> IoResult<AVector<i64>> {
+        if self.last.is_none() {
+            let mut pfd = libc::pollfd {
+                fd: self.dev.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, self.first_timeout) } <= 0 {
+                warn!("No scan from {} within {}ms", self.path.to_string_lossy(), self.first_timeout);
+            }
+        }
+        loop {
+            match self.dev.read(&mut self.buf) {
+                Ok(0)   => break,
+                Ok(n)   => {
+                    if n < self.layout.size { break }
+                    let start = (n / self.layout.size - 1) * self.layout.size;
+                    self.last = Some(self.layout.decode(&self.buf[start..n]));
+                    if n < self.buf.len() { break }
+                },
+                Err(ref e) if IoErrorKind::WouldBlock == e.kind() => break,
+                Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+                Err(e)  => return Err(IoError::new(e.kind(), format!(
+                            "couldn't read scan from {}: {}", self.path.to_string_lossy(), e))),
+            }
+        }
+        self.last.ok_or_else(|| IoError::new(IoErrorKind::WouldBlock, format!(
+                    "no scan from {} yet", self.path.to_string_lossy())))
+    }
+}
+
+impl Drop for IioAccelerometer {
+    fn drop(&mut self) {
+        if let Err(e) = write_sysfs(self.path.join("buffer/enable"), "0") {
+            warn!("Couldn't disable buffer for {}: {}", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+impl super::Accelerometer for IioAccelerometer {
+    fn read(&mut self) -> IoResult<AVector<f64>> {
+        let av = self.update()?;
+        Ok(self.mount.rotate(AVector::<f64> {
+            x: { av.x as f64 * self.scale },
+            y: { av.y as f64 * self.scale },
+            z: { av.z as f64 * self.scale },
+        }))
+    }
+    fn read_raw(&mut self) -> IoResult<AVector<i32>> {
+        let av = self.update()?;
+        Ok(self.mount.rotate_raw(AVector::<i32> {
+            x: { av.x as i32 },
+            y: { av.y as i32 },
+            z: { av.z as i32 },
+        }))
+    }
+    fn get_scale(&self) -> f64 {
+        self.scale
+    }
+    fn event_fd(&self) -> Option<RawFd> {
+        Some(self.dev.as_raw_fd())
+    }
+}
+
+
+/// Read a (sysfs) file, trimming the trailing newline.
+fn read_trimmed<P: AsRef<Path>>(path: P) -> IoResult<String> {
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    Ok(s.trim().to_owned())
+}
+
+/// Write a value to a sysfs attribute.
+fn write_sysfs<P: AsRef<Path>>(path: P, val: &str) -> IoResult<()> {
+    trace!("Writing '{}' to {}", val, path.as_ref().to_string_lossy());
+    OpenOptions::new().write(true).open(path.as_ref())
+        .and_then(|mut f| f.write_all(val.as_bytes()))
+        .map_err(|e| IoError::new(e.kind(), format!(
+                    "couldn't write '{}' to {}: {}", val, path.as_ref().to_string_lossy(), e)))
+}
+
+/// Find the trigger belonging to a device.
+///
+/// Drivers that provide their own trigger name it `<name>-dev<N>`.
+fn guess_trigger(path: &Path) -> IoResult<Option<String>> {
+    let current = read_trimmed(path.join("trigger/current_trigger")).unwrap_or_default();
+    if !current.is_empty() {
+        return Ok(Some(current));
+    }
+    let name = read_trimmed(path.join("name"))?;
+    let devnum = path.file_name()
+        .map(|f| f.to_string_lossy().trim_start_matches("iio:device").to_owned())
+        .unwrap_or_default();
+    let wanted = format!("{}-dev{}", name, devnum);
+    let base = path.parent().unwrap_or(path).join("trigger*");
+    for entry in glob(&base.to_string_lossy()).map_err(|e|
+            IoError::new(IoErrorKind::InvalidInput, e.msg))?.flatten() {
+        if let Ok(tname) = read_trimmed(entry.join("name")) {
+            if tname == wanted {
+                return Ok(Some(tname));
+            }
+        }
+    }
+    Ok(None)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use testutil::TempDir;
+
+    /// Make a fake sysfs device directory with the given (enabled) scan
+    /// elements, as (name, index, type).
+    fn fixture(elements: &[(&str, u32, &str)]) -> TempDir {
+        let dir = TempDir::new("iioaccel");
+        fs::create_dir_all(dir.join("scan_elements")).unwrap();
+        for &(el, index, scantype) in elements {
+            let file = |suffix: &str| dir.join(format!("scan_elements/{}_{}", el, suffix));
+            fs::write(file("en"), "1\n").unwrap();
+            fs::write(file("index"), format!("{}\n", index)).unwrap();
+            fs::write(file("type"), format!("{}\n", scantype)).unwrap();
+        }
+        dir
+    }
+
+    fn offsets(layout: &ScanLayout) -> (usize, usize, usize) {
+        (layout.channels.0.offset, layout.channels.1.offset, layout.channels.2.offset)
+    }
+
+    #[test]
+    fn layout_with_timestamp() {
+        let dir = fixture(&[
+            ("in_accel_x", 0, "le:s12/16>>4"),
+            ("in_accel_y", 1, "le:s12/16>>4"),
+            ("in_accel_z", 2, "le:s12/16>>4"),
+            ("in_timestamp", 3, "le:s64/64>>0"),
+        ]);
+        let layout = ScanLayout::from_dir(dir.path(), DEFAULT_CHANNEL_PREFIX, ("x", "y", "z")).unwrap();
+        assert_eq!((0, 2, 4), offsets(&layout));
+        assert_eq!(16, layout.size);
+
+        let mut scan = [0u8; 16];
+        scan[0..2].copy_from_slice(&(-16i16 << 4).to_le_bytes());
+        scan[2..4].copy_from_slice(&(1000i16 << 4).to_le_bytes());
+        scan[4..6].copy_from_slice(&(7i16 << 4).to_le_bytes());
+        let av = layout.decode(&scan);
+        assert_eq!((-16, 1000, 7), (av.x, av.y, av.z));
+    }
+
+    #[test]
+    fn layout_aligns_to_repeat() {
+        // The 4-byte repeated element has to start on a 4-byte boundary,
+        // which pushes everything after it along.
+        let dir = fixture(&[
+            ("in_accel_x", 0, "le:s16/16>>0"),
+            ("in_quat", 1, "le:s16/16X2>>0"),
+            ("in_accel_y", 2, "le:s16/16>>0"),
+            ("in_accel_z", 3, "le:s16/16>>0"),
+        ]);
+        let layout = ScanLayout::from_dir(dir.path(), DEFAULT_CHANNEL_PREFIX, ("x", "y", "z")).unwrap();
+        assert_eq!((0, 8, 10), offsets(&layout));
+        assert_eq!(12, layout.size);
+    }
+
+    #[test]
+    fn no_reading_before_first_scan() {
+        let dir = fixture(&[
+            ("in_accel_x", 0, "le:s16/16>>0"),
+            ("in_accel_y", 1, "le:s16/16>>0"),
+            ("in_accel_z", 2, "le:s16/16>>0"),
+        ]);
+        fs::create_dir_all(dir.join("buffer")).unwrap();
+        fs::write(dir.join("buffer/enable"), "0\n").unwrap();
+        fs::write(dir.join("buffer/length"), "0\n").unwrap();
+        // A FIFO stands in for the character device.
+        let devnode = dir.join("iio:device0");
+        let cpath = ::std::ffi::CString::new(devnode.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) });
+        let mut opts: HashMap<String, String> = [
+            ("path", dir.path().to_string_lossy().into_owned()),
+            ("devnode", devnode.to_string_lossy().into_owned()),
+            ("scale", "0.5".to_owned()),
+            ("trigger", "".to_owned()),
+            ("first_timeout", "0".to_owned()),
+        ].iter().map(|(k, v)| (k.to_string(), v.to_owned())).collect();
+        let mut accel = IioAccelerometer::from_opts(&mut opts).unwrap();
+        assert_eq!("1", read_trimmed(dir.join("buffer/enable")).unwrap());
+        assert_eq!(IoErrorKind::WouldBlock, accel.update().unwrap_err().kind());
+
+        let scan = |x: i16, y: i16, z: i16| -> Vec<u8> {
+            [x, y, z].iter().flat_map(|c| c.to_le_bytes().to_vec()).collect()
+        };
+        let mut writer = OpenOptions::new().write(true).open(&devnode).unwrap();
+        writer.write_all(&scan(1, 2, 3)).unwrap();
+        writer.write_all(&scan(4, -5, 6)).unwrap();
+        let av = accel.update().unwrap();
+        assert_eq!((4, -5, 6), (av.x, av.y, av.z));
+        // With nothing new, the last scan still holds.
+        let av = accel.update().unwrap();
+        assert_eq!((4, -5, 6), (av.x, av.y, av.z));
+        drop(accel);
+        assert_eq!("0", read_trimmed(dir.join("buffer/enable")).unwrap());
+    }
+
+    #[test]
+    fn layout_needs_every_channel() {
+        let dir = fixture(&[
+            ("in_accel_x", 0, "le:s16/16>>0"),
+            ("in_accel_y", 1, "le:s16/16>>0"),
+        ]);
+        let err = ScanLayout::from_dir(dir.path(), DEFAULT_CHANNEL_PREFIX, ("x", "y", "z")).unwrap_err();
+        assert_eq!(IoErrorKind::NotFound, err.kind());
+    }
+}
@@ -2,15 +2,41 @@
 //!
 //! Traits and structs for representing accelerometers.
 
-use super::{Rotation,Orientator,SENSITIVITY};
+use super::{Rotation,Posture,Orientator,OrientationResult,sensitivity};
 
+use std::collections::HashMap;
 use std::ops::{Add,Div,Sub,Mul,AddAssign};
 use std::fmt::{Display, Formatter};
 use std::fmt::Result as FmtResult;
+use std::fs::File;
+use std::io::Read;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::time::Instant;
+
+type IoResult<T> = Result<T, IoError>;
+
+pub mod filter;
+use self::filter::{Filter, FilterChain};
+pub mod classify;
+use self::classify::{Classifier, DEFAULT_FLAT_LIMIT, FLAT_LIMIT_OPT};
+pub mod shake;
+use self::shake::ShakeGuard;
 
 #[cfg(feature = "fsaccel")]
 pub mod fsaccel;
+#[cfg(feature = "fsaccel")]
 pub use self::fsaccel::FsAccelerometer as FsAccel;
+#[cfg(feature = "iioaccel")]
+pub mod iioaccel;
+#[cfg(feature = "iioaccel")]
+pub use self::iioaccel::IioAccelerometer as IioAccel;
+#[cfg(feature = "evdevaccel")]
+pub mod evdevaccel;
+#[cfg(feature = "evdevaccel")]
+pub use self::evdevaccel::EvdevAccelerometer as EvdevAccel;
 
 
 /// Describes an acceleration vector.
@@ -59,6 +85,13 @@ impl<T> AddAssign<AccelerationVector<T>> for AccelerationVector<T> where T: AddA
     }
 }
 
+impl AccelerationVector<f64> {
+    /// The length of the vector.
+    pub fn norm(&self) -> f64 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+}
+
 impl<T,U> Mul<U> for AccelerationVector<T> where U: Into<f64>, T: Mul<f64> + Default + Clone + Copy + PartialOrd {
     type Output = AccelerationVector<<T as Mul<f64>>::Output>;
     fn mul(self, other: U) -> AccelerationVector<<T as Mul<f64>>::Output> {
@@ -84,70 +117,313 @@ impl<T,U> Div<U> for AccelerationVector<T> where U: Into<f64>, T: Div<f64> + Def
 }
 
 
+/// Standard gravity, in m/s^2, which scaled readings are expected to be in.
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// The files a sensor's mount matrix can be found in, in order of
+/// preference.
+pub const MOUNT_MATRIX_FILES: [&str; 2] = ["in_accel_mount_matrix", "mount_matrix"];
+
+/// The option used to override a sensor's mount matrix.
+pub const MOUNT_MATRIX_OPT: &str = "mount_matrix";
+
+/// Rotates sensor axes into the screen's axes.
+///
+/// Uses the same layout as the kernel's `mount_matrix` and udev's
+/// `ACCEL_MOUNT_MATRIX`: `x1, y1, z1; x2, y2, z2; x3, y3, z3`, where each
+/// row gives how much of the sensor's x, y, and z axes make up one of the
+/// screen's axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MountMatrix([[f64; 3]; 3]);
+
+impl Default for MountMatrix {
+    fn default() -> MountMatrix {
+        MountMatrix([
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+impl Display for MountMatrix {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let m = &self.0;
+        write!(f, "{}, {}, {}; {}, {}, {}; {}, {}, {}",
+               m[0][0], m[0][1], m[0][2],
+               m[1][0], m[1][1], m[1][2],
+               m[2][0], m[2][1], m[2][2])
+    }
+}
+
+impl MountMatrix {
+    /// Parse a mount matrix (`x1, y1, z1; x2, y2, z2; x3, y3, z3`).
+    pub fn from_str(s: &str) -> Result<MountMatrix, IoError> {
+        let bad = |why: String| IoError::new(IoErrorKind::InvalidData,
+                                             format!("bad mount matrix '{}': {}", s, why));
+        let mut m = [[0f64; 3]; 3];
+        let rows: Vec<&str> = s.trim().split(';').collect();
+        if 3 != rows.len() {
+            return Err(bad(format!("expected 3 rows, found {}", rows.len())));
+        }
+        for (i, row) in rows.iter().enumerate() {
+            let cols: Vec<&str> = row.split(',').collect();
+            if 3 != cols.len() {
+                return Err(bad(format!("expected 3 columns in row {}, found {}", i + 1, cols.len())));
+            }
+            for (j, col) in cols.iter().enumerate() {
+                m[i][j] = col.trim().parse::<f64>().map_err(|e| bad(format!("'{}': {}", col.trim(), e)))?;
+            }
+        }
+        Ok(MountMatrix(m))
+    }
+
+    /// Read a mount matrix from a sensor's sysfs directory, if it has one.
+    pub fn from_sysfs(dir: &Path) -> Result<Option<MountMatrix>, IoError> {
+        for fname in MOUNT_MATRIX_FILES.iter() {
+            let mut contents = String::new();
+            match File::open(dir.join(fname)) {
+                Ok(mut f)   => { f.read_to_string(&mut contents)?; },
+                Err(ref e) if IoErrorKind::NotFound == e.kind() => continue,
+                Err(e)  => return Err(e),
+            }
+            return MountMatrix::from_str(&contents).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Get the mount matrix for a sensor: the `mount_matrix` option if
+    /// set, otherwise whatever's in sysfs (if `dir` is given), otherwise
+    /// the identity.
+    pub fn from_opts(opts: &HashMap<String, String>, dir: Option<&Path>) -> Result<MountMatrix, IoError> {
+        let matrix = match (opts.get(MOUNT_MATRIX_OPT), dir) {
+            (Some(s), _)    => MountMatrix::from_str(s)?,
+            (None, Some(d)) => MountMatrix::from_sysfs(d)?.unwrap_or_default(),
+            (None, None)    => MountMatrix::default(),
+        };
+        debug!("Mount matrix is {}", matrix);
+        Ok(matrix)
+    }
+
+    /// Rotate a vector from the sensor's axes into the screen's.
+    pub fn rotate(&self, v: AccelerationVector<f64>) -> AccelerationVector<f64> {
+        let m = &self.0;
+        AccelerationVector::<f64> {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    /// Rotate a raw vector, rounding back to integers.
+    pub fn rotate_raw(&self, v: AccelerationVector<i32>) -> AccelerationVector<i32> {
+        let r = self.rotate(AccelerationVector::<f64> {
+            x: v.x as f64,
+            y: v.y as f64,
+            z: v.z as f64,
+        });
+        AccelerationVector::<i32> {
+            x: r.x.round() as i32,
+            y: r.y.round() as i32,
+            z: r.z.round() as i32,
+        }
+    }
+}
+
+
 /// Trait for an accelerometer
+#[allow(dead_code)] // spinnrd itself only needs read()
 pub trait Accelerometer {
     /// Returns the scaled output of an accelerometer, preferably in m/s^2.
     /// Up, right, and towards-the-observer should be positive.
-    fn read(&mut self) -> AccelerationVector<f64>;
+    fn read(&mut self) -> IoResult<AccelerationVector<f64>>;
 
     /// Returns the raw output of an accelerometer.
     /// Up, right, and towards-the-observer should be positive.
     /// i32 for easy conversion to f64
-    fn read_raw(&mut self) -> AccelerationVector<i32>;
+    fn read_raw(&mut self) -> IoResult<AccelerationVector<i32>>;
 
     /// Returns the scale between raw integers and m/s^2.
     fn get_scale(&self) -> f64;
+
+    /// Returns a file descriptor that's readable when there's a new
+    /// reading, if the accelerometer has one (otherwise it gets polled).
+    fn event_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Returns how much the output changed with the last reading (in
+    /// m/s^2), if the accelerometer keeps track.
+    fn motion(&self) -> Option<f64> {
+        None
+    }
+
+    /// Returns the last reading as it was before being filtered, if the
+    /// accelerometer filters its readings.
+    fn unfiltered(&self) -> Option<AccelerationVector<f64>> {
+        None
+    }
 }
 
-impl<T: Accelerometer> Orientator for T {
-    fn orientation(&mut self) -> Option<Rotation> {
-        let acc = self.read();
-        if (acc.x.abs() - acc.y.abs()).abs() > acc.z.abs() / *SENSITIVITY + 1.4715 {
-            if acc.x.abs() > acc.y.abs() {
-                if acc.x < 0.0 {
-                    trace!("rot: {}; accel: {}", Rotation::Right, acc);
-                    Some(Rotation::Right)
-                } else {
-                    trace!("rot: {}; accel: {}", Rotation::Left, acc);
-                    Some(Rotation::Left)
-                }
-            } else {
-                if acc.y < 0.0 {
-                    trace!("rot: {}; accel: {}", Rotation::Normal, acc);
-                    Some(Rotation::Normal)
-                } else {
-                    trace!("rot: {}; accel: {}", Rotation::Inverted, acc);
-                    Some(Rotation::Inverted)
-                }
-            }
-        } else {
-            trace!("rot: {}; accel: {}", "None (dxy too low)", acc);
-            None
+/// Gets the orientation from an accelerometer.
+pub struct AccelOrientator<T> {
+    accel: T,
+    classifier: Classifier,
+    shake: ShakeGuard,
+    /// How far (in degrees) from upright counts as lying flat
+    flat_limit: f64,
+    /// How the device was lying at the last reading
+    posture: Option<Posture>,
+    /// The last reading, before and after filtering
+    reading: Option<(AccelerationVector<f64>, AccelerationVector<f64>)>,
+    /// How much the reading changed last time, for accelerometers that
+    /// don't keep track themselves
+    motion: f64,
+}
+
+impl<T: Accelerometer> AccelOrientator<T> {
+    /// Set up the classifier from `opts` (see `Classifier::from_opts`), how
+    /// flat the device has to be to be lying flat (`flat_limit`), and when
+    /// it's being shaken (see `ShakeGuard::from_opts`).
+    pub fn from_opts(accel: T, opts: &HashMap<String, String>) -> IoResult<AccelOrientator<T>> {
+        let flat_limit = match opts.get(FLAT_LIMIT_OPT) {
+            Some(f) => match f.trim().parse::<f64>() {
+                Ok(f) if (0.0..=90.0).contains(&f)  => f,
+                _   => return Err(IoError::new(IoErrorKind::InvalidInput,
+                                               format!("bad {} '{}'", FLAT_LIMIT_OPT, f))),
+            },
+            None    => DEFAULT_FLAT_LIMIT,
+        };
+        Ok(AccelOrientator {
+            accel,
+            classifier: Classifier::from_opts(opts)?,
+            shake: ShakeGuard::from_opts(opts)?,
+            flat_limit,
+            posture: None,
+            reading: None,
+            motion: 0.0,
+        })
+    }
+
+    /// The rotation `acc` (filtered from `raw`, taken at `at`) points to,
+    /// unless the device is being shaken around.
+    fn classify(&mut self, acc: AccelerationVector<f64>, raw: AccelerationVector<f64>, at: Instant)
+        -> Option<Rotation>
+    {
+        // While it's shaken, the posture stays as it was.
+        if ! self.shake.steady(raw, at) {
+            trace!("rot: None (shaken); accel: {}", raw);
+            return None;
+        }
+        self.posture = classify::posture(acc, self.flat_limit);
+        self.classifier.classify(acc)
+    }
+}
+
+impl<T: Accelerometer> Orientator for AccelOrientator<T> {
+    fn orientation(&mut self) -> OrientationResult {
+        let acc = match self.accel.read() {
+            Ok(a)   => a,
+            // Nothing to go by yet
+            Err(ref e) if IoErrorKind::WouldBlock == e.kind()   => return Ok(None),
+            Err(e)  => return Err(e),
+        };
+        let raw = self.accel.unfiltered().unwrap_or(acc);
+        // Unfiltered, it moves as much as the readings do.
+        self.motion = self.reading.map_or(0.0, |(_, last)| (acc - last).norm());
+        self.reading = Some((raw, acc));
+        Ok(self.classify(acc, raw, Instant::now()))
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        self.accel.event_fd()
+    }
+
+    fn motion(&self) -> Option<f64> {
+        self.accel.motion().or(Some(self.motion))
+    }
+
+    fn posture(&self) -> Option<Posture> {
+        self.posture
+    }
+
+    fn shaken(&self) -> bool {
+        self.shake.settling()
+    }
+
+    fn readings(&self) -> Vec<(&'static str, String)> {
+        let show = |v: AccelerationVector<f64>| format!("{:.2} {:.2} {:.2}", v.x, v.y, v.z);
+        match self.reading {
+            Some((raw, filtered))   => vec![("raw", show(raw)), ("filtered", show(filtered))],
+            None    => Vec::new(),
         }
     }
 }
 
 
 /// Trait for accelerometer with low-pass filtering
-#[derive(Default, Debug, Clone, Copy)]
+///
+/// Usually, the filtering is an exponential moving average with a time
+/// constant of `--hysteresis` (see `FilterChain::ema`), but any
+/// `FilterChain` can be used.
 pub struct FilteredAccelerometer<T> {
     accel: T,
-    mult: f64,
-    current: AccelerationVector<f64>
+    filters: FilterChain,
+    /// The last reading, before filtering
+    reading: AccelerationVector<f64>,
+    current: AccelerationVector<f64>,
+    /// When `current` was last updated
+    updated: Instant,
+    /// How much `current` changed with the last update
+    motion: f64,
+    /// Whether the filters have been started from a reading yet
+    seeded: bool,
 }
 
 impl<T: Accelerometer> FilteredAccelerometer<T> {
-    pub fn new(mut accel: T, mult: f64) -> FilteredAccelerometer<T> {
-        let ival = accel.read();
-        FilteredAccelerometer::<T> {
-            accel: accel,
-            mult: mult,
+    /// Start filtering `accel`'s readings. If it doesn't have one yet
+    /// (`WouldBlock`), the filters start from the first one it has.
+    pub fn new(mut accel: T, mut filters: FilterChain) -> IoResult<FilteredAccelerometer<T>> {
+        let (ival, seeded) = match accel.read() {
+            Ok(a)   => (a, true),
+            Err(ref e) if IoErrorKind::WouldBlock == e.kind()   => (AccelerationVector::default(), false),
+            Err(e)  => return Err(e),
+        };
+        let now = Instant::now();
+        filters.reset(ival, now);
+        Ok(FilteredAccelerometer::<T> {
+            accel,
+            filters,
+            reading: ival,
             current: ival,
+            updated: now,
+            motion: 0.0,
+            seeded,
+        })
+    }
+
+    pub fn update(&mut self) -> IoResult<()> {
+        let reading = self.accel.read()?;
+        let now = Instant::now();
+        if ! self.seeded {
+            self.filters.reset(reading, now);
+            self.reading = reading;
+            self.current = reading;
+            self.updated = now;
+            self.seeded = true;
+            return Ok(());
         }
+        self.filter(reading, now);
+        Ok(())
     }
 
-    pub fn update(&mut self) {
-        self.current += (self.accel.read() - self.current) * self.mult;
+    /// Run `reading`, taken at `at`, through the filters.
+    fn filter(&mut self, reading: AccelerationVector<f64>, at: Instant) {
+        self.reading = reading;
+        let filtered = self.filters.filter(reading, at);
+        self.motion = (filtered - self.current).norm();
+        self.current = filtered;
+        self.updated = at;
     }
 
     pub fn raw_estimate(&self) -> AccelerationVector<i32> {
@@ -161,30 +437,42 @@ impl<T: Accelerometer> FilteredAccelerometer<T> {
 }
 
 impl<T: Accelerometer> Accelerometer for FilteredAccelerometer<T> {
-    fn read(&mut self) -> AccelerationVector<f64> {
-        self.update();
-        return self.current
+    fn read(&mut self) -> IoResult<AccelerationVector<f64>> {
+        self.update()?;
+        return Ok(self.current)
     }
 
-    fn read_raw(&mut self) -> AccelerationVector<i32> {
-        self.update();
-        return self.raw_estimate()
+    fn read_raw(&mut self) -> IoResult<AccelerationVector<i32>> {
+        self.update()?;
+        return Ok(self.raw_estimate())
     }
 
     fn get_scale(&self) -> f64 {
         self.accel.get_scale()
     }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        Accelerometer::event_fd(&self.accel)
+    }
+
+    fn motion(&self) -> Option<f64> {
+        Some(self.motion)
+    }
+
+    fn unfiltered(&self) -> Option<AccelerationVector<f64>> {
+        Some(self.reading)
+    }
 }
 
 impl<'l, T: Accelerometer> Accelerometer for &'l mut FilteredAccelerometer<T> {
-    fn read(&mut self) -> AccelerationVector<f64> {
-        self.update();
-        return self.current
+    fn read(&mut self) -> IoResult<AccelerationVector<f64>> {
+        self.update()?;
+        return Ok(self.current)
     }
 
-    fn read_raw(&mut self) -> AccelerationVector<i32> {
-        self.update();
-        return self.raw_estimate()
+    fn read_raw(&mut self) -> IoResult<AccelerationVector<i32>> {
+        self.update()?;
+        return Ok(self.raw_estimate())
     }
 
     fn get_scale(&self) -> f64 {
@@ -192,3 +480,205 @@ impl<'l, T: Accelerometer> Accelerometer for &'l mut FilteredAccelerometer<T> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::Duration;
+    use testutil::TempDir;
+
+    /// An accelerometer that always reads the same thing
+    struct Still(AccelerationVector<f64>);
+
+    impl Accelerometer for Still {
+        fn read(&mut self) -> IoResult<AccelerationVector<f64>> {
+            Ok(self.0)
+        }
+
+        fn read_raw(&mut self) -> IoResult<AccelerationVector<i32>> {
+            Ok(AccelerationVector::default())
+        }
+
+        fn get_scale(&self) -> f64 {
+            1.0
+        }
+    }
+
+    /// An accelerometer that hasn't got a reading until it's given one
+    struct Pending(Option<AccelerationVector<f64>>);
+
+    impl Accelerometer for Pending {
+        fn read(&mut self) -> IoResult<AccelerationVector<f64>> {
+            self.0.ok_or_else(|| IoError::new(IoErrorKind::WouldBlock, "no reading yet"))
+        }
+
+        fn read_raw(&mut self) -> IoResult<AccelerationVector<i32>> {
+            Ok(AccelerationVector::default())
+        }
+
+        fn get_scale(&self) -> f64 {
+            1.0
+        }
+    }
+
+    /// How far (as a fraction) a filter with a 1s time constant gets
+    /// towards a new reading, given readings at `times` (in ms).
+    fn step_response(times: &[u64]) -> f64 {
+        let mut filter = FilteredAccelerometer::new(
+            Still(AccelerationVector::default()), FilterChain::ema(Duration::from_secs(1))).unwrap();
+        let start = filter.updated;
+        let step = AccelerationVector { x: 0.0, y: -9.81, z: 0.0 };
+        for &t in times {
+            filter.filter(step, start + Duration::from_millis(t));
+        }
+        filter.current.y / step.y
+    }
+
+    #[test]
+    fn settles_with_time_not_readings() {
+        let one_time_constant = 1.0 - (-1.0f64).exp();
+        let regular: Vec<u64> = (1..21).map(|i| i * 50).collect();
+        for times in &[&regular[..], &[3, 10, 400, 410, 415, 900, 1000], &[1000]] {
+            let response = step_response(times);
+            assert!((response - one_time_constant).abs() < 1e-9,
+                    "got {} with readings at {:?}", response, times);
+        }
+    }
+
+    #[test]
+    fn settling_time() {
+        // 95% of the way there takes 3 time constants
+        assert!(step_response(&[120, 1700, 2150, 2990]) < 0.95);
+        assert!(step_response(&[120, 1700, 2150, 3000]) >= 0.95);
+        assert!(step_response(&[5, 2000, 3000]) >= 0.95);
+    }
+
+    #[test]
+    fn bumps_dont_rotate() {
+        let mut orient = AccelOrientator::from_opts(
+            Still(AccelerationVector::default()), &HashMap::new()).unwrap();
+        let start = Instant::now();
+        let normal = AccelerationVector { x: 0.0, y: -9.81, z: 0.0 };
+        // A knock to the side, then lying on its side (but still jiggling)
+        let bump = AccelerationVector { x: 15.0, y: -9.81, z: 0.0 };
+        let left = AccelerationVector { x: 9.81, y: 0.0, z: 0.0 };
+        let trace = [(0, normal, Some(Rotation::Normal)), (50, bump, None), (100, left, None),
+                     (400, left, None), (450, left * 1.5, None), (900, left, None),
+                     (1000, left, Some(Rotation::Left))];
+        for &(t, acc, rotation) in &trace {
+            let at = start + Duration::from_millis(t);
+            assert_eq!(rotation, orient.classify(acc, acc, at), "at {}ms", t);
+        }
+    }
+
+    #[test]
+    fn no_time_constant_follows_readings() {
+        let mut filter = FilteredAccelerometer::new(
+            Still(AccelerationVector::default()), FilterChain::ema(Duration::from_secs(0))).unwrap();
+        let at = filter.updated;
+        filter.filter(AccelerationVector { x: 9.81, y: 0.0, z: 0.0 }, at);
+        assert_eq!(9.81, filter.current.x);
+    }
+
+    #[test]
+    fn filters_start_from_first_reading() {
+        let mut filter = FilteredAccelerometer::new(
+            Pending(None), FilterChain::ema(Duration::from_secs(1))).unwrap();
+        assert_eq!(IoErrorKind::WouldBlock, filter.read().unwrap_err().kind());
+        // Not averaged with anything from before it had a reading
+        let left = AccelerationVector { x: 9.81, y: 0.0, z: 0.0 };
+        filter.accel.0 = Some(left);
+        assert_eq!(9.81, filter.read().unwrap().x);
+        assert_eq!(9.81, filter.read().unwrap().x);
+    }
+
+    #[test]
+    fn no_reading_yet_is_no_rotation() {
+        let mut orient = AccelOrientator::from_opts(Pending(None), &HashMap::new()).unwrap();
+        assert_eq!(None, orient.orientation().unwrap());
+        orient.accel.0 = Some(AccelerationVector { x: 0.0, y: -9.81, z: 0.0 });
+        assert_eq!(Some(Rotation::Normal), orient.orientation().unwrap());
+    }
+
+    /// A portrait panel's matrix, turning the sensor a quarter turn
+    const PORTRAIT: &str = "0, -1, 0; 1, 0, 0; 0, 0, 1";
+
+    fn portrait() -> MountMatrix {
+        MountMatrix([
+            [0.0, -1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ])
+    }
+
+    fn opts(matrix: &str) -> HashMap<String, String> {
+        let mut opts = HashMap::new();
+        opts.insert(MOUNT_MATRIX_OPT.to_owned(), matrix.to_owned());
+        opts
+    }
+
+    #[test]
+    fn parses_mount_matrix() {
+        assert_eq!(portrait(), MountMatrix::from_str(PORTRAIT).unwrap());
+        // As udev's hwdb and the kernel write them
+        assert_eq!(portrait(), MountMatrix::from_str("0,-1,0;1,0,0;0,0,1").unwrap());
+        assert_eq!(portrait(), MountMatrix::from_str(" 0, -1, 0; 1, 0, 0; 0, 0, 1\n").unwrap());
+        assert_eq!(MountMatrix([[0.5, 0.0, 0.0], [0.0, -0.5, 0.0], [0.0, 0.0, 1.0]]),
+                   MountMatrix::from_str("0.5, 0, 0; 0, -0.5, 0; 0, 0, 1").unwrap());
+        // Round trip
+        assert_eq!(portrait(), MountMatrix::from_str(&portrait().to_string()).unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_mount_matrix() {
+        for bad in &["", "1, 0, 0; 0, 1, 0", "1, 0, 0; 0, 1, 0; 0, 0, 1; 0, 0, 0",
+                     "1, 0; 0, 1, 0; 0, 0, 1", "1, 0, 0; 0, 1, 0, 0; 0, 0, 1",
+                     "1, 0, 0; 0, one, 0; 0, 0, 1", "1, 0, 0; 0, , 0; 0, 0, 1"] {
+            let e = MountMatrix::from_str(bad).unwrap_err();
+            assert_eq!(IoErrorKind::InvalidData, e.kind(), "{:?}", bad);
+            assert!(e.to_string().contains(bad), "{}", e);
+        }
+    }
+
+    #[test]
+    fn rotates() {
+        let v = AccelerationVector::<f64> { x: 1.0, y: 2.0, z: 3.0 };
+        let r = portrait().rotate(v);
+        assert_eq!((-2.0, 1.0, 3.0), (r.x, r.y, r.z));
+        let r = MountMatrix::default().rotate(v);
+        assert_eq!((1.0, 2.0, 3.0), (r.x, r.y, r.z));
+    }
+
+    #[test]
+    fn reads_mount_matrix_from_sysfs() {
+        let dir = TempDir::new("mount-matrix");
+        assert_eq!(None, MountMatrix::from_sysfs(dir.path()).unwrap());
+        assert_eq!(MountMatrix::default(), MountMatrix::from_opts(&HashMap::new(), Some(dir.path())).unwrap());
+
+        fs::write(dir.join("mount_matrix"), "1, 0, 0; 0, -1, 0; 0, 0, -1\n").unwrap();
+        let flipped = MountMatrix([[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]]);
+        assert_eq!(Some(flipped), MountMatrix::from_sysfs(dir.path()).unwrap());
+
+        // The IIO channel's own one comes first.
+        fs::write(dir.join("in_accel_mount_matrix"), format!("{}\n", PORTRAIT)).unwrap();
+        assert_eq!(Some(portrait()), MountMatrix::from_sysfs(dir.path()).unwrap());
+        assert_eq!(portrait(), MountMatrix::from_opts(&HashMap::new(), Some(dir.path())).unwrap());
+
+        fs::write(dir.join("in_accel_mount_matrix"), "garbage\n").unwrap();
+        assert!(MountMatrix::from_sysfs(dir.path()).is_err());
+    }
+
+    #[test]
+    fn option_overrides_sysfs() {
+        let dir = TempDir::new("mount-matrix-opt");
+        fs::write(dir.join("in_accel_mount_matrix"), "1, 0, 0; 0, -1, 0; 0, 0, -1\n").unwrap();
+        assert_eq!(portrait(), MountMatrix::from_opts(&opts(PORTRAIT), Some(dir.path())).unwrap());
+        assert_eq!(portrait(), MountMatrix::from_opts(&opts(PORTRAIT), None).unwrap());
+        // Even a broken sysfs one doesn't matter then...
+        fs::write(dir.join("in_accel_mount_matrix"), "garbage\n").unwrap();
+        assert_eq!(portrait(), MountMatrix::from_opts(&opts(PORTRAIT), Some(dir.path())).unwrap());
+        // ...but a broken option does.
+        assert!(MountMatrix::from_opts(&opts("1, 0, 0"), Some(dir.path())).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! # shake
+//! Telling when readings are thrown off by the device being shaken, bumped,
+//! or carried around, rather than just tilted.
+
+use super::*;
+
+use std::time::Duration;
+
+/// The option used to set how far from 1g a reading can be
+pub const SHAKE_TOLERANCE_OPT: &str = "shake_tolerance";
+
+/// The option used to set how long to wait after being shaken
+pub const SHAKE_SETTLE_OPT: &str = "shake_settle";
+
+/// How far (in m/s^2) the acceleration can be from gravity before a
+/// reading is thrown out
+pub const DEFAULT_SHAKE_TOLERANCE: f64 = 3.0;
+
+/// How long (in ms) to ignore readings for after being shaken
+pub const DEFAULT_SHAKE_SETTLE: u64 = 500;
+
+/// Throws out readings that are too far from 1g to be just gravity, and
+/// any that come soon after.
+///
+/// Readings have to be in m/s^2 (i.e. the backend's scale has to be right),
+/// or they'll all look too far off.
+///
+/// Lying still (or being turned slowly), an accelerometer only reads
+/// gravity, so anything much stronger or weaker means it's being moved
+/// around, and the direction it reads says little about which way is down.
+pub struct ShakeGuard {
+    /// How far (in m/s^2) from 1g a reading can be (0 turns this off)
+    tolerance: f64,
+    /// How long after being shaken to start trusting readings again
+    settle: Duration,
+    /// When the last reading that was too far from 1g was taken
+    shaken: Option<Instant>,
+}
+
+impl ShakeGuard {
+    pub fn new(tolerance: f64, settle: Duration) -> ShakeGuard {
+        ShakeGuard {
+            tolerance,
+            settle,
+            shaken: None,
+        }
+    }
+
+    /// Get the tolerance (`shake_tolerance`, in m/s^2) and settling time
+    /// (`shake_settle`, in ms) from `opts`.
+    pub fn from_opts(opts: &HashMap<String, String>) -> IoResult<ShakeGuard> {
+        let bad = |opt: &str, value: &str| IoError::new(IoErrorKind::InvalidInput,
+                                                        format!("bad {} '{}'", opt, value));
+        let tolerance = match opts.get(SHAKE_TOLERANCE_OPT) {
+            Some(t) => match t.trim().parse::<f64>() {
+                Ok(t) if 0.0 <= t   => t,
+                _   => return Err(bad(SHAKE_TOLERANCE_OPT, t)),
+            },
+            None    => DEFAULT_SHAKE_TOLERANCE,
+        };
+        let settle = match opts.get(SHAKE_SETTLE_OPT) {
+            Some(s) => s.trim().parse::<u64>().map_err(|_| bad(SHAKE_SETTLE_OPT, s))?,
+            None    => DEFAULT_SHAKE_SETTLE,
+        };
+        Ok(ShakeGuard::new(tolerance, Duration::from_millis(settle)))
+    }
+
+    /// Whether readings are still being ignored after being shaken.
+    pub fn settling(&self) -> bool {
+        self.shaken.is_some()
+    }
+
+    /// Whether `reading` (before any filtering), taken at `at`, can be
+    /// trusted to point down.
+    pub fn steady(&mut self, reading: AccelerationVector<f64>, at: Instant) -> bool {
+        if 0.0 == self.tolerance {
+            return true;
+        }
+        let off = (reading.norm() - STANDARD_GRAVITY).abs();
+        if off > self.tolerance {
+            if self.shaken.is_none() {
+                debug!("Shaken ({:.1} m/s^2 off 1g); waiting for it to settle", off);
+            }
+            self.shaken = Some(at);
+            return false;
+        }
+        match self.shaken {
+            Some(s) if at.saturating_duration_since(s) < self.settle    => false,
+            Some(_) => {
+                debug!("Settled");
+                self.shaken = None;
+                true
+            },
+            None    => true,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Gravity straight down the screen, scaled by `g`
+    fn down(g: f64) -> AccelerationVector<f64> {
+        AccelerationVector { x: 0.0, y: -STANDARD_GRAVITY * g, z: 0.0 }
+    }
+
+    /// Which readings in `trace` (of ms and readings) `guard` trusts
+    fn run(guard: &mut ShakeGuard, trace: &[(u64, AccelerationVector<f64>)]) -> Vec<bool> {
+        let start = Instant::now();
+        trace.iter()
+            .map(|&(t, r)| guard.steady(r, start + Duration::from_millis(t)))
+            .collect()
+    }
+
+    #[test]
+    fn ignores_shaking() {
+        let mut guard = ShakeGuard::new(3.0, Duration::from_millis(0));
+        let trace = [(0, down(1.0)), (50, down(1.5)), (100, down(0.2)), (150, down(1.2))];
+        assert_eq!(vec![true, false, false, true], run(&mut guard, &trace));
+    }
+
+    #[test]
+    fn waits_to_settle() {
+        let mut guard = ShakeGuard::new(DEFAULT_SHAKE_TOLERANCE,
+                                        Duration::from_millis(DEFAULT_SHAKE_SETTLE));
+        let trace = [(0, down(1.0)), (100, down(2.0)), (150, down(1.0)), (550, down(1.0)),
+                     (600, down(1.0)), (650, down(0.0)), (700, down(1.0)), (1200, down(1.0))];
+        assert_eq!(vec![true, false, false, false, true, false, false, true],
+                   run(&mut guard, &trace));
+    }
+
+    #[test]
+    fn settling_until_trusted() {
+        let mut guard = ShakeGuard::new(3.0, Duration::from_millis(DEFAULT_SHAKE_SETTLE));
+        let start = Instant::now();
+        assert!(!guard.settling());
+        guard.steady(down(2.0), start);
+        assert!(guard.settling());
+        // Nothing new comes in once it's put down, but it's still settling
+        // until a reading after the settling time says so.
+        assert!(guard.settling());
+        assert!(!guard.steady(down(1.0), start + Duration::from_millis(100)));
+        assert!(guard.settling());
+        assert!(guard.steady(down(1.0), start + Duration::from_millis(DEFAULT_SHAKE_SETTLE)));
+        assert!(!guard.settling());
+    }
+
+    #[test]
+    fn no_tolerance_trusts_everything() {
+        let mut guard = ShakeGuard::new(0.0, Duration::from_millis(DEFAULT_SHAKE_SETTLE));
+        let trace = [(0, down(3.0)), (10, down(0.0)), (20, down(1.0))];
+        assert_eq!(vec![true, true, true], run(&mut guard, &trace));
+    }
+}
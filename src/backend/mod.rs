@@ -4,32 +4,67 @@
 
 use super::*;
 
-#[cfg(any(feature = "fsaccel", feature = "iioaccel"))]
-use accel::FilteredAccelerometer;
+#[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+use accel::{AccelOrientator, FilteredAccelerometer};
+#[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+use accel::filter::{FilterChain, FilterError, FILTER_OPT};
 
 #[cfg(feature = "fsaccel")]
 use accel::FsAccel;
 
+#[cfg(feature = "iioaccel")]
+use accel::IioAccel;
+
+#[cfg(feature = "evdevaccel")]
+use accel::EvdevAccel;
+
+#[cfg(feature = "sensorproxy")]
+mod sensorproxy;
+
 #[allow(dead_code)] // doesn't need to be used, just needs to exist
-struct DummyOrientator();
+pub struct DummyOrientator();
 impl Orientator for DummyOrientator {
-    fn orientation(&mut self) -> Option<Rotation> {
-        None
+    fn orientation(&mut self) -> OrientationResult {
+        Ok(None)
     }
 }
 
 #[cfg(feature = "fsaccel")]
-type FsAccelT = FsAccel;
+type FsAccelT = AccelOrientator<FsAccel>;
 #[cfg(feature = "fsaccel")]
-type FilteredFsAccelT = FilteredAccelerometer<FsAccel>;
+type FilteredFsAccelT = AccelOrientator<FilteredAccelerometer<FsAccel>>;
 #[cfg(not(feature = "fsaccel"))]
 type FsAccelT = DummyOrientator;
 #[cfg(not(feature = "fsaccel"))]
 type FilteredFsAccelT = DummyOrientator;
 
+#[cfg(feature = "iioaccel")]
+type IioAccelT = AccelOrientator<IioAccel>;
+#[cfg(feature = "iioaccel")]
+type FilteredIioAccelT = AccelOrientator<FilteredAccelerometer<IioAccel>>;
+#[cfg(not(feature = "iioaccel"))]
+type IioAccelT = DummyOrientator;
+#[cfg(not(feature = "iioaccel"))]
+type FilteredIioAccelT = DummyOrientator;
+
+#[cfg(feature = "evdevaccel")]
+type EvdevAccelT = AccelOrientator<EvdevAccel>;
+#[cfg(feature = "evdevaccel")]
+type FilteredEvdevAccelT = AccelOrientator<FilteredAccelerometer<EvdevAccel>>;
+#[cfg(not(feature = "evdevaccel"))]
+type EvdevAccelT = DummyOrientator;
+#[cfg(not(feature = "evdevaccel"))]
+type FilteredEvdevAccelT = DummyOrientator;
+
+#[cfg(feature = "sensorproxy")]
+type SensorProxyT = sensorproxy::SensorProxy;
+#[cfg(not(feature = "sensorproxy"))]
+type SensorProxyT = DummyOrientator;
+
 
 pub fn backend_help() -> String {
-    format!("{}", fsbackendhelp())
+    format!("{}{}{}{}", fsbackendhelp(), iiobackendhelp(), evdevbackendhelp(),
+            sensorproxybackendhelp())
 }
 
 #[cfg(feature = "fsaccel")]
@@ -39,8 +74,10 @@ fn fsbackendhelp() -> String {
     For fsaccel:
         path: The path to the accelerometer files.
             [Autodetects if not set]
-        scale: Use a set scale instead of reading the scale file.
-        defscale: A default scale to use in case the scale file can't be found.
+        scale: Use a set scale instead of reading the scale file. Scaled 
+            readings must be in m/s^2 (see UNITS).
+        defscale: A default scale to use in case the scale file can't be 
+            found, as for scale.
         scalefile: The name of the file to check for the scale.
             [Defaults to \"{}\"]
         data_prefix: The part of the channel data file name before the 
@@ -53,65 +90,373 @@ fn fsbackendhelp() -> String {
             the channel name. [Defaults to \"{}\"]
         fix_sign: Whether to apply signfix (when signed integers are 
             written as unsigned). [Defaults to {}]
+        mount_matrix: Override the sensor's mount matrix, as 
+            'x1, y1, z1; x2, y2, z2; x3, y3, z3' (like udev's 
+            ACCEL_MOUNT_MATRIX).
+            [Defaults to the contents of {}]
+        filter: The filters to smooth readings with, in order, separated 
+            by '+': ema:<time constant in ms>, median:<readings>, 
+            mean:<readings>, or kalman[:<q>:<r>] (q being how much the 
+            acceleration wanders, in (m/s^2)^2 per second, and r how noisy 
+            readings are, in (m/s^2)^2; both default to {} and {}). 'none' 
+            turns filtering off, like fsaccel_raw.
+            [Defaults to ema:<--hysteresis>]
+        classifier: How to get the rotation from the direction of gravity: 
+            'legacy' (using --sensitivity) or 'angle' (from the angle 
+            gravity makes with the screen, sticking with the current 
+            rotation near the boundaries). [Defaults to legacy]
+        tilt_limit: With the angle classifier, how far (in degrees) the 
+            screen can tip back from upright and still rotate.
+            [Defaults to {}]
+        band: With the angle classifier, how far (in degrees) past the 45° 
+            boundary between rotations to stick with the current one.
+            [Defaults to {}]
+        flat_limit: How far (in degrees) the screen has to tip back (or 
+            forward) from upright to be lying flat (face-up or face-down).
+            [Defaults to {}]
+        shake_tolerance: How far (in m/s^2) the acceleration can be from 
+            1g before a reading is ignored as the device being shaken or 
+            carried around; 0 never ignores any. [Defaults to {}]
+        shake_settle: How long (in ms) to keep ignoring readings after 
+            being shaken. [Defaults to {}]
 ", DEFAULT_SCALE_FILE, DEFAULT_DATA_PREFIX,
    DEFAULT_DESCR_PREFIX, DEFAULT_DATA_SUFFIX, DEFAULT_DESCR_SUFFIX,
-   DEFAULT_FIX_SIGN
+   DEFAULT_FIX_SIGN, ::accel::MOUNT_MATRIX_FILES.join(" or "),
+   ::accel::filter::DEFAULT_KALMAN_Q, ::accel::filter::DEFAULT_KALMAN_R,
+   ::accel::classify::DEFAULT_TILT_LIMIT, ::accel::classify::DEFAULT_BAND,
+   ::accel::classify::DEFAULT_FLAT_LIMIT,
+   ::accel::shake::DEFAULT_SHAKE_TOLERANCE, ::accel::shake::DEFAULT_SHAKE_SETTLE
     )
 }
 #[cfg(not(feature = "fsaccel"))]
 fn fsbackendhelp() -> String { "".to_owned() }
 
+#[cfg(feature = "iioaccel")]
+fn iiobackendhelp() -> String {
+    use accel::iioaccel::*;
+    format!("
+    For iioaccel:
+        path: The sysfs directory of the IIO device.
+            [Autodetects if not set]
+        devnode: The buffered character device to read scans from.
+            [Defaults to \"{}/<device name>\"]
+        scale: Use a set scale instead of reading the scale file, as for 
+            fsaccel.
+        scalefile: The name of the file to check for the scale.
+            [Defaults to \"{}\"]
+        channel_prefix: The part of the scan element names before the 
+            channel name. [Defaults to \"{}\"]
+        trigger: The name of the trigger to use; empty for none.
+            [Defaults to the current trigger, or the device's own]
+        buffer_length: How many scans the kernel buffer holds.
+            [Defaults to {}]
+        sampling_frequency: Set the sampling frequency (in Hz).
+            [Leaves it alone if not set]
+        first_timeout: How long to wait for the first scan (in ms).
+            [Defaults to {}]
+        mount_matrix: Override the sensor's mount matrix, as for fsaccel.
+            [Defaults to the contents of {}]
+        filter: The filters to smooth readings with, as for fsaccel.
+            [Defaults to ema:<--hysteresis>]
+        classifier, tilt_limit, band, flat_limit, shake_tolerance, 
+            shake_settle: As for fsaccel.
+", DEFAULT_IIOACCEL_DEVDIR, ::accel::fsaccel::DEFAULT_SCALE_FILE,
+   DEFAULT_CHANNEL_PREFIX, DEFAULT_BUFFER_LENGTH, DEFAULT_FIRST_TIMEOUT,
+   ::accel::MOUNT_MATRIX_FILES.join(" or ")
+    )
+}
+#[cfg(not(feature = "iioaccel"))]
+fn iiobackendhelp() -> String { "".to_owned() }
+
+#[cfg(feature = "evdevaccel")]
+fn evdevbackendhelp() -> String {
+    use accel::evdevaccel::*;
+    format!("
+    For evdevaccel:
+        path: The input event device of the accelerometer.
+            [Autodetects within \"{}\" if not set]
+        scale: Use a set scale instead of the axis resolution, as for 
+            fsaccel.
+        mount_matrix: The sensor's mount matrix, as for fsaccel.
+            [Defaults to the identity matrix]
+        filter: The filters to smooth readings with, as for fsaccel.
+            [Defaults to ema:<--hysteresis>]
+        classifier, tilt_limit, band, flat_limit, shake_tolerance, 
+            shake_settle: As for fsaccel.
+", DEFAULT_EVDEVACCEL_PATH
+    )
+}
+#[cfg(not(feature = "evdevaccel"))]
+fn evdevbackendhelp() -> String { "".to_owned() }
+
+#[cfg(feature = "sensorproxy")]
+fn sensorproxybackendhelp() -> String {
+    use self::sensorproxy::*;
+    format!("
+    For sensorproxy:
+        Gets the orientation from iio-sensor-proxy instead of reading the 
+        accelerometer (so there's no filtering, and no _raw version).
+        bus: 'system' or 'session'. [Defaults to system]
+        name: The bus name of iio-sensor-proxy. [Defaults to {}]
+        timeout: How long to wait for iio-sensor-proxy to answer (in ms).
+            [Defaults to {}]
+", DEFAULT_SENSORPROXY_NAME, DEFAULT_SENSORPROXY_TIMEOUT
+    )
+}
+#[cfg(not(feature = "sensorproxy"))]
+fn sensorproxybackendhelp() -> String { "".to_owned() }
+
 
 pub enum OrientatorKind {
     FsAccel(FilteredFsAccelT),
     FsAccelRaw(FsAccelT),
-    // IioAccel(FilteredIioAccelT),
-    // IioAccelRaw(IioAccelT),
+    IioAccel(FilteredIioAccelT),
+    IioAccelRaw(IioAccelT),
+    EvdevAccel(FilteredEvdevAccelT),
+    EvdevAccelRaw(EvdevAccelT),
+    #[cfg_attr(not(feature = "sensorproxy"), allow(dead_code))]
+    SensorProxy(SensorProxyT),
     // FaceCam(FaceCamT),
 }
 
 impl Orientator for OrientatorKind {
-    fn orientation(&mut self) -> Option <Rotation> {
+    fn orientation(&mut self) -> OrientationResult {
         match self {
             &mut OrientatorKind::FsAccel(ref mut a) => a.orientation(),
             &mut OrientatorKind::FsAccelRaw(ref mut a) => a.orientation(),
-            // &mut OrientatorKind::IioAccel(a)    => a.orientation(),
+            &mut OrientatorKind::IioAccel(ref mut a) => a.orientation(),
+            &mut OrientatorKind::IioAccelRaw(ref mut a) => a.orientation(),
+            &mut OrientatorKind::EvdevAccel(ref mut a) => a.orientation(),
+            &mut OrientatorKind::EvdevAccelRaw(ref mut a) => a.orientation(),
+            &mut OrientatorKind::SensorProxy(ref mut s) => s.orientation(),
             // &mut OrientatorKind::FaceCam(c) => c.orientation(),
         }
     }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        match self {
+            &OrientatorKind::FsAccel(ref a) => a.event_fd(),
+            &OrientatorKind::FsAccelRaw(ref a) => a.event_fd(),
+            &OrientatorKind::IioAccel(ref a) => a.event_fd(),
+            &OrientatorKind::IioAccelRaw(ref a) => a.event_fd(),
+            &OrientatorKind::EvdevAccel(ref a) => a.event_fd(),
+            &OrientatorKind::EvdevAccelRaw(ref a) => a.event_fd(),
+            &OrientatorKind::SensorProxy(ref s) => s.event_fd(),
+        }
+    }
+
+    fn motion(&self) -> Option<f64> {
+        match self {
+            &OrientatorKind::FsAccel(ref a) => a.motion(),
+            &OrientatorKind::FsAccelRaw(ref a) => a.motion(),
+            &OrientatorKind::IioAccel(ref a) => a.motion(),
+            &OrientatorKind::IioAccelRaw(ref a) => a.motion(),
+            &OrientatorKind::EvdevAccel(ref a) => a.motion(),
+            &OrientatorKind::EvdevAccelRaw(ref a) => a.motion(),
+            &OrientatorKind::SensorProxy(ref s) => s.motion(),
+        }
+    }
+
+    fn posture(&self) -> Option<Posture> {
+        match self {
+            &OrientatorKind::FsAccel(ref a) => a.posture(),
+            &OrientatorKind::FsAccelRaw(ref a) => a.posture(),
+            &OrientatorKind::IioAccel(ref a) => a.posture(),
+            &OrientatorKind::IioAccelRaw(ref a) => a.posture(),
+            &OrientatorKind::EvdevAccel(ref a) => a.posture(),
+            &OrientatorKind::EvdevAccelRaw(ref a) => a.posture(),
+            &OrientatorKind::SensorProxy(ref s) => s.posture(),
+        }
+    }
+
+    fn shaken(&self) -> bool {
+        match self {
+            &OrientatorKind::FsAccel(ref a) => a.shaken(),
+            &OrientatorKind::FsAccelRaw(ref a) => a.shaken(),
+            &OrientatorKind::IioAccel(ref a) => a.shaken(),
+            &OrientatorKind::IioAccelRaw(ref a) => a.shaken(),
+            &OrientatorKind::EvdevAccel(ref a) => a.shaken(),
+            &OrientatorKind::EvdevAccelRaw(ref a) => a.shaken(),
+            &OrientatorKind::SensorProxy(ref s) => s.shaken(),
+        }
+    }
+
+    fn readings(&self) -> Vec<(&'static str, String)> {
+        match self {
+            &OrientatorKind::FsAccel(ref a) => a.readings(),
+            &OrientatorKind::FsAccelRaw(ref a) => a.readings(),
+            &OrientatorKind::IioAccel(ref a) => a.readings(),
+            &OrientatorKind::IioAccelRaw(ref a) => a.readings(),
+            &OrientatorKind::EvdevAccel(ref a) => a.readings(),
+            &OrientatorKind::EvdevAccelRaw(ref a) => a.readings(),
+            &OrientatorKind::SensorProxy(ref s) => s.readings(),
+        }
+    }
+
 }
 
 macro_rules! orinit {
-    ( $tomatch:ident, $opts:ident: $( $name:expr, $init:ident $(, $mult:expr)* );+ $(;)* ) => {
+    ( $tomatch:ident, $opts:ident: $( $name:expr, $init:ident $(, $hyst:expr)* );+ $(;)* ) => {
         match $tomatch.as_str() {
             $(
                 $name => {
                     if ! $opts.contains_key($name) {
                         $opts.insert($name.to_owned(), HashMap::new());
                     }
-                    $init($opts.get_mut($name).unwrap() $(, $mult)*)
+                    $init($opts.get_mut($name).unwrap() $(, $hyst)*)
                 }),*,
                 _     => Err(BackendError::NoSuchBackend($tomatch)),
         }
     }
 }
-/// Initialize an orientator
-pub fn init_orientator(mult: f64) -> Result<OrientatorKind,i32> {
-    let (backends, mut opts) = get_backend_options();
-    for backend in backends {
-        let last_output =  orinit!(backend, opts:
-                // "iioaccel", init_iioaccel, Some(mult);
-                // "iioaccel_raw", init_iioaccel, None;
-                // "camaccel", init_camaccel;
-                "fsaccel_raw", init_fsaccel, None;
-                "fsaccel", init_fsaccel, Some(mult);
-                );
-        match last_output {
-            Ok(o)   => return Ok(o),
-            Err(e)  => warn!("Error initializing backend: {}", e),
+/// Initialize a single backend.
+fn init_backend(backend: &str, opts: HashMap<String, String>, hysteresis: Duration) -> BackendResult {
+    let backend = backend.to_owned();
+    let mut optmap = HashMap::new();
+    optmap.insert(backend.to_owned(), opts);
+    orinit!(backend, optmap:
+            "iioaccel_raw", init_iioaccel, None;
+            "iioaccel", init_iioaccel, Some(hysteresis);
+            "evdevaccel_raw", init_evdevaccel, None;
+            "evdevaccel", init_evdevaccel, Some(hysteresis);
+            "sensorproxy", init_sensorproxy;
+            // "camaccel", init_camaccel;
+            "fsaccel_raw", init_fsaccel, None;
+            "fsaccel", init_fsaccel, Some(hysteresis);
+            )
+}
+
+/// Starts a backend, given its name, options and the filter's time constant.
+type InitFn<O> = fn(&str, HashMap<String, String>, Duration) -> Result<O, BackendError>;
+
+/// Every backend that can be used, and the one in use.
+///
+/// If the backend in use stops working, the next one that works is used
+/// instead; every so often, the ones before it are tried again.
+pub struct Backends<O = OrientatorKind> {
+    /// The backends and their options, most preferred first
+    specs: Vec<(String, HashMap<String, String>)>,
+    /// The low-pass filter's time constant
+    hysteresis: Duration,
+    /// The backend in use, and where it is in `specs`
+    active: Option<(usize, O)>,
+    /// How long to wait between trying more preferred backends
+    retry: Duration,
+    /// When more preferred backends were last tried
+    last_try: Instant,
+    /// Starts a backend, given its name and options
+    init: InitFn<O>,
+}
+
+impl<O: Orientator> Backends<O> {
+    /// Start the first of the backends before `end` that works, logging
+    /// failures at `level`.
+    fn start(&self, end: usize, level: log::Level) -> Option<(usize, O)> {
+        for (i, (backend, opts)) in self.specs[..end].iter().enumerate() {
+            // Options are cloned so anything autodetected last time gets
+            // detected again (e.g. if the device was renumbered).
+            match (self.init)(backend, opts.clone(), self.hysteresis) {
+                Ok(o)   => return Some((i, o)),
+                Err(e)  => log!(level, "Error initializing backend: {}", e),
+            }
+        }
+        None
+    }
+
+    /// Describe the backend at `i` in `specs`.
+    fn name(&self, i: usize) -> String {
+        format!("{} (#{})", self.specs[i].0, i + 1)
+    }
+
+    /// Whether any backend is working.
+    pub fn is_running(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Describe the backend in use.
+    pub fn active_name(&self) -> String {
+        self.active.as_ref().map_or("none".to_owned(), |a| self.name(a.0))
+    }
+
+    /// When more preferred backends will next be tried, if there are any.
+    pub fn next_retry(&self) -> Option<Instant> {
+        match self.active {
+            Some((0, _))    => None,
+            _   => Some(self.last_try + self.retry),
+        }
+    }
+}
+
+impl<O: Orientator> Orientator for Backends<O> {
+    fn orientation(&mut self) -> OrientationResult {
+        let preferred = self.active.as_ref().map_or(self.specs.len(), |a| a.0);
+        if 0 < preferred && self.last_try.elapsed() >= self.retry {
+            self.last_try = Instant::now();
+            if let Some(a) = self.start(preferred, log::Level::Debug) {
+                info!("Switching from backend {} to {}", self.active_name(), self.name(a.0));
+                self.active = Some(a);
+            }
+        }
+        let result = match self.active {
+            Some((_, ref mut o))    => o.orientation(),
+            None    => return Ok(None),
+        };
+        if let Err(e) = result {
+            error!("Backend {} stopped working ({}); trying the others", self.active_name(), e);
+            // The broken one has to be closed before it can be reopened.
+            self.active = None;
+            self.last_try = Instant::now();
+            self.active = self.start(self.specs.len(), log::Level::Warn);
+            if self.active.is_none() {
+                error!("No backend is working; will retry every {}ms", self.retry.as_millis());
+                return Ok(None);
+            }
+            info!("Switched to backend {}", self.active_name());
+            // Read it straight away, since ones that only say something
+            // when there's a new reading might not for a while.
+            return self.active.as_mut().unwrap().1.orientation();
         }
+        result
     }
-    return Err(ERR_NO_ORIENTATOR);
+
+    fn event_fd(&self) -> Option<RawFd> {
+        self.active.as_ref().and_then(|a| a.1.event_fd())
+    }
+
+    fn motion(&self) -> Option<f64> {
+        self.active.as_ref().and_then(|a| a.1.motion())
+    }
+
+    fn posture(&self) -> Option<Posture> {
+        self.active.as_ref().and_then(|a| a.1.posture())
+    }
+
+    fn shaken(&self) -> bool {
+        self.active.as_ref().is_some_and(|a| a.1.shaken())
+    }
+
+    fn readings(&self) -> Vec<(&'static str, String)> {
+        self.active.as_ref().map_or(Vec::new(), |a| a.1.readings())
+    }
+}
+
+/// Initialize the backends, starting the first that works.
+pub fn init_orientator(hysteresis: Duration) -> Result<Backends,i32> {
+    let retry = get_u32_arg_val("backend_retry").unwrap_or(DEFAULT_BACKEND_RETRY);
+    let mut backends = Backends {
+        specs: get_backend_options(),
+        hysteresis,
+        active: None,
+        retry: Duration::from_millis(retry as u64),
+        last_try: Instant::now(),
+        init: init_backend,
+    };
+    backends.active = backends.start(backends.specs.len(), log::Level::Warn);
+    if backends.active.is_none() {
+        return Err(ERR_NO_ORIENTATOR);
+    }
+    info!("Using backend {}", backends.active_name());
+    Ok(backends)
 }
 
 
@@ -123,8 +468,21 @@ enum BackendError {
     NotCompiled(&'static str),
     /// Backend does not exist
     NoSuchBackend(String),
-    /// Couldn't find/open filesystem accelerometer files
-    FsAccel(std::io::Error),
+    /// Couldn't find/open/parse filesystem accelerometer files
+    #[cfg(feature = "fsaccel")]
+    FsAccel(accel::fsaccel::FsAccelError),
+    /// Couldn't set up the IIO buffer
+    #[cfg(feature = "iioaccel")]
+    IioAccel(std::io::Error),
+    /// Couldn't find/open the input event device
+    #[cfg(feature = "evdevaccel")]
+    EvdevAccel(std::io::Error),
+    /// Couldn't claim iio-sensor-proxy's accelerometer
+    #[cfg(feature = "sensorproxy")]
+    SensorProxy(sensorproxy::SensorProxyError),
+    /// The filter option couldn't be parsed
+    #[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+    Filter(FilterError),
 }
 
 impl Display for BackendError {
@@ -137,9 +495,26 @@ impl Display for BackendError {
             &NoSuchBackend(ref b)   => {
                 write!(fmt, "backend '{}' does not exist!", b)
             },
+            #[cfg(feature = "fsaccel")]
             &FsAccel(ref e) => {
                 write!(fmt, "fsaccel init error: {}", e)
             },
+            #[cfg(feature = "iioaccel")]
+            &IioAccel(ref e) => {
+                write!(fmt, "iioaccel init error: {}", e)
+            },
+            #[cfg(feature = "evdevaccel")]
+            &EvdevAccel(ref e) => {
+                write!(fmt, "evdevaccel init error: {}", e)
+            },
+            #[cfg(feature = "sensorproxy")]
+            &SensorProxy(ref e) => {
+                write!(fmt, "sensorproxy init error: {}", e)
+            },
+            #[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+            &Filter(ref e) => {
+                write!(fmt, "bad {} option: {}", FILTER_OPT, e)
+            },
         }
     }
 }
@@ -154,28 +529,258 @@ impl std::error::Error for BackendError {
         match self {
             &BackendError::NotCompiled(_)   => None,
             &BackendError::NoSuchBackend(_) => None,
+            #[cfg(feature = "fsaccel")]
             &BackendError::FsAccel(ref e) => Some(e),
+            #[cfg(feature = "iioaccel")]
+            &BackendError::IioAccel(ref e) => Some(e),
+            #[cfg(feature = "evdevaccel")]
+            &BackendError::EvdevAccel(ref e) => Some(e),
+            #[cfg(feature = "sensorproxy")]
+            &BackendError::SensorProxy(ref e) => Some(e),
+            #[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+            &BackendError::Filter(ref e) => Some(e),
         }
     }
 }
 
 type BackendResult = Result<OrientatorKind, BackendError>;
 
+#[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
+/// The filters to use on an accelerometer: the ones in its `filter` option,
+/// or an exponential moving average over `hysteresis`.
+fn filter_chain(opts: &HashMap<String, String>, hysteresis: Duration) -> Result<FilterChain, BackendError> {
+    match opts.get(FILTER_OPT) {
+        Some(f) => f.parse().map_err(BackendError::Filter),
+        None    => Ok(FilterChain::ema(hysteresis)),
+    }
+}
+
 #[cfg(not(feature = "fsaccel"))]
 /// Don't initiaze a non-compiled filesystem accelerometer
-fn init_fsaccel(_opts: HashMap<String, String>) -> BackendResult {
+fn init_fsaccel(_opts: &mut HashMap<String, String>, _hyst: Option<Duration>) -> BackendResult {
     return Err(BackendError::NotCompiled("fsaccel"));
 }
 #[cfg(feature = "fsaccel")]
 /// Initialize a filesystem accelerometer
-fn init_fsaccel(opts: &mut HashMap<String, String>, mult: Option<f64>) -> BackendResult {
-    match mult {
-        Some(m) => Ok(OrientatorKind::FsAccel(FilteredAccelerometer::new(
+fn init_fsaccel(opts: &mut HashMap<String, String>, hyst: Option<Duration>) -> BackendResult {
+    match hyst {
+        Some(h) => {
+            let filters = filter_chain(opts, h)?;
+            let accel = FilteredAccelerometer::new(
                     FsAccel::from_opts(opts).map_err(|e| BackendError::FsAccel(e))?,
-                    m
-                    ))),
-        None    => Ok(OrientatorKind::FsAccelRaw(FsAccel::from_opts(opts).map_err(|e| BackendError::FsAccel(e))?)),
+                    filters
+                    ).map_err(|e| BackendError::FsAccel(e.into()))?;
+            Ok(OrientatorKind::FsAccel(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::FsAccel(e.into()))?))
+        },
+        None    => {
+            let accel = FsAccel::from_opts(opts).map_err(|e| BackendError::FsAccel(e))?;
+            Ok(OrientatorKind::FsAccelRaw(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::FsAccel(e.into()))?))
+        },
+    }
+}
+
+#[cfg(not(feature = "iioaccel"))]
+/// Don't initiaze a non-compiled IIO buffer accelerometer
+fn init_iioaccel(_opts: &mut HashMap<String, String>, _hyst: Option<Duration>) -> BackendResult {
+    return Err(BackendError::NotCompiled("iioaccel"));
+}
+#[cfg(feature = "iioaccel")]
+/// Initialize an IIO buffer accelerometer
+fn init_iioaccel(opts: &mut HashMap<String, String>, hyst: Option<Duration>) -> BackendResult {
+    match hyst {
+        Some(h) => {
+            let filters = filter_chain(opts, h)?;
+            let accel = FilteredAccelerometer::new(
+                    IioAccel::from_opts(opts).map_err(|e| BackendError::IioAccel(e))?,
+                    filters
+                    ).map_err(|e| BackendError::IioAccel(e))?;
+            Ok(OrientatorKind::IioAccel(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::IioAccel(e))?))
+        },
+        None    => {
+            let accel = IioAccel::from_opts(opts).map_err(|e| BackendError::IioAccel(e))?;
+            Ok(OrientatorKind::IioAccelRaw(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::IioAccel(e))?))
+        },
     }
 }
 
+#[cfg(not(feature = "evdevaccel"))]
+/// Don't initiaze a non-compiled input event accelerometer
+fn init_evdevaccel(_opts: &mut HashMap<String, String>, _hyst: Option<Duration>) -> BackendResult {
+    return Err(BackendError::NotCompiled("evdevaccel"));
+}
+#[cfg(feature = "evdevaccel")]
+/// Initialize an input event accelerometer
+fn init_evdevaccel(opts: &mut HashMap<String, String>, hyst: Option<Duration>) -> BackendResult {
+    match hyst {
+        Some(h) => {
+            let filters = filter_chain(opts, h)?;
+            let accel = FilteredAccelerometer::new(
+                    EvdevAccel::from_opts(opts).map_err(|e| BackendError::EvdevAccel(e))?,
+                    filters
+                    ).map_err(|e| BackendError::EvdevAccel(e))?;
+            Ok(OrientatorKind::EvdevAccel(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::EvdevAccel(e))?))
+        },
+        None    => {
+            let accel = EvdevAccel::from_opts(opts).map_err(|e| BackendError::EvdevAccel(e))?;
+            Ok(OrientatorKind::EvdevAccelRaw(AccelOrientator::from_opts(accel, opts)
+                    .map_err(|e| BackendError::EvdevAccel(e))?))
+        },
+    }
+}
+
+#[cfg(not(feature = "sensorproxy"))]
+/// Don't initialize a non-compiled iio-sensor-proxy client
+fn init_sensorproxy(_opts: &mut HashMap<String, String>) -> BackendResult {
+    Err(BackendError::NotCompiled("sensorproxy"))
+}
+#[cfg(feature = "sensorproxy")]
+/// Initialize an iio-sensor-proxy client
+fn init_sensorproxy(opts: &mut HashMap<String, String>) -> BackendResult {
+    Ok(OrientatorKind::SensorProxy(
+            sensorproxy::SensorProxy::from_opts(opts).map_err(BackendError::SensorProxy)?))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
 
+    thread_local! {
+        /// The fake backends that don't work at the moment
+        static BROKEN: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    }
+
+    fn broken(name: &str) -> bool {
+        BROKEN.with(|b| b.borrow().contains(name))
+    }
+
+    fn set_broken(name: &str, is_broken: bool) {
+        BROKEN.with(|b| if is_broken {
+            b.borrow_mut().insert(name.to_owned());
+        } else {
+            b.borrow_mut().remove(name);
+        });
+    }
+
+    /// A backend that always reads its `rotation` option, until it's
+    /// broken.
+    struct FakeOrientator {
+        name: String,
+        rotation: Rotation,
+    }
+
+    impl Orientator for FakeOrientator {
+        fn orientation(&mut self) -> OrientationResult {
+            if broken(&self.name) {
+                return Err(IoError::other("broken"));
+            }
+            Ok(Some(self.rotation))
+        }
+    }
+
+    fn init_fake(name: &str, opts: HashMap<String, String>, _hyst: Duration)
+            -> Result<FakeOrientator, BackendError> {
+        if broken(name) {
+            return Err(BackendError::NoSuchBackend(name.to_owned()));
+        }
+        Ok(FakeOrientator {
+            name: name.to_owned(),
+            rotation: opts["rotation"].parse().unwrap(),
+        })
+    }
+
+    /// Start fake backends, as `(name, rotation)`, retrying every `retry`.
+    fn backends(specs: &[(&str, &str)], retry: Duration) -> Backends<FakeOrientator> {
+        let mut backends = Backends {
+            specs: specs.iter().map(|&(name, rotation)| {
+                let mut opts = HashMap::new();
+                opts.insert("rotation".to_owned(), rotation.to_owned());
+                (name.to_owned(), opts)
+            }).collect(),
+            hysteresis: Duration::from_millis(0),
+            active: None,
+            retry,
+            last_try: Instant::now(),
+            init: init_fake,
+        };
+        backends.active = backends.start(backends.specs.len(), log::Level::Warn);
+        backends
+    }
+
+    const SPECS: [(&str, &str); 3] = [("a", "normal"), ("b", "left"), ("c", "right")];
+
+    #[test]
+    fn starts_first_that_works() {
+        set_broken("a", true);
+        let mut b = backends(&SPECS, Duration::from_secs(3600));
+        assert_eq!("b (#2)", b.active_name());
+        assert_eq!(Some(Rotation::Left), b.orientation().unwrap());
+    }
+
+    #[test]
+    fn switches_when_one_stops_working() {
+        let mut b = backends(&SPECS, Duration::from_secs(3600));
+        assert_eq!("a (#1)", b.active_name());
+        assert_eq!(None, b.next_retry());
+        assert_eq!(Some(Rotation::Normal), b.orientation().unwrap());
+
+        // The next one is read straight away.
+        set_broken("a", true);
+        assert_eq!(Some(Rotation::Left), b.orientation().unwrap());
+        assert_eq!("b (#2)", b.active_name());
+
+        // Broken ones are skipped over.
+        set_broken("b", true);
+        assert_eq!(Some(Rotation::Right), b.orientation().unwrap());
+        assert_eq!("c (#3)", b.active_name());
+        assert!(b.next_retry().is_some());
+    }
+
+    #[test]
+    fn waits_for_backend_retry() {
+        set_broken("a", true);
+        let mut b = backends(&SPECS, Duration::from_secs(3600));
+        set_broken("a", false);
+        // Not time yet.
+        assert_eq!(Some(Rotation::Left), b.orientation().unwrap());
+        assert_eq!("b (#2)", b.active_name());
+    }
+
+    #[test]
+    fn retries_preferred_backends() {
+        set_broken("a", true);
+        let mut b = backends(&SPECS, Duration::from_millis(0));
+        assert_eq!("b (#2)", b.active_name());
+        // Still broken, so it stays put.
+        assert_eq!(Some(Rotation::Left), b.orientation().unwrap());
+        assert_eq!("b (#2)", b.active_name());
+
+        set_broken("a", false);
+        assert_eq!(Some(Rotation::Normal), b.orientation().unwrap());
+        assert_eq!("a (#1)", b.active_name());
+        assert_eq!(None, b.next_retry());
+    }
+
+    #[test]
+    fn recovers_when_none_work() {
+        for &(name, _) in SPECS.iter() {
+            set_broken(name, true);
+        }
+        let mut b = backends(&SPECS, Duration::from_millis(0));
+        assert!(!b.is_running());
+        assert_eq!("none", b.active_name());
+        assert_eq!(None, b.orientation().unwrap());
+
+        set_broken("c", false);
+        assert_eq!(Some(Rotation::Right), b.orientation().unwrap());
+        assert!(b.is_running());
+        assert_eq!("c (#3)", b.active_name());
+    }
+}
@@ -0,0 +1,373 @@
+//! # sensorproxy
+//! A backend that gets the orientation from iio-sensor-proxy over D-Bus,
+//! instead of reading the accelerometer itself.
+
+use super::*;
+
+use std::cell::RefCell;
+use std::io::ErrorKind as IoErrorKind;
+use std::rc::Rc;
+
+use dbus::arg::RefArg;
+use dbus::blocking::LocalConnection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+use dbus::channel::{BusType, Channel};
+use dbus::message::{MatchRule, SignalArgs};
+
+/// The bus name iio-sensor-proxy owns
+pub const DEFAULT_SENSORPROXY_NAME: &str = "net.hadess.SensorProxy";
+
+/// How long to wait for iio-sensor-proxy to answer (in ms)
+pub const DEFAULT_SENSORPROXY_TIMEOUT: u64 = 1000;
+
+/// The interface and object iio-sensor-proxy exports
+const INTERFACE: &str = "net.hadess.SensorProxy";
+const OBJECT_PATH: &str = "/net/hadess/SensorProxy";
+
+#[derive(Debug)]
+pub enum SensorProxyError {
+    /// An option had a value that couldn't be used
+    BadOption(&'static str, String),
+    /// Couldn't connect to the bus
+    Connect(dbus::Error),
+    /// Nothing owns the bus name
+    NotRunning(String, dbus::Error),
+    /// iio-sensor-proxy didn't find an accelerometer
+    NoAccelerometer,
+    /// A call to iio-sensor-proxy failed
+    Call(&'static str, dbus::Error),
+}
+
+impl Display for SensorProxyError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            SensorProxyError::BadOption(o, v)   => {
+                write!(fmt, "bad value '{}' for option {}", v, o)
+            },
+            SensorProxyError::Connect(e)    => write!(fmt, "can't connect to D-Bus ({})", e),
+            SensorProxyError::NotRunning(n, e)  => {
+                write!(fmt, "nothing owns {} (is iio-sensor-proxy running?) ({})", n, e)
+            },
+            SensorProxyError::NoAccelerometer   => {
+                write!(fmt, "iio-sensor-proxy doesn't have an accelerometer")
+            },
+            SensorProxyError::Call(m, e)    => write!(fmt, "{} failed ({})", m, e),
+        }
+    }
+}
+
+impl std::error::Error for SensorProxyError {
+    fn description(&self) -> &str {
+        "couldn't use iio-sensor-proxy"
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SensorProxyError::Connect(e)    => Some(e),
+            SensorProxyError::NotRunning(_, e)  => Some(e),
+            SensorProxyError::Call(_, e)    => Some(e),
+            _   => None,
+        }
+    }
+}
+
+/// What's been heard from iio-sensor-proxy
+struct Heard {
+    /// The last orientation it reported
+    orientation: Option<Rotation>,
+    /// Whether it has gone away (taking our claim with it)
+    gone: bool,
+}
+
+/// Gets the orientation from iio-sensor-proxy.
+///
+/// The accelerometer is claimed for as long as this exists. If
+/// iio-sensor-proxy goes away, reading fails, so the backend gets started
+/// (and the accelerometer claimed) again.
+pub struct SensorProxy {
+    conn: LocalConnection,
+    /// iio-sensor-proxy's unique bus name
+    owner: String,
+    timeout: Duration,
+    heard: Rc<RefCell<Heard>>,
+}
+
+impl SensorProxy {
+    pub fn from_opts(opts: &HashMap<String, String>) -> Result<SensorProxy, SensorProxyError> {
+        let mut channel = Channel::get_private(match opts.get("bus").map(|b| b.as_str()) {
+            None | Some("system")   => BusType::System,
+            Some("session") => BusType::Session,
+            Some(b) => return Err(SensorProxyError::BadOption("bus", b.to_owned())),
+        }).map_err(SensorProxyError::Connect)?;
+        // So there's a file descriptor to wait on for changes
+        channel.set_watch_enabled(true);
+        let conn = LocalConnection::from(channel);
+        let timeout = match opts.get("timeout") {
+            Some(t) => t.parse::<u64>()
+                .map_err(|_| SensorProxyError::BadOption("timeout", t.to_owned()))?,
+            None    => DEFAULT_SENSORPROXY_TIMEOUT,
+        };
+        let timeout = Duration::from_millis(timeout);
+        let name = opts.get("name").map_or(DEFAULT_SENSORPROXY_NAME, |n| n.as_str());
+
+        // Signals come from the unique name, so that's what gets watched.
+        let (owner,): (String,) = conn
+            .with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", timeout)
+            .method_call("org.freedesktop.DBus", "GetNameOwner", (name,))
+            .map_err(|e| SensorProxyError::NotRunning(name.to_owned(), e))?;
+
+        let heard = Rc::new(RefCell::new(Heard { orientation: None, gone: false }));
+        let h = heard.clone();
+        let rule = PropertiesPropertiesChanged::match_rule(
+            Some(&owner.clone().into()), Some(&OBJECT_PATH.into())).static_clone();
+        conn.add_match(rule, move |p: PropertiesPropertiesChanged, _, _| {
+            if INTERFACE == p.interface_name {
+                if let Some(o) = p.changed_properties.get("AccelerometerOrientation") {
+                    h.borrow_mut().orientation = o.as_str().and_then(parse_orientation);
+                }
+            }
+            true
+        }).map_err(|e| SensorProxyError::Call("watching AccelerometerOrientation", e))?;
+        let h = heard.clone();
+        let o = owner.clone();
+        let rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+            .with_sender("org.freedesktop.DBus");
+        conn.add_match(rule, move |(_, old, _): (String, String, String), _, _| {
+            if old == o {
+                h.borrow_mut().gone = true;
+            }
+            true
+        }).map_err(|e| SensorProxyError::Call("watching iio-sensor-proxy", e))?;
+
+        let sp = SensorProxy { conn, owner, timeout, heard };
+        {
+            let proxy = sp.proxy();
+            let has: bool = proxy.get(INTERFACE, "HasAccelerometer")
+                .map_err(|e| SensorProxyError::Call("HasAccelerometer", e))?;
+            if ! has {
+                return Err(SensorProxyError::NoAccelerometer);
+            }
+            proxy.method_call::<(), _, _, _>(INTERFACE, "ClaimAccelerometer", ())
+                .map_err(|e| SensorProxyError::Call("ClaimAccelerometer", e))?;
+            // Only reported properly once it's been claimed
+            let orientation: String = proxy.get(INTERFACE, "AccelerometerOrientation")
+                .map_err(|e| SensorProxyError::Call("AccelerometerOrientation", e))?;
+            sp.heard.borrow_mut().orientation = parse_orientation(&orientation);
+        }
+        info!("Claimed iio-sensor-proxy's accelerometer ({})", sp.owner);
+        Ok(sp)
+    }
+
+    fn proxy(&self) -> dbus::blocking::Proxy<'_, &LocalConnection> {
+        self.conn.with_proxy(self.owner.as_str(), OBJECT_PATH, self.timeout)
+    }
+}
+
+impl Orientator for SensorProxy {
+    fn orientation(&mut self) -> OrientationResult {
+        loop {
+            match self.conn.process(Duration::from_millis(0)) {
+                Ok(true)    => continue,
+                Ok(false)   => break,
+                Err(e)  => return Err(IoError::new(IoErrorKind::NotConnected, e)),
+            }
+        }
+        let heard = self.heard.borrow();
+        if heard.gone {
+            return Err(IoError::new(IoErrorKind::NotConnected, "iio-sensor-proxy went away"));
+        }
+        Ok(heard.orientation)
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        Some(self.conn.channel().watch().fd)
+    }
+}
+
+impl Drop for SensorProxy {
+    fn drop(&mut self) {
+        if self.heard.borrow().gone {
+            return;
+        }
+        if let Err(e) = self.proxy()
+            .method_call::<(), _, _, _>(INTERFACE, "ReleaseAccelerometer", ()) {
+            debug!("Couldn't release iio-sensor-proxy's accelerometer ({})", e);
+        }
+    }
+}
+
+/// The rotation matching an orientation iio-sensor-proxy reports (which is
+/// the edge of the device that's pointing up).
+///
+/// `undefined` (or anything unexpected) is `None`.
+fn parse_orientation(orientation: &str) -> Option<Rotation> {
+    match orientation {
+        "normal"    => Some(Rotation::Normal),
+        "left-up"   => Some(Rotation::Left),
+        "right-up"  => Some(Rotation::Right),
+        "bottom-up" => Some(Rotation::Inverted),
+        _   => None,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use dbus::arg::{PropMap, Variant};
+    use dbus::message::MessageType;
+    use testbus::session_bus;
+
+    /// What the mock iio-sensor-proxy should do next
+    enum Command {
+        /// Report a new orientation
+        Orient(&'static str),
+        /// Leave the bus
+        Quit,
+    }
+
+    /// A pretend iio-sensor-proxy, with just enough of the interface for
+    /// the backend.
+    struct MockProxy {
+        commands: mpsc::Sender<Command>,
+        /// The accelerometer methods that have been called, in order
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockProxy {
+        fn start(name: &'static str, orientation: &'static str) -> MockProxy {
+            let (commands, rx) = mpsc::channel();
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let c = calls.clone();
+            let (ready_tx, ready_rx) = mpsc::channel();
+            thread::spawn(move || {
+                let conn = LocalConnection::new_session().unwrap();
+                conn.request_name(name, false, false, false).unwrap();
+                ready_tx.send(()).unwrap();
+                let mut orientation = orientation;
+                loop {
+                    let channel = conn.channel();
+                    channel.read_write(Some(Duration::from_millis(5))).unwrap();
+                    while let Some(msg) = channel.pop_message() {
+                        if MessageType::MethodCall != msg.msg_type() { continue }
+                        let member = msg.member().unwrap().to_string();
+                        let reply = match member.as_str() {
+                            "Get"   => match msg.read2::<&str, &str>().unwrap().1 {
+                                "HasAccelerometer"  => msg.method_return().append1(Variant(true)),
+                                "AccelerometerOrientation"  => {
+                                    msg.method_return().append1(Variant(orientation))
+                                },
+                                p   => panic!("unexpected property {}", p),
+                            },
+                            _   => {
+                                c.lock().unwrap().push(member);
+                                msg.method_return()
+                            },
+                        };
+                        channel.send(reply).unwrap();
+                    }
+                    match rx.try_recv() {
+                        Ok(Command::Orient(o))  => {
+                            orientation = o;
+                            let mut changed = PropMap::new();
+                            changed.insert("AccelerometerOrientation".to_owned(),
+                                           Variant(Box::new(o.to_owned()) as Box<dyn RefArg>));
+                            let signal = PropertiesPropertiesChanged {
+                                interface_name: INTERFACE.to_owned(),
+                                changed_properties: changed,
+                                invalidated_properties: Vec::new(),
+                            };
+                            channel.send(signal.to_emit_message(&OBJECT_PATH.into())).unwrap();
+                        },
+                        Ok(Command::Quit) | Err(mpsc::TryRecvError::Disconnected)  => break,
+                        Err(mpsc::TryRecvError::Empty)  => {},
+                    }
+                }
+            });
+            ready_rx.recv().unwrap();
+            MockProxy { commands, calls }
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    fn connect(name: &str) -> SensorProxy {
+        let mut opts = HashMap::new();
+        opts.insert("bus".to_owned(), "session".to_owned());
+        opts.insert("name".to_owned(), name.to_owned());
+        SensorProxy::from_opts(&opts).unwrap()
+    }
+
+    /// Keep reading the orientation until `done` is happy with it (or it's
+    /// taking too long), and return the last one.
+    fn wait_for<F: Fn(&OrientationResult) -> bool>(sp: &mut SensorProxy, done: F) -> OrientationResult {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let result = sp.orientation();
+            if done(&result) || Instant::now() > deadline {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn follows_orientation() {
+        if !session_bus() { return }
+        const NAME: &str = "net.hadess.SensorProxy.OrientationTest";
+        let mock = MockProxy::start(NAME, "left-up");
+        let mut sp = connect(NAME);
+        assert_eq!(vec!["ClaimAccelerometer"], mock.calls());
+        assert_eq!(Some(Rotation::Left), sp.orientation().unwrap());
+
+        let reported = [("normal", Some(Rotation::Normal)), ("bottom-up", Some(Rotation::Inverted)),
+                        ("right-up", Some(Rotation::Right)), ("left-up", Some(Rotation::Left)),
+                        ("undefined", None), ("sideways", None)];
+        for &(orientation, rotation) in &reported {
+            // Go somewhere else first, so each change is seen.
+            mock.commands.send(Command::Orient("normal")).unwrap();
+            mock.commands.send(Command::Orient(orientation)).unwrap();
+            let got = wait_for(&mut sp, |r| r.as_ref().ok() == Some(&rotation));
+            assert_eq!(rotation, got.unwrap(), "for {}", orientation);
+        }
+
+        drop(sp);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while mock.calls().len() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(vec!["ClaimAccelerometer", "ReleaseAccelerometer"], mock.calls());
+    }
+
+    #[test]
+    fn fails_when_proxy_leaves() {
+        if !session_bus() { return }
+        const NAME: &str = "net.hadess.SensorProxy.LeavingTest";
+        let mock = MockProxy::start(NAME, "normal");
+        let mut sp = connect(NAME);
+        assert_eq!(Some(Rotation::Normal), sp.orientation().unwrap());
+        mock.commands.send(Command::Quit).unwrap();
+        let err = wait_for(&mut sp, |r| r.is_err()).unwrap_err();
+        assert_eq!(IoErrorKind::NotConnected, err.kind());
+    }
+
+    #[test]
+    fn needs_proxy_running() {
+        if !session_bus() { return }
+        let mut opts = HashMap::new();
+        opts.insert("bus".to_owned(), "session".to_owned());
+        opts.insert("name".to_owned(), "net.hadess.SensorProxy.NobodyHome".to_owned());
+        match SensorProxy::from_opts(&opts) {
+            Err(SensorProxyError::NotRunning(..))   => {},
+            Err(e)  => panic!("wrong error: {}", e),
+            Ok(_)   => panic!("found a proxy that isn't there"),
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! # spinnr
+//! spinnr, the spinnr client, watches the spinfile spinnrd writes and
+//! rotates X outputs and touchscreens to match. It replaces `spinnr.sh`.
+
+#[macro_use] extern crate lazy_static;
+extern crate simplelog;
+extern crate chrono;
+extern crate syslog;
+extern crate regex;
+extern crate clap;
+extern crate libc;
+#[macro_use] extern crate log;
+extern crate x11rb;
+
+#[cfg(feature = "sysd")]
+extern crate systemd;
+
+
+macro_rules! qprintln {
+    ( $($args:tt)* ) => {
+        if ! *IS_QUIET { println!($($args)*); }
+    };
+}
+macro_rules! qprinterr {
+    ( $($args:tt)*) => {
+        if ! *IS_QUIET { eprintln!($($args)*); }
+    };
+}
+
+
+#[path = "../logging.rs"]
+mod logging;
+#[path = "../paths.rs"]
+mod paths;
+#[path = "../rotation.rs"]
+#[allow(dead_code)] // spinnr only needs Rotation
+mod rotation;
+#[path = "../frontend/x11.rs"]
+mod x11;
+#[allow(dead_code)]
+mod metadata {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+
+use logging::*;
+use paths::*;
+use rotation::*;
+use x11::XSender;
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{File,OpenOptions};
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path,PathBuf};
+use std::fmt::{Display, Formatter};
+use std::fmt::Result as FmtResult;
+
+use clap::{Arg,ArgMatches};
+
+/// The default logging level
+#[cfg(debug_assertions)]
+const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Debug;
+#[cfg(not(debug_assertions))]
+const DEFAULT_LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+/// The default logfile
+const DEFAULT_LOG_FILE: &str = "%x/spinnr.log";
+
+/// The backup logfile
+const BACKUP_LOG_FILE: &str = "/tmp/spinnr.log";
+
+/// The file to (try) to write the logging fail message to
+const LOG_FAIL_FILE: &str = "/tmp/spinnr.%t.logfail";
+
+/// Error indicating the X display couldn't be used
+const ERR_NO_DISPLAY: i32 = 3;
+
+/// Error indicating the spinfile couldn't be watched
+const ERR_NO_WATCH: i32 = 4;
+
+lazy_static!{
+    static ref VERSION: String = format!("{} ({})", metadata::PKG_VERSION, metadata::FEATURES_STR);
+
+    /// The command line arguments
+    static ref CLI_ARGS: ArgMatches<'static> = clap::App::new("spinnr")
+        .version((*VERSION).as_str())
+        .author("James Wescott <james@wescottdesign.com>")
+        .about("Rotates X outputs and touchscreens to match spinnrd's spinfile")
+        .arg(Arg::with_name("quiet")
+             .short("q")
+             .long("quiet")
+             .help("Turns off printing to stdout")
+            )
+        .arg(Arg::with_name("spinfile")
+             .long("spin-file")
+             .short("f")
+             .value_name("SPINFILE")
+             .default_value(DEFAULT_SPINFILE)
+             .help("Location of the spinfile spinnrd writes the orientation to. Uses filename formatting.")
+             )
+        .arg(Arg::with_name("workingdir")
+             .long("working-directory")
+             .number_of_values(1)
+             .value_name("WORKING_DIR")
+             .default_value(DEFAULT_WORKING_DIRECTORY)
+             .help("spinnrd's working directory (what %d expands to).")
+             )
+        .arg(Arg::with_name("display")
+             .long("display")
+             .short("d")
+             .value_name("DISPLAY")
+             .multiple(true)
+             .number_of_values(1)
+             .help("Add an X display to rotate. [Defaults to $DISPLAY]")
+             )
+        .arg(Arg::with_name("output")
+             .long("output")
+             .short("o")
+             .value_name("OUTPUT")
+             .multiple(true)
+             .number_of_values(1)
+             .help("Add an output to rotate. [Defaults to the primary output]")
+             )
+        .arg(Arg::with_name("touchscreen")
+             .long("touchscreen")
+             .short("t")
+             .value_name("TOUCHSCREEN")
+             .multiple(true)
+             .number_of_values(1)
+             .help("Add a touchscreen to rotate. [Defaults to every direct touch device]")
+             .long_help("Any pointer whose name contains TOUCHSCREEN (ignoring case) is rotated.")
+             )
+        .arg(Arg::with_name("no_touchscreen")
+             .long("no-touchscreens")
+             .short("T")
+             .conflicts_with("touchscreen")
+             .help("Don't rotate any touchscreens.")
+             )
+        .arg(Arg::with_name("logfile")
+             .long("log-file")
+             .number_of_values(1)
+             .value_name("LOGFILE")
+             .default_value(DEFAULT_LOG_FILE)
+             .help("Location of file to log to. 'systemd', 'system', and 'syslog' log to system log. Uses filename formatting.")
+             .long_help("'systemd' will log to systemd journal. 'system' will log to the systemd journal if available, or the system log otherwise. 'syslog' will log to the system log.")
+             )
+        .arg(Arg::with_name("loglvl")
+             .long("log-level")
+             .value_name("LOG_LEVEL")
+             .possible_values(&["trace", "debug", "info", "warn", "error"])
+             .help("Set the verbosity of the logging.")
+             .long_help("Values are listed in order of decreasing verbosity")
+             )
+        .after_help("Filenames are formatted the same way as spinnrd's (see spinnrd --help).")
+        .get_matches();
+
+    static ref IS_QUIET: bool = CLI_ARGS.is_present("quiet");
+
+    /// spinnrd's working directory, for `%d`
+    static ref WORKING_DIR: PathBuf = PathBuf::from(
+        parse_path(CLI_ARGS.value_of("workingdir").unwrap_or(DEFAULT_WORKING_DIRECTORY), true));
+}
+
+/// Get the value of an option.
+fn arg_value(name: &str) -> Option<String> {
+    CLI_ARGS.value_of(name).map(|s| s.to_owned())
+}
+
+/// spinnr runs as part of an X session, so it never daemonizes.
+fn is_daemon() -> bool {
+    false
+}
+
+fn main() {
+    ::std::process::exit(mainprog());
+}
+
+/// The actual main body of the program
+fn mainprog() -> i32 {
+    match init_logger() {
+        Ok(l)   => {
+            qprintln!("Logging initialized to {}", l);
+            debug!("Logging initialized to {}", l);
+        },
+        Err(e)  => {
+            qprinterr!("{}", e);
+            log_logging_failure(e);
+            return 2i32
+        }
+    }
+
+    let spinfile = PathBuf::from(parse_path(
+            CLI_ARGS.value_of("spinfile").unwrap_or(DEFAULT_SPINFILE), false));
+
+    let mut sender = match XSender::init(&x11_options()) {
+        Ok(s)   => s,
+        Err(e)  => {
+            error!("Couldn't start rotating X: {}", e);
+            qprinterr!("Couldn't start rotating X: {}", e);
+            return ERR_NO_DISPLAY;
+        },
+    };
+
+    let mut watcher = match SpinfileWatcher::new(&spinfile) {
+        Ok(w)   => w,
+        Err(e)  => {
+            error!("Couldn't watch {}: {}", spinfile.to_string_lossy(), e);
+            qprinterr!("Couldn't watch {}: {}", spinfile.to_string_lossy(), e);
+            return ERR_NO_WATCH;
+        },
+    };
+    info!("Watching {} to rotate {}", spinfile.to_string_lossy(), sender);
+
+    // Catch up with whatever spinnrd wrote before we started.
+    if spinfile.exists() {
+        apply_spinfile(&spinfile, &mut sender);
+    }
+    loop {
+        if let Err(e) = watcher.wait() {
+            error!("Can't watch {} any more: {}", spinfile.to_string_lossy(), e);
+            return ERR_NO_WATCH;
+        }
+        apply_spinfile(&spinfile, &mut sender);
+    }
+}
+
+/// The x11 frontend options matching the command line.
+fn x11_options() -> HashMap<String, String> {
+    let mut opts = HashMap::new();
+    for name in &["display", "output", "touchscreen"] {
+        if let Some(values) = CLI_ARGS.values_of(name) {
+            opts.insert(name.to_string(), values.collect::<Vec<_>>().join(","));
+        }
+    }
+    if CLI_ARGS.is_present("no_touchscreen") {
+        opts.insert("touchscreen".to_owned(), String::new());
+    }
+    opts
+}
+
+/// Rotate to the rotation in the spinfile.
+fn apply_spinfile(spinfile: &Path, sender: &mut XSender) {
+    let contents = match std::fs::read_to_string(spinfile) {
+        Ok(s)   => s,
+        Err(e)  => {
+            warn!("Couldn't read {} ({})", spinfile.to_string_lossy(), e);
+            return;
+        },
+    };
+    match contents.parse::<Rotation>() {
+        Ok(rotation)    => {
+            info!("Rotating to {}", rotation);
+            if let Err(e) = sender.rotate(rotation) {
+                error!("Couldn't rotate to {}: {}", rotation, e);
+            }
+        },
+        Err(e)  => warn!("Ignoring '{}' in {} ({})",
+                         contents.trim(), spinfile.to_string_lossy(), e),
+    }
+}
+
+/// Watches for spinnrd writing the spinfile, with inotify.
+///
+/// This watches the spinfile's directory rather than the file itself, so
+/// it doesn't matter if the file doesn't exist yet or gets replaced.
+struct SpinfileWatcher {
+    fd: RawFd,
+    name: OsString,
+}
+
+impl SpinfileWatcher {
+    fn new(spinfile: &Path) -> Result<SpinfileWatcher, IoError> {
+        let name = match spinfile.file_name() {
+            Some(n) => n.to_owned(),
+            None    => return Err(IoError::new(IoErrorKind::InvalidInput, "not a file")),
+        };
+        let dir = match spinfile.parent() {
+            Some(d) if d != Path::new("") => d,
+            _   => Path::new("."),
+        };
+        let cdir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| IoError::new(IoErrorKind::InvalidInput, e))?;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        let watcher = SpinfileWatcher { fd, name };
+        // FileSender truncates and rewrites the file, but something else
+        // might write a new one and move it into place.
+        let wd = unsafe {
+            libc::inotify_add_watch(fd, cdir.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO)
+        };
+        if wd < 0 {
+            return Err(IoError::last_os_error());
+        }
+        Ok(watcher)
+    }
+
+    /// Block until the spinfile has been written.
+    fn wait(&mut self) -> Result<(), IoError> {
+        // Enough for plenty of events with maximum-length names
+        let mut buf = [0u8; 16 * (16 + 256)];
+        loop {
+            let len = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if len < 0 {
+                let e = IoError::last_os_error();
+                if IoErrorKind::Interrupted == e.kind() {
+                    continue;
+                }
+                return Err(e);
+            }
+            let len = len as usize;
+            let mut written = false;
+            let mut offset = 0;
+            while offset + size_of::<libc::inotify_event>() <= len {
+                let event = unsafe {
+                    std::ptr::read_unaligned(
+                        buf[offset..].as_ptr() as *const libc::inotify_event)
+                };
+                let start = offset + size_of::<libc::inotify_event>();
+                let end = (start + event.len as usize).min(len);
+                // The name is padded with NULs
+                let name = buf[start..end].split(|&b| 0 == b).next().unwrap_or(&[]);
+                if OsStr::from_bytes(name) == self.name {
+                    written = true;
+                }
+                offset = end;
+            }
+            if written {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for SpinfileWatcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
@@ -0,0 +1,120 @@
+//! # spinnrctl
+//! spinnrctl sends one request to a running spinnrd over its control socket
+//! and prints the response.
+
+#[macro_use] extern crate lazy_static;
+extern crate chrono;
+extern crate regex;
+extern crate clap;
+
+
+#[path = "../paths.rs"]
+#[allow(dead_code)] // spinnrctl only needs parse_path
+mod paths;
+#[path = "../protocol.rs"]
+#[allow(dead_code)] // format_response is for spinnrd
+mod protocol;
+#[allow(dead_code)]
+mod metadata {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+
+use paths::*;
+use protocol::*;
+
+use std::io::{BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use clap::{Arg,ArgMatches};
+
+/// Error indicating spinnrd refused the request
+const ERR_REFUSED: i32 = 1;
+
+/// Error indicating spinnrd couldn't be reached
+const ERR_NO_CONNECTION: i32 = 2;
+
+lazy_static!{
+    static ref VERSION: String = format!("{} ({})", metadata::PKG_VERSION, metadata::FEATURES_STR);
+
+    /// The command line arguments
+    static ref CLI_ARGS: ArgMatches<'static> = clap::App::new("spinnrctl")
+        .version((*VERSION).as_str())
+        .author("James Wescott <james@wescottdesign.com>")
+        .about("Controls a running spinnrd")
+        .arg(Arg::with_name("socket")
+             .long("socket")
+             .short("s")
+             .value_name("SOCKET")
+             .default_value(DEFAULT_CONTROL_SOCKET)
+             .help("spinnrd's control socket (see spinnrd --control-socket). Uses filename formatting.")
+             )
+        .arg(Arg::with_name("workingdir")
+             .long("working-directory")
+             .number_of_values(1)
+             .value_name("WORKING_DIR")
+             .default_value(DEFAULT_WORKING_DIRECTORY)
+             .help("spinnrd's working directory (what %d expands to).")
+             )
+        .arg(Arg::with_name("command")
+             .value_name("COMMAND")
+             .required(true)
+             .multiple(true)
+             .help("What to tell spinnrd to do (see below).")
+             )
+        .after_help("COMMANDS:
+    status                      Show the rotation, the lock, the latest readings, and the settings
+    lock [ROTATION]             Lock the rotation where it is, or at ROTATION
+    unlock                      Unlock the rotation
+    rotate ROTATION             Rotate to ROTATION until the device is turned (or move the lock there)
+    set delay MS                Change --delay until spinnrd is reloaded
+    set period MS               Change --interval until spinnrd is reloaded
+    set sensitivity VALUE       Change --sensitivity until spinnrd is reloaded
+    pause                       Stop reading the orientation
+    resume                      Start reading it again
+
+Filenames are formatted the same way as spinnrd's (see spinnrd --help). Only users who can write to the control socket can use it (see spinnrd --control-mode).")
+        .get_matches();
+
+    /// spinnrd's working directory, for `%d`
+    static ref WORKING_DIR: PathBuf = PathBuf::from(
+        parse_path(CLI_ARGS.value_of("workingdir").unwrap_or(DEFAULT_WORKING_DIRECTORY), true));
+}
+
+fn main() {
+    ::std::process::exit(mainprog());
+}
+
+/// The actual main body of the program
+fn mainprog() -> i32 {
+    let socket = PathBuf::from(parse_path(
+            CLI_ARGS.value_of("socket").unwrap_or(DEFAULT_CONTROL_SOCKET), false));
+    let request = CLI_ARGS.values_of("command").map_or(Vec::new(), |v| v.collect()).join(" ");
+
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(s)   => s,
+        Err(e)  => {
+            eprintln!("Couldn't connect to spinnrd at {} ({})", socket.display(), e);
+            return ERR_NO_CONNECTION;
+        },
+    };
+    let response = stream.write_all(format!("{}\n", request).as_bytes())
+        .and_then(|_| read_response(&mut BufReader::new(&stream)));
+    match response {
+        Ok(Ok(values))  => {
+            for (key, value) in values {
+                println!("{}: {}", key, value);
+            }
+            0
+        },
+        Ok(Err(e))  => {
+            eprintln!("spinnrd refused '{}': {}", request, e);
+            ERR_REFUSED
+        },
+        Err(e)  => {
+            eprintln!("Couldn't talk to spinnrd at {} ({})", socket.display(), e);
+            ERR_NO_CONNECTION
+        },
+    }
+}
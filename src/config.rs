@@ -0,0 +1,517 @@
+//! # config
+//! Reading options from config files.
+//!
+//! Config files are TOML, with a key for every command line option (named
+//! after its long form) plus `backend-options` and `frontend-options`
+//! tables of per-backend and per-frontend options:
+//!
+//! ```toml
+//! interval = 150
+//! log-level = "info"
+//! backend = ["iioaccel", "fsaccel"]
+//!
+//! [backend-options.fsaccel]
+//! mount_matrix = "0, 1, 0; -1, 0, 0; 0, 0, 1"
+//!
+//! [frontend-options.file]
+//! path = "%d/spinnrd.spin"
+//! ```
+//!
+//! Options given on the command line win over the config file, which wins
+//! over the built-in defaults.
+
+use super::*;
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::Path;
+use std::sync::RwLock;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+
+/// The system-wide config file
+pub const SYSTEM_CONFIG_FILE: &str = "/etc/spinnrd/config.toml";
+
+/// The per-user config file, relative to the XDG config directory
+pub const USER_CONFIG_FILE: &str = "spinnrd/config.toml";
+
+lazy_static! {
+    /// The currently loaded config
+    static ref CONFIG: RwLock<Config> = RwLock::new(Config::default());
+}
+
+/// A single option value; anything TOML calls a scalar.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Display for OptionValue {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            OptionValue::Str(s) => write!(f, "{}", s),
+            OptionValue::Int(i) => write!(f, "{}", i),
+            OptionValue::Float(x)   => write!(f, "{}", x),
+            OptionValue::Bool(b)    => write!(f, "{}", b),
+        }
+    }
+}
+
+/// A map of `backend (or frontend) -> option -> value`.
+pub type OptionMap = HashMap<String, HashMap<String, OptionValue>>;
+
+/// A list of backends or frontends, either as a list or in the same
+/// `;`-separated form the command line takes.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum NameList {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Display for NameList {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            NameList::One(s)    => write!(f, "{}", s),
+            NameList::Many(v)   => write!(f, "{}", v.join(";")),
+        }
+    }
+}
+
+/// The log levels the config file accepts.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            LogLevel::Trace => write!(f, "trace"),
+            LogLevel::Debug => write!(f, "debug"),
+            LogLevel::Info  => write!(f, "info"),
+            LogLevel::Warn  => write!(f, "warn"),
+            LogLevel::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Deserialize a string option, checked the same way as on the command
+/// line (so a bad one is caught with the line it's on).
+macro_rules! validated {
+    ( $name:ident, $validate:ident ) => {
+        fn $name<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+            let s = String::deserialize(d)?;
+            $validate(s.clone()).map_err(D::Error::custom)?;
+            Ok(Some(s))
+        }
+    };
+}
+validated!(de_mode, validate_mode);
+validated!(de_nonempty, validate_nonempty);
+validated!(de_rotation, validate_rotation);
+validated!(de_rotations, validate_rotations);
+
+/// The contents of a config file.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    quiet: Option<bool>,
+    interval: Option<u32>,
+    slow_interval: Option<u32>,
+    motion_threshold: Option<f64>,
+    hysteresis: Option<u32>,
+    sensitivity: Option<f64>,
+    pid_file: Option<String>,
+    no_pid_file: Option<bool>,
+    lock_file: Option<String>,
+    control_socket: Option<String>,
+    #[serde(default, deserialize_with = "de_mode")]
+    control_mode: Option<String>,
+    working_directory: Option<String>,
+    #[serde(default, deserialize_with = "de_nonempty")]
+    log_file: Option<String>,
+    log_level: Option<LogLevel>,
+    spin_file: Option<String>,
+    daemonize: Option<bool>,
+    delay: Option<u32>,
+    #[serde(default, deserialize_with = "de_rotations")]
+    allowed: Option<String>,
+    #[serde(default, deserialize_with = "de_rotation")]
+    natural: Option<String>,
+    backend: Option<NameList>,
+    backend_retry: Option<u32>,
+    #[serde(default)]
+    backend_options: OptionMap,
+    frontend: Option<NameList>,
+    #[serde(default)]
+    frontend_options: OptionMap,
+    quirks_dir: Option<String>,
+    dmi_root: Option<String>,
+}
+
+impl Config {
+    /// Read a config file.
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| ConfigError::Io(e, path.to_owned()))?;
+        toml::from_str(&contents).map_err(|e| {
+            let line = e.span().map(|s| 1 + contents[..s.start].matches('\n').count());
+            ConfigError::Parse(Box::new(e), path.to_owned(), line)
+        })
+    }
+
+    /// Layer `over` on top of this config.
+    fn merge(self, over: Config) -> Config {
+        macro_rules! layer {
+            ( $($field:ident),* ) => {
+                Config {
+                    $( $field: over.$field.or(self.$field), )*
+                    backend_options: merge_maps(self.backend_options, over.backend_options),
+                    frontend_options: merge_maps(self.frontend_options, over.frontend_options),
+                }
+            }
+        }
+        layer!(quiet, interval, slow_interval, motion_threshold, hysteresis, sensitivity,
+               pid_file, no_pid_file, lock_file, control_socket,
+               control_mode, working_directory, log_file, log_level, spin_file, daemonize,
+               delay, allowed, natural, backend, backend_retry, frontend, quirks_dir, dmi_root)
+    }
+
+    /// Get the value for a command line argument (by its clap name).
+    pub fn get(&self, name: &str) -> Option<String> {
+        fn s<T: ToString>(v: &Option<T>) -> Option<String> {
+            v.as_ref().map(|v| v.to_string())
+        }
+        match name {
+            "quiet"     => s(&self.quiet),
+            "period"    => s(&self.interval),
+            "slow_period"   => s(&self.slow_interval),
+            "motion_threshold"  => s(&self.motion_threshold),
+            "hysteresis"    => s(&self.hysteresis),
+            "sensitivity"   => s(&self.sensitivity),
+            "pidfile"   => s(&self.pid_file),
+            "nopidfile" => s(&self.no_pid_file),
+            "lockfile"  => s(&self.lock_file),
+            "control_socket"    => s(&self.control_socket),
+            "control_mode"  => s(&self.control_mode),
+            "workingdir"    => s(&self.working_directory),
+            "logfile"   => s(&self.log_file),
+            "loglvl"    => s(&self.log_level),
+            "spinfile"  => s(&self.spin_file),
+            "daemonize" => s(&self.daemonize),
+            "delay"     => s(&self.delay),
+            "allowed"   => s(&self.allowed),
+            "natural"   => s(&self.natural),
+            "backend"   => s(&self.backend),
+            "backend_retry" => s(&self.backend_retry),
+            "frontend"  => s(&self.frontend),
+            "quirks_dir"    => s(&self.quirks_dir),
+            "dmi_root"  => s(&self.dmi_root),
+            _   => None,
+        }
+    }
+
+    /// The per-backend options.
+    pub fn backend_options(&self) -> HashMap<String, HashMap<String, String>> {
+        stringify_map(&self.backend_options)
+    }
+
+    /// The per-frontend options.
+    pub fn frontend_options(&self) -> HashMap<String, HashMap<String, String>> {
+        stringify_map(&self.frontend_options)
+    }
+}
+
+/// Layer the options in `over` on top of those in `under`.
+fn merge_maps(mut under: OptionMap, over: OptionMap) -> OptionMap {
+    for (name, opts) in over {
+        under.entry(name).or_default().extend(opts);
+    }
+    under
+}
+
+/// Turn every value in an `OptionMap` into a string.
+pub fn stringify_map(map: &OptionMap) -> HashMap<String, HashMap<String, String>> {
+    map.iter()
+        .map(|(name, opts)| (
+                name.to_owned(),
+                opts.iter().map(|(k, v)| (k.to_owned(), v.to_string())).collect()
+                ))
+        .collect()
+}
+
+/// Represents an error loading the config
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Couldn't read the file
+    Io(IoError, PathBuf),
+    /// Bad syntax, unknown key, or bad value (on a line, if it's known
+    /// which)
+    Parse(Box<toml::de::Error>, PathBuf, Option<usize>),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            ConfigError::Io(e, p)   => {
+                write!(fmt, "couldn't read config file {}: {}", p.to_string_lossy(), e)
+            },
+            ConfigError::Parse(e, p, Some(line))    => {
+                write!(fmt, "bad config file {}:{}: {}", p.to_string_lossy(), line, e.message())
+            },
+            ConfigError::Parse(e, p, None)  => {
+                write!(fmt, "bad config file {}: {}", p.to_string_lossy(), e.message())
+            },
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn description(&self) -> &str {
+        "couldn't load config"
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io(e, _)   => Some(e),
+            ConfigError::Parse(e, _, _) => Some(&**e),
+        }
+    }
+}
+
+/// The per-user config file (given `$XDG_CONFIG_HOME` and `$HOME`), if
+/// there's anywhere to look for one.
+fn user_config_file(xdg_config_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    match xdg_config_home {
+        Some(ref d) if !d.is_empty() => Some(PathBuf::from(d)),
+        _   => home.map(|h| PathBuf::from(h).join(".config")),
+    }.map(|d| d.join(USER_CONFIG_FILE))
+}
+
+/// The config files to load, in order of increasing precedence, and
+/// whether each one has to be there.
+///
+/// `--config` (`config`) replaces the search; otherwise the system-wide and
+/// per-user files are used if they exist.
+fn config_files(config: Option<&str>, system: &Path, user: Option<PathBuf>) -> Vec<(PathBuf, bool)> {
+    match config {
+        Some(p) => vec![(PathBuf::from(p), true)],
+        None    => {
+            let mut files = vec![(system.to_owned(), false)];
+            files.extend(user.map(|p| (p, false)));
+            files
+        },
+    }
+}
+
+/// Read config files, each on top of the ones before it. Returns the
+/// result and the files that were there.
+fn load_files(files: Vec<(PathBuf, bool)>) -> Result<(Config, Vec<PathBuf>), ConfigError> {
+    let mut config = Config::default();
+    let mut loaded = Vec::new();
+    for (path, required) in files {
+        if !required && !path.exists() {
+            continue;
+        }
+        config = config.merge(Config::from_file(&path)?);
+        loaded.push(path);
+    }
+    Ok((config, loaded))
+}
+
+/// Load (or reload) the config files.
+///
+/// If anything goes wrong, the currently loaded config is left alone.
+pub fn load_config() -> Result<Vec<PathBuf>, ConfigError> {
+    let files = config_files(
+        CLI_ARGS.value_of("config"),
+        Path::new(SYSTEM_CONFIG_FILE),
+        user_config_file(std::env::var_os("XDG_CONFIG_HOME"), std::env::var_os("HOME")));
+    let (config, loaded) = load_files(files)?;
+    set_config(config);
+    Ok(loaded)
+}
+
+/// Replace the currently loaded config.
+pub fn set_config(config: Config) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config;
+}
+
+/// Run `f` on the currently loaded config.
+pub fn with_config<T, F: FnOnce(&Config) -> T>(f: F) -> T {
+    f(&CONFIG.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Get the value of an option: from the command line if it was given
+/// there, otherwise from the config file, otherwise its default (if any).
+pub fn arg_value(name: &str) -> Option<String> {
+    with_config(|c| value_of(&CLI_ARGS, c, name))
+}
+
+/// Whether a flag was set on the command line or in the config file.
+pub fn arg_flag(name: &str) -> bool {
+    with_config(|c| flag_of(&CLI_ARGS, c, name))
+}
+
+/// `arg_value`, for any command line and config.
+fn value_of(args: &ArgMatches, config: &Config, name: &str) -> Option<String> {
+    if args.occurrences_of(name) > 0 {
+        return args.value_of(name).map(|s| s.to_owned());
+    }
+    config.get(name).or_else(|| args.value_of(name).map(|s| s.to_owned()))
+}
+
+/// `arg_flag`, for any command line and config.
+fn flag_of(args: &ArgMatches, config: &Config, name: &str) -> bool {
+    args.is_present(name) || config.get(name).is_some_and(|v| "true" == v)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use testutil::TempDir;
+
+    fn write(path: &Path, contents: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        path.to_owned()
+    }
+
+    /// The error loading a config file, as it'd be logged.
+    fn error(contents: &str) -> String {
+        let dir = TempDir::new("config");
+        let path = write(&dir.join("config.toml"), contents);
+        let e = Config::from_file(&path).unwrap_err().to_string();
+        assert!(e.contains(&path.to_string_lossy().into_owned()), "no file in '{}'", e);
+        e
+    }
+
+    #[test]
+    fn reads_every_kind_of_value() {
+        let dir = TempDir::new("config");
+        let path = write(&dir.join("config.toml"), r#"
+interval = 150
+sensitivity = 2.5
+daemonize = true
+log-level = "debug"
+allowed = "normal,left,right"
+natural = "left"
+control-mode = "600"
+backend = ["iioaccel", "fsaccel"]
+
+[backend-options.fsaccel]
+mount_matrix = "0, 1, 0; -1, 0, 0; 0, 0, 1"
+scale = 0.5
+"#);
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(Some("150".to_owned()), config.get("period"));
+        assert_eq!(Some("2.5".to_owned()), config.get("sensitivity"));
+        assert_eq!(Some("true".to_owned()), config.get("daemonize"));
+        assert_eq!(Some("debug".to_owned()), config.get("loglvl"));
+        assert_eq!(Some("normal,left,right".to_owned()), config.get("allowed"));
+        assert_eq!(Some("left".to_owned()), config.get("natural"));
+        assert_eq!(Some("600".to_owned()), config.get("control_mode"));
+        assert_eq!(Some("iioaccel;fsaccel".to_owned()), config.get("backend"));
+        assert_eq!(None, config.get("delay"));
+        assert_eq!("0.5", config.backend_options()["fsaccel"]["scale"]);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let e = error("interval = 150\nintervall = 200\n");
+        assert!(e.contains(":2:") && e.contains("intervall"), "{}", e);
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for &(contents, line) in &[
+            ("interval = -5\n", 1),
+            ("interval = 5\nlog-level = \"loud\"\n", 2),
+            ("\n\nallowed = \"normal,sideways\"\n", 3),
+            ("natural = \"up\"\n", 1),
+            ("delay = 5\ncontrol-mode = \"rw-rw----\"\n", 2),
+            ("log-file = \"\"\n", 1),
+            ("interval = \n", 1),
+        ] {
+            let e = error(contents);
+            assert!(e.contains(&format!(":{}: ", line)), "no line {} in '{}'", line, e);
+        }
+    }
+
+    #[test]
+    fn finds_user_config() {
+        assert_eq!(Some(PathBuf::from("/xdg").join(USER_CONFIG_FILE)),
+                   user_config_file(Some("/xdg".into()), Some("/home/me".into())));
+        assert_eq!(Some(PathBuf::from("/home/me/.config").join(USER_CONFIG_FILE)),
+                   user_config_file(Some("".into()), Some("/home/me".into())));
+        assert_eq!(None, user_config_file(None, None));
+    }
+
+    #[test]
+    fn precedence() {
+        let dir = TempDir::new("config");
+        let system = write(&dir.join("etc/spinnrd/config.toml"),
+                           "delay = 100\ninterval = 200\nnatural = \"left\"\n");
+        let user = user_config_file(Some(dir.join("xdg").into_os_string()), None).unwrap();
+        write(&user, "delay = 300\n");
+        let explicit = write(&dir.join("explicit.toml"), "delay = 400\n");
+        let explicit = explicit.to_string_lossy().into_owned();
+        let default = |name| cli_app().get_matches_from(vec!["spinnrd"]).value_of(name)
+            .map(|s: &str| s.to_owned());
+
+        // The per-user file goes on top of the system-wide one...
+        let (config, loaded) = load_files(config_files(None, &system, Some(user.clone()))).unwrap();
+        assert_eq!(vec![system.clone(), user.clone()], loaded);
+        let args = cli_app().get_matches_from(vec!["spinnrd"]);
+        assert_eq!(Some("300".to_owned()), value_of(&args, &config, "delay"));
+        assert_eq!(Some("200".to_owned()), value_of(&args, &config, "period"));
+        assert_eq!(default("hysteresis"), value_of(&args, &config, "hysteresis"));
+        // ...and the command line on top of both.
+        let args = cli_app().get_matches_from(vec!["spinnrd", "--delay", "5", "--natural", "right"]);
+        assert_eq!(Some("5".to_owned()), value_of(&args, &config, "delay"));
+        assert_eq!(Some("right".to_owned()), value_of(&args, &config, "natural"));
+        assert_eq!(Some("200".to_owned()), value_of(&args, &config, "period"));
+
+        // Missing files are skipped...
+        let (config, loaded) = load_files(config_files(None, &dir.join("nowhere"), Some(user.clone()))).unwrap();
+        assert_eq!(vec![user.clone()], loaded);
+        let args = cli_app().get_matches_from(vec!["spinnrd"]);
+        assert_eq!(default("period"), value_of(&args, &config, "period"));
+
+        // ...and --config replaces both.
+        let (config, loaded) = load_files(config_files(Some(&explicit), &system, Some(user))).unwrap();
+        assert_eq!(vec![PathBuf::from(&explicit)], loaded);
+        assert_eq!(Some("400".to_owned()), value_of(&args, &config, "delay"));
+        assert_eq!(default("natural"), value_of(&args, &config, "natural"));
+        // A --config that isn't there is an error, though.
+        let missing = dir.join("missing.toml").to_string_lossy().into_owned();
+        assert!(matches!(load_files(config_files(Some(&missing), &system, None)),
+                         Err(ConfigError::Io(_, _))));
+    }
+
+    #[test]
+    fn flags_from_either() {
+        let dir = TempDir::new("config");
+        let path = write(&dir.join("config.toml"), "daemonize = true\nquiet = false\n");
+        let config = Config::from_file(&path).unwrap();
+        let args = cli_app().get_matches_from(vec!["spinnrd", "--quiet"]);
+        assert!(flag_of(&args, &config, "daemonize"));
+        assert!(flag_of(&args, &config, "quiet"));
+        assert!(!flag_of(&args, &Config::default(), "daemonize"));
+    }
+}
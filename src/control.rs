@@ -0,0 +1,260 @@
+//! # control
+//! The control socket, through which `spinnrctl` (or anything else allowed
+//! to connect to it) can see what spinnrd is doing and steer it. See
+//! `protocol` for how it's spoken to.
+
+use super::*;
+use protocol::*;
+
+use std::fs::Permissions;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::str::FromStr;
+
+/// The default permissions of the control socket (clients need write
+/// permission to connect, so only spinnrd's user and group can)
+pub const DEFAULT_CONTROL_MODE: &str = "660";
+
+/// The longest request that's accepted
+const MAX_REQUEST: usize = 1024;
+
+/// A setting that can be changed while spinnrd is running
+#[derive(Debug, Clone, Copy)]
+pub enum Setting {
+    Delay(Duration),
+    Sensitivity(f64),
+    Period(Duration),
+}
+
+impl Display for Setting {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Setting::Delay(d)   => write!(f, "delay to {}ms", d.as_millis()),
+            Setting::Sensitivity(s) => write!(f, "sensitivity to {}", s),
+            Setting::Period(p)  => write!(f, "period to {}ms", p.as_millis()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Request {
+    /// Report what's going on
+    Status,
+    /// Lock the rotation where it is, or at a rotation
+    Lock(Option<Rotation>),
+    Unlock,
+    /// Change a setting (until spinnrd is reloaded)
+    Set(Setting),
+    /// Rotate now, and stay there until the device is turned
+    Rotate(Rotation),
+    /// Stop reading the orientation (so the rotation stays put)
+    Pause,
+    Resume,
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Request, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let rotation = |r: &str| r.parse::<Rotation>().map_err(|e| format!("{} ('{}')", e, r));
+        let millis = |v: &str| v.parse::<u64>().map(Duration::from_millis)
+            .map_err(|_| format!("not a number of milliseconds ('{}')", v));
+        match words[..] {
+            ["status"]  => Ok(Request::Status),
+            ["lock"]    => Ok(Request::Lock(None)),
+            ["lock", r] => Ok(Request::Lock(Some(rotation(r)?))),
+            ["unlock"]  => Ok(Request::Unlock),
+            ["set", "delay", v] => Ok(Request::Set(Setting::Delay(millis(v)?))),
+            ["set", "period", v]    => match millis(v)? {
+                p if p > Duration::from_millis(0)   => Ok(Request::Set(Setting::Period(p))),
+                _   => Err("the period can't be 0".to_owned()),
+            },
+            ["set", "sensitivity", v]   => match v.parse::<f64>() {
+                Ok(s) if s > 0.0    => Ok(Request::Set(Setting::Sensitivity(s))),
+                _   => Err(format!("not a positive number ('{}')", v)),
+            },
+            ["set", ..] => Err("usage: set delay|sensitivity|period VALUE".to_owned()),
+            ["rotate", r]   => Ok(Request::Rotate(rotation(r)?)),
+            ["pause"]   => Ok(Request::Pause),
+            ["resume"]  => Ok(Request::Resume),
+            [c, ..] => Err(format!("no such command '{}' (or wrong arguments)", c)),
+            []  => Err("no command".to_owned()),
+        }
+    }
+}
+
+/// A client, and whatever it's sent that isn't a whole line yet
+struct Client {
+    stream: UnixStream,
+    buf: Vec<u8>,
+}
+
+/// The control socket and everything connected to it.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+}
+
+impl ControlSocket {
+    pub fn bind(path: PathBuf, mode: u32) -> Result<ControlSocket, IoError> {
+        remove_stale_socket(&path)?;
+        // Created with the right permissions, so nobody else can connect
+        // before they're set.
+        let umask = unsafe { libc::umask(!mode & 0o777) };
+        let listener = UnixListener::bind(&path);
+        unsafe { libc::umask(umask) };
+        let listener = listener?;
+        listener.set_nonblocking(true)?;
+        std::fs::set_permissions(&path, Permissions::from_mode(mode))?;
+        Ok(ControlSocket {
+            path,
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Accept any new clients, and answer every request that's come in
+    /// with `handle`.
+    pub fn serve<F: FnMut(Request) -> Response>(&mut self, mut handle: F) {
+        self.accept();
+        let path = &self.path;
+        self.clients.retain_mut(|client| {
+            let mut requests = Vec::new();
+            let alive = client.read(&mut requests);
+            for request in requests {
+                debug!("Control request: {}", request);
+                let response = request.parse().and_then(&mut handle);
+                if let Err(ref e) = response {
+                    debug!("Control request '{}' failed: {}", request, e);
+                }
+                if let Err(e) = client.write(&format_response(&response)) {
+                    debug!("Dropping client of {} ({})", path.to_string_lossy(), e);
+                    return false;
+                }
+            }
+            alive
+        });
+    }
+
+    pub fn wake_on(&self, wakeup: &mut Wakeup) {
+        wakeup.fd(self.listener.as_raw_fd());
+        for client in self.clients.iter() {
+            wakeup.fd(client.stream.as_raw_fd());
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        warn!("Dropping client of {} ({})", self.path.to_string_lossy(), e);
+                        continue;
+                    }
+                    self.clients.push(Client { stream, buf: Vec::new() });
+                },
+                Err(ref e) if IoErrorKind::WouldBlock == e.kind() => break,
+                Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+                Err(e)  => {
+                    warn!("Couldn't accept client of {} ({})", self.path.to_string_lossy(), e);
+                    break;
+                },
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Couldn't remove {} ({})", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+impl Client {
+    /// Read the whole lines that have come in into `requests`. Returns
+    /// whether it's still connected.
+    fn read(&mut self, requests: &mut Vec<String>) -> bool {
+        let mut chunk = [0u8; 256];
+        let alive = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0)   => break false,
+                Ok(n)   => self.buf.extend_from_slice(&chunk[..n]),
+                Err(ref e) if IoErrorKind::WouldBlock == e.kind() => break true,
+                Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+                Err(_)  => break false,
+            }
+        };
+        while let Some(end) = self.buf.iter().position(|&b| b'\n' == b) {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            requests.push(String::from_utf8_lossy(&line).trim().to_owned());
+        }
+        // Whatever this is, it isn't a request.
+        alive && self.buf.len() <= MAX_REQUEST
+    }
+
+    /// Write a response, without waiting.
+    ///
+    /// Responses are small, so a client that can't take a whole one isn't
+    /// reading them.
+    fn write(&mut self, response: &str) -> Result<(), IoError> {
+        match self.stream.write(response.as_bytes()) {
+            Ok(n) if n == response.len()    => Ok(()),
+            Ok(_)   => Err(IoError::new(IoErrorKind::WriteZero, "client isn't keeping up")),
+            Err(e)  => Err(e),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::TempDir;
+
+    #[test]
+    fn parses_requests() {
+        let ok = |s: &str| s.parse::<Request>().unwrap_or_else(|e| panic!("'{}': {}", s, e));
+        assert!(matches!(ok("status"), Request::Status));
+        assert!(matches!(ok("  lock\t"), Request::Lock(None)));
+        assert!(matches!(ok("lock left"), Request::Lock(Some(Rotation::Left))));
+        assert!(matches!(ok("unlock"), Request::Unlock));
+        assert!(matches!(ok("rotate inverted"), Request::Rotate(Rotation::Inverted)));
+        assert!(matches!(ok("pause"), Request::Pause));
+        assert!(matches!(ok("resume"), Request::Resume));
+        match ok("set delay 0") {
+            Request::Set(Setting::Delay(d)) => assert_eq!(Duration::from_millis(0), d),
+            r   => panic!("{:?}", r),
+        }
+        match ok("set period 150") {
+            Request::Set(Setting::Period(p))    => assert_eq!(Duration::from_millis(150), p),
+            r   => panic!("{:?}", r),
+        }
+        match ok("set sensitivity 0.5") {
+            Request::Set(Setting::Sensitivity(s))   => assert_eq!(0.5, s),
+            r   => panic!("{:?}", r),
+        }
+
+        for bad in &["", "   ", "spin", "status now", "lock sideways", "rotate",
+                     "set", "set delay", "set delay -1", "set delay soon", "set period 0",
+                     "set sensitivity 0", "set sensitivity -2", "set interval 5"] {
+            assert!(bad.parse::<Request>().is_err(), "'{}' parsed", bad);
+        }
+    }
+
+    #[test]
+    fn binds_with_mode() {
+        let dir = TempDir::new("control");
+        let path = dir.join("spinnrd.ctl");
+        let control = ControlSocket::bind(path.clone(), 0o600).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+        drop(control);
+        assert!(!path.exists());
+    }
+}
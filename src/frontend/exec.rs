@@ -0,0 +1,450 @@
+//! # exec
+//! A frontend that runs a command every time the rotation changes, for
+//! side effects that are easiest as a script.
+
+use super::*;
+
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, ChildStderr, Command, ExitStatus, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long a command gets before it's killed (in ms)
+pub const DEFAULT_EXEC_TIMEOUT: u64 = 5000;
+
+/// How much of a command's stderr is kept for the log
+const MAX_STDERR: usize = 4096;
+
+/// How often to check whether a command that's closed its stderr (so
+/// there's nothing to wake up for) has finished (in ms)
+const REAP_INTERVAL: u64 = 100;
+
+/// What a command is run for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Event {
+    Rotation(Rotation),
+    Posture(Posture),
+    Lock(bool),
+}
+
+impl Display for Event {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::Rotation(r)  => write!(fmt, "{}", r),
+            Event::Posture(p)   => write!(fmt, "{}", p),
+            Event::Lock(true)   => write!(fmt, "locked"),
+            Event::Lock(false)  => write!(fmt, "unlocked"),
+        }
+    }
+}
+
+/// A command that's running
+struct Running {
+    child: Child,
+    stderr: Option<ChildStderr>,
+    /// What it's written to stderr so far
+    errput: Vec<u8>,
+    event: Event,
+    started: Instant,
+}
+
+/// Runs a command with each new rotation.
+///
+/// The command gets the rotation as its last argument, and in
+/// `SPINNR_ROTATION`, with the rotation before it in
+/// `SPINNR_PREVIOUS_ROTATION` and when it happened (in seconds since the
+/// epoch) in `SPINNR_TIMESTAMP`.
+///
+/// With `postures`, it's also run when the device is laid flat or picked
+/// back up, with `face-up`, `face-down`, or `upright` as its last argument
+/// (and in `SPINNR_POSTURE`). With `locks`, it's run with `locked` or
+/// `unlocked` when the rotation lock changes (and `SPINNR_LOCKED` set to
+/// `true` or `false`). `SPINNR_EVENT` says which it's being run for
+/// (`rotation`, `posture`, or `lock`).
+///
+/// Only one command runs at a time; rotations that come in while one is
+/// running are run after it, skipping any that have been superseded.
+pub struct ExecSender {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+    /// Whether to run the command for postures as well as rotations
+    postures: bool,
+    /// Whether to run the command when the rotation lock changes
+    locks: bool,
+    running: Option<Running>,
+    /// The rotation to run the command with once the current one is done
+    pending: Option<Rotation>,
+    /// The posture to run the command with once the current one is done
+    pending_posture: Option<Posture>,
+    /// The lock to run the command with once the current one is done
+    pending_lock: Option<bool>,
+    /// The rotation the command was last run with
+    previous: Option<Rotation>,
+    /// The posture the command was last run with
+    posture: Option<Posture>,
+    /// Whether the command was last run for the rotation being locked
+    locked: Option<bool>,
+}
+
+impl ExecSender {
+    /// `command` is split on whitespace into the program and its first
+    /// arguments; it isn't run by a shell.
+    pub fn init(command: &str, timeout: Duration, postures: bool, locks: bool)
+        -> InitResult<ExecSender>
+    {
+        let mut words = command.split_whitespace().map(|w| w.to_owned());
+        let program = match words.next() {
+            Some(p) => p,
+            None    => return Err(FrontendError::BadOption("command", command.to_owned())),
+        };
+        Ok(ExecSender {
+            program,
+            args: words.collect(),
+            timeout,
+            postures,
+            locks,
+            running: None,
+            pending: None,
+            pending_posture: None,
+            pending_lock: None,
+            previous: None,
+            posture: None,
+            locked: None,
+        })
+    }
+
+    /// Start the command for `event`.
+    fn spawn(&mut self, event: Event) -> Result<(), IoError> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs()).unwrap_or(0);
+        let name = |o: Option<String>| o.unwrap_or_default();
+        let mut command = Command::new(&self.program);
+        command.args(&self.args)
+            .arg(event.to_string())
+            .env("SPINNR_TIMESTAMP", timestamp.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        match event {
+            Event::Rotation(r)  => {
+                command.env("SPINNR_EVENT", "rotation")
+                    .env("SPINNR_ROTATION", r.to_string())
+                    .env("SPINNR_PREVIOUS_ROTATION", name(self.previous.map(|r| r.to_string())));
+                if self.postures {
+                    command.env("SPINNR_POSTURE", name(self.posture.map(|p| p.to_string())));
+                }
+            },
+            Event::Posture(p)   => {
+                command.env("SPINNR_EVENT", "posture")
+                    .env("SPINNR_ROTATION", name(self.previous.map(|r| r.to_string())))
+                    .env("SPINNR_POSTURE", p.to_string())
+                    .env("SPINNR_PREVIOUS_POSTURE", name(self.posture.map(|p| p.to_string())));
+            },
+            Event::Lock(l)  => {
+                command.env("SPINNR_EVENT", "lock")
+                    .env("SPINNR_ROTATION", name(self.previous.map(|r| r.to_string())))
+                    .env("SPINNR_LOCKED", l.to_string());
+            },
+        }
+        let mut child = command.spawn()?;
+        debug!("Running {} for {} (pid {})", self.program, event, child.id());
+        // Reading stderr mustn't block the main loop.
+        let stderr = child.stderr.take().filter(|e| set_nonblocking(e).is_ok());
+        match event {
+            Event::Rotation(r)  => self.previous = Some(r),
+            Event::Posture(p)   => self.posture = Some(p),
+            Event::Lock(l)  => self.locked = Some(l),
+        }
+        self.running = Some(Running {
+            child,
+            stderr,
+            errput: Vec::new(),
+            event,
+            started: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Run the command for `event` now, or once the one that's running is
+    /// done (in place of any other of the same kind that's waiting).
+    fn run(&mut self, event: Event) -> SendResult {
+        let busy = self.reap();
+        match event {
+            Event::Rotation(r)  => self.pending = Some(r).filter(|_| busy),
+            Event::Posture(p)   => self.pending_posture = Some(p).filter(|_| busy),
+            Event::Lock(l)  => self.pending_lock = Some(l).filter(|_| busy),
+        }
+        if busy {
+            debug!("{} is still running; will run it for {} next", self.program, event);
+            return Ok(());
+        }
+        self.spawn(event).map_err(SendError::IoError)
+    }
+
+    /// Check on the running command, logging how it went if it's done (or
+    /// killing it if it's taken too long).
+    ///
+    /// Returns whether it's still running.
+    fn reap(&mut self) -> bool {
+        let program = &self.program;
+        let timeout = self.timeout;
+        let running = match self.running {
+            Some(ref mut r) => r,
+            None    => return false,
+        };
+        running.read_stderr();
+        let status = match running.child.try_wait() {
+            Ok(Some(s)) => s,
+            Ok(None) if running.started.elapsed() < timeout   => return true,
+            Ok(None)    => {
+                warn!("{} took more than {}ms for {}; killing it",
+                      program, timeout.as_millis(), running.event);
+                if let Err(e) = running.child.kill() {
+                    warn!("Couldn't kill {} ({})", program, e);
+                }
+                match running.child.wait() {
+                    Ok(s)   => s,
+                    Err(e)  => {
+                        warn!("Couldn't wait for {} ({})", program, e);
+                        self.running = None;
+                        return false;
+                    },
+                }
+            },
+            Err(e)  => {
+                warn!("Couldn't check on {} ({})", program, e);
+                self.running = None;
+                return false;
+            },
+        };
+        running.read_stderr();
+        log_finished(program, running, status);
+        self.running = None;
+        false
+    }
+}
+
+impl Running {
+    /// Read whatever the command has written to stderr, without blocking.
+    fn read_stderr(&mut self) {
+        let mut buf = [0u8; 1024];
+        let stderr = match self.stderr {
+            Some(ref mut e) => e,
+            None    => return,
+        };
+        loop {
+            match stderr.read(&mut buf) {
+                Ok(0)   => {
+                    // Closed, so there's no point waiting on it any more.
+                    self.stderr = None;
+                    break;
+                },
+                Ok(n)   => {
+                    let keep = n.min(MAX_STDERR.saturating_sub(self.errput.len()));
+                    self.errput.extend_from_slice(&buf[..keep]);
+                },
+                Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+                Err(_)  => break,
+            }
+        }
+    }
+}
+
+/// Log how a command went, including anything it wrote to stderr.
+fn log_finished(program: &str, running: &Running, status: ExitStatus) {
+    let errput = String::from_utf8_lossy(&running.errput);
+    if status.success() {
+        debug!("{} for {} finished", program, running.event);
+        for line in errput.lines() {
+            info!("{}: {}", program, line);
+        }
+    } else {
+        warn!("{} for {} failed ({})", program, running.event, status);
+        for line in errput.lines() {
+            warn!("{}: {}", program, line);
+        }
+    }
+}
+
+fn set_nonblocking(stderr: &ChildStderr) -> Result<(), IoError> {
+    let fd = stderr.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(IoError::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+impl Frontend for ExecSender {
+    fn send(&mut self, rotation: Rotation) -> SendResult {
+        self.run(Event::Rotation(rotation))
+    }
+
+    fn send_posture(&mut self, posture: Posture) -> SendResult {
+        if ! self.postures {
+            return Ok(());
+        }
+        self.run(Event::Posture(posture))
+    }
+
+    fn send_lock(&mut self, locked: bool) -> SendResult {
+        if ! self.locks {
+            return Ok(());
+        }
+        // Moving the lock to another rotation doesn't change whether it's
+        // locked.
+        if Some(locked) == self.locked && self.pending_lock.is_none() {
+            return Ok(());
+        }
+        self.run(Event::Lock(locked))
+    }
+
+    fn poll(&mut self) {
+        if self.reap() {
+            return;
+        }
+        // Rotations first, since they're more urgent; the rest wait their
+        // turn.
+        let event = if let Some(r) = self.pending.take().filter(|&r| Some(r) != self.previous) {
+            Event::Rotation(r)
+        } else if let Some(l) = self.pending_lock.take().filter(|&l| Some(l) != self.locked) {
+            Event::Lock(l)
+        } else if let Some(p) = self.pending_posture.take().filter(|&p| Some(p) != self.posture) {
+            Event::Posture(p)
+        } else {
+            return;
+        };
+        if let Err(e) = self.spawn(event) {
+            error!("Couldn't run {} for {} ({})", self.program, event, e);
+        }
+    }
+
+    fn wake_on(&self, wakeup: &mut Wakeup) {
+        if let Some(ref running) = self.running {
+            wakeup.at(running.started + self.timeout);
+            match running.stderr {
+                // It hangs up when the command exits.
+                Some(ref e) => wakeup.fd(e.as_raw_fd()),
+                None    => wakeup.after(Duration::from_millis(REAP_INTERVAL)),
+            }
+        }
+    }
+}
+
+impl Drop for ExecSender {
+    fn drop(&mut self) {
+        if let Some(ref mut running) = self.running {
+            debug!("Killing {} (still running for {})", self.program, running.event);
+            let _ = running.child.kill();
+            let _ = running.child.wait();
+        }
+    }
+}
+
+impl Display for ExecSender {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "ExecSender running {}", self.program)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::thread;
+    use testutil::TempDir;
+
+    /// Write an executable script to `dir`, returning its path.
+    fn script(dir: &Path, body: &str) -> String {
+        let path = dir.join("command");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// A script that writes its arguments and environment to `out`, then
+    /// sleeps for `sleep` seconds.
+    fn recorder(dir: &Path, sleep: &str) -> String {
+        script(dir, &format!(
+            "echo \"$*|$SPINNR_EVENT|$SPINNR_ROTATION|$SPINNR_PREVIOUS_ROTATION\" >> {}/out\nsleep {}",
+            dir.to_string_lossy(), sleep))
+    }
+
+    fn recorded(dir: &Path) -> Vec<String> {
+        fs::read_to_string(dir.join("out")).unwrap_or_default()
+            .lines().map(|l| l.to_owned()).collect()
+    }
+
+    /// Keep polling until there's nothing running or waiting to run.
+    fn finish(sender: &mut ExecSender) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while sender.reap() || sender.pending.is_some() {
+            assert!(Instant::now() < deadline, "command never finished");
+            sender.poll();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn passes_rotation() {
+        let dir = TempDir::new("exec");
+        let command = format!("{} first second", recorder(dir.path(), "0"));
+        let mut sender = ExecSender::init(&command, Duration::from_secs(5), false, false).unwrap();
+        sender.send(Rotation::Left).unwrap();
+        finish(&mut sender);
+        sender.send(Rotation::Right).unwrap();
+        finish(&mut sender);
+        let out = recorded(dir.path());
+        assert_eq!(vec!["first second left|rotation|left|",
+                        "first second right|rotation|right|left"], out);
+    }
+
+    #[test]
+    fn kills_slow_commands() {
+        let dir = TempDir::new("exec");
+        let command = script(dir.path(), "exec sleep 10");
+        let mut sender = ExecSender::init(&command, Duration::from_millis(50), false, false).unwrap();
+        let start = Instant::now();
+        sender.send(Rotation::Normal).unwrap();
+        assert!(sender.reap());
+        finish(&mut sender);
+        let took = start.elapsed();
+        assert!(took >= Duration::from_millis(50) && took < Duration::from_secs(5),
+                "took {:?}", took);
+    }
+
+    #[test]
+    fn skips_superseded_rotations() {
+        let dir = TempDir::new("exec");
+        let command = recorder(dir.path(), "0.2");
+        let mut sender = ExecSender::init(&command, Duration::from_secs(5), false, false).unwrap();
+        // Right is replaced by inverted before the first run is done...
+        sender.send(Rotation::Left).unwrap();
+        sender.send(Rotation::Right).unwrap();
+        sender.send(Rotation::Inverted).unwrap();
+        finish(&mut sender);
+        // ...and there's no need to run it again for where it already is.
+        sender.send(Rotation::Left).unwrap();
+        sender.send(Rotation::Right).unwrap();
+        sender.send(Rotation::Left).unwrap();
+        finish(&mut sender);
+        let out = recorded(dir.path());
+        assert_eq!(vec!["left|rotation|left|", "inverted|rotation|inverted|left",
+                        "left|rotation|left|inverted"], out);
+    }
+
+    #[test]
+    fn failing_commands_arent_errors() {
+        let mut sender = ExecSender::init("false", Duration::from_secs(5), false, false).unwrap();
+        sender.send(Rotation::Normal).unwrap();
+        finish(&mut sender);
+        assert!(ExecSender::init("  ", Duration::from_secs(5), false, false).is_err());
+    }
+}
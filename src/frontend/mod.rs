@@ -8,30 +8,133 @@ use std::io::Error as IoError;
 use std::fs::File;
 use std::io::Write;
 
+mod exec;
+mod socket;
+#[cfg(feature = "x11")]
+mod x11;
+#[cfg(feature = "dbus")]
+mod sensorproxy;
 
-// #[cfg(feature = "x11")]
-// type XSender = ???;
-// #[cfg(not(feature = "x11"))]
-// type XSender = DummySender;
+use self::exec::ExecSender;
+use self::socket::SocketSender;
+pub use self::socket::remove_stale_socket;
+
+#[cfg(feature = "x11")]
+type XSender = x11::XSender;
+#[cfg(not(feature = "x11"))]
+type XSender = DummySender;
+
+#[cfg(feature = "dbus")]
+type DBusSender = sensorproxy::DBusSender;
+#[cfg(not(feature = "dbus"))]
+type DBusSender = DummySender;
 
 type SendResult = Result<(), SendError>;
 
 pub fn frontend_help() -> String {
-    format!("{}", file_sender_help())
+    format!("{}{}{}{}{}{}", common_help(), file_sender_help(), socket_sender_help(),
+            exec_sender_help(), x11_sender_help(), dbus_sender_help())
+}
+
+fn common_help() -> String {
+    format!("
+    For all frontends:
+        quit_on_error: Whether to quit if sending a rotation to this 
+            frontend fails. Defaults to {}.\n",
+        DEFAULT_QUIT_ON_ROTATION_SEND_ERR
+        )
 }
 
 fn file_sender_help() -> String {
     format!("
     For File:
-        path: The path to the spinfile. Defaults to {}.\n",
+        path: The path to the spinfile. Defaults to --spin-file ({}).\n",
         DEFAULT_SPINFILE
         )
 }
-        
+
+fn socket_sender_help() -> String {
+    format!("
+    For Socket:
+        Clients are sent the current rotation when they connect, then 
+        every new one, one per line.
+        path: The Unix domain socket to listen on. Uses filename 
+            formatting. [Defaults to {}]
+        mode: The socket's permissions, in octal (clients need write 
+            permission to connect). [Defaults to {:o}]
+        locks: Whether to also send locked or unlocked whenever the 
+            rotation is locked or unlocked (and when a client connects). 
+            [Defaults to false]\n",
+        socket::DEFAULT_SOCKET, socket::DEFAULT_SOCKET_MODE
+        )
+}
+
+fn exec_sender_help() -> String {
+    format!("
+    For Exec:
+        Runs a command for every new rotation, with the rotation as its 
+        last argument and in $SPINNR_ROTATION. $SPINNR_PREVIOUS_ROTATION 
+        is the one before it (empty at first), and $SPINNR_TIMESTAMP is 
+        when it happened (in seconds since the epoch). Only one runs at a 
+        time; anything it writes to stderr is logged.
+        command: The program to run, and any arguments to give it before 
+            the rotation, separated by spaces (it isn't run by a shell).
+            [Required]
+        timeout: How long to let it run before killing it (in ms).
+            [Defaults to {}]
+        postures: Whether to also run it when the device is laid flat or 
+            picked back up, with face-up, face-down, or upright instead of 
+            a rotation (and in $SPINNR_POSTURE). $SPINNR_EVENT says which 
+            it's being run for (rotation, posture, or lock). 
+            [Defaults to false]
+        locks: Whether to also run it when the rotation is locked or 
+            unlocked, with locked or unlocked instead of a rotation (and 
+            $SPINNR_LOCKED set to true or false). [Defaults to false]\n",
+        exec::DEFAULT_EXEC_TIMEOUT
+        )
+}
+
+#[cfg(feature = "x11")]
+fn x11_sender_help() -> String {
+    "
+    For X11:
+        display: The X displays to rotate, separated by commas.
+            [Defaults to $DISPLAY]
+        output: The outputs to rotate, separated by commas.
+            [Defaults to the primary output, or the first connected one]
+        touchscreen: The touchscreens to rotate, separated by commas. Any 
+            pointer whose name contains one of these (ignoring case) is 
+            rotated; set it empty to leave touchscreens alone.
+            [Defaults to every direct touch device]
+        xauthority: The X authority file to connect with.
+            [Defaults to $XAUTHORITY]\n".to_owned()
+}
+#[cfg(not(feature = "x11"))]
+fn x11_sender_help() -> String { "".to_owned() }
+
+#[cfg(feature = "dbus")]
+fn dbus_sender_help() -> String {
+    format!("
+    For DBus:
+        Exports iio-sensor-proxy's net.hadess.SensorProxy interface, with 
+        the rotation as AccelerometerOrientation. iio-sensor-proxy can't 
+        be running at the same time.
+        bus: 'system' or 'session'. [Defaults to system]
+        name: The bus name to own. [Defaults to {}]\n",
+        sensorproxy::DEFAULT_BUS_NAME
+        )
+}
+#[cfg(not(feature = "dbus"))]
+fn dbus_sender_help() -> String { "".to_owned() }
+
 
 #[derive(Debug)]
 pub enum SendError {
     IoError(IoError),
+    #[cfg(feature = "x11")]
+    X11(x11::X11Error),
+    #[cfg(feature = "dbus")]
+    DBus(sensorproxy::DBusError),
 }
 
 impl Display for SendError {
@@ -40,6 +143,10 @@ impl Display for SendError {
             &SendError::IoError(ref e)  => {
                 write!(fmt, "io error sending rotation: {}", e)
             },
+            #[cfg(feature = "x11")]
+            &SendError::X11(ref e)  => write!(fmt, "{}", e),
+            #[cfg(feature = "dbus")]
+            &SendError::DBus(ref e) => write!(fmt, "{}", e),
         }
     }
 }
@@ -52,6 +159,10 @@ impl std::error::Error for SendError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             &SendError::IoError(ref e)  => Some(e),
+            #[cfg(feature = "x11")]
+            &SendError::X11(ref e)  => Some(e),
+            #[cfg(feature = "dbus")]
+            &SendError::DBus(ref e) => Some(e),
         }
     }
 }
@@ -59,18 +170,92 @@ impl std::error::Error for SendError {
 
 pub trait Frontend {
     fn send(&mut self, Rotation) -> SendResult;
+
+    /// Do any upkeep that can't wait for the next rotation (like accepting
+    /// new clients). Called every time around the main loop.
+    fn poll(&mut self) {}
+
+    /// Add anything that means `poll` has work to do (like a listening
+    /// socket) to what the main loop waits for.
+    fn wake_on(&self, _: &mut Wakeup) {}
+
+    /// Say whether the device is lying flat (and which way up) now.
+    ///
+    /// This is on top of the rotation, which stays put while it's flat, so
+    /// only frontends that have a use for it need to do anything.
+    fn send_posture(&mut self, _: Posture) -> SendResult {
+        Ok(())
+    }
+
+    /// Say whether the rotation is locked now (the rotations sent while
+    /// it is are the one it's locked at).
+    ///
+    /// The exec and socket frontends only do anything with this when asked
+    /// to (their `locks` option); the dbus one always does.
+    fn send_lock(&mut self, _: bool) -> SendResult {
+        Ok(())
+    }
 }
 
 //FIXME: why does this need display???
 pub enum FrontendKind {
     File(FileSender),
-    //X11(???),
+    Socket(SocketSender),
+    Exec(ExecSender),
+    #[cfg_attr(not(feature = "x11"), allow(dead_code))]
+    X11(XSender),
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    DBus(DBusSender),
 }
 
 impl Frontend for FrontendKind {
     fn send(&mut self, orientation: Rotation) -> SendResult {
         match self {
             &mut FrontendKind::File(ref mut s)  => s.send(orientation),
+            &mut FrontendKind::Socket(ref mut s)    => s.send(orientation),
+            &mut FrontendKind::Exec(ref mut s)  => s.send(orientation),
+            &mut FrontendKind::X11(ref mut s)   => s.send(orientation),
+            &mut FrontendKind::DBus(ref mut s)  => s.send(orientation),
+        }
+    }
+
+    fn poll(&mut self) {
+        match self {
+            &mut FrontendKind::File(ref mut s)  => s.poll(),
+            &mut FrontendKind::Socket(ref mut s)    => s.poll(),
+            &mut FrontendKind::Exec(ref mut s)  => s.poll(),
+            &mut FrontendKind::X11(ref mut s)   => s.poll(),
+            &mut FrontendKind::DBus(ref mut s)  => s.poll(),
+        }
+    }
+
+    fn wake_on(&self, wakeup: &mut Wakeup) {
+        match self {
+            &FrontendKind::File(ref s)  => s.wake_on(wakeup),
+            &FrontendKind::Socket(ref s)    => s.wake_on(wakeup),
+            &FrontendKind::Exec(ref s)  => s.wake_on(wakeup),
+            &FrontendKind::X11(ref s)   => s.wake_on(wakeup),
+            &FrontendKind::DBus(ref s)  => s.wake_on(wakeup),
+        }
+    }
+
+    fn send_posture(&mut self, posture: Posture) -> SendResult {
+        match self {
+            &mut FrontendKind::File(ref mut s)  => s.send_posture(posture),
+            &mut FrontendKind::Socket(ref mut s)    => s.send_posture(posture),
+            &mut FrontendKind::Exec(ref mut s)  => s.send_posture(posture),
+            &mut FrontendKind::X11(ref mut s)   => s.send_posture(posture),
+            &mut FrontendKind::DBus(ref mut s)  => s.send_posture(posture),
+        }
+    }
+
+    fn send_lock(&mut self, locked: bool) -> SendResult {
+        match self {
+            &mut FrontendKind::File(ref mut s)  => s.send_lock(locked),
+            &mut FrontendKind::Socket(ref mut s)    => s.send_lock(locked),
+            &mut FrontendKind::Exec(ref mut s)  => s.send_lock(locked),
+            &mut FrontendKind::X11(ref mut s)   => s.send_lock(locked),
+            &mut FrontendKind::DBus(ref mut s)  => s.send_lock(locked),
         }
     }
 }
@@ -81,6 +266,12 @@ impl std::fmt::Display for FrontendKind {
             &FrontendKind::File(ref fs)   => {
                 write!(fmt, "FileSender to {}", fs.to_string_lossy())
             },
+            &FrontendKind::Socket(ref ss)   => {
+                write!(fmt, "SocketSender on {}", ss.to_string_lossy())
+            },
+            &FrontendKind::Exec(ref es) => write!(fmt, "{}", es),
+            &FrontendKind::X11(ref xs)  => write!(fmt, "{}", xs),
+            &FrontendKind::DBus(ref ds) => write!(fmt, "{}", ds),
         }
     }
 }
@@ -88,6 +279,127 @@ impl std::fmt::Display for FrontendKind {
 #[allow(dead_code)]
 pub struct DummySender();
 
+impl Frontend for DummySender {
+    fn send(&mut self, _: Rotation) -> SendResult {
+        Ok(())
+    }
+}
+
+impl Display for DummySender {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "DummySender")
+    }
+}
+
+#[cfg(feature = "x11")]
+impl Frontend for XSender {
+    fn send(&mut self, orientation: Rotation) -> SendResult {
+        self.rotate(orientation).map_err(SendError::X11)
+    }
+}
+
+/// A frontend in use, and how sending to it has gone
+struct FrontendSlot<F = FrontendKind> {
+    frontend: F,
+    /// Whether to quit if sending to it fails
+    quit_on_error: bool,
+    /// The last rotation it was sent successfully
+    last_sent: Option<Rotation>,
+    /// Whether the last send failed
+    failing: bool,
+}
+
+/// Every frontend in use. Rotations are sent to all of them.
+pub struct Frontends<F = FrontendKind> {
+    slots: Vec<FrontendSlot<F>>,
+}
+
+impl<F: Frontend + Display> Frontends<F> {
+    /// Whether every frontend has already been sent `rotation`.
+    pub fn is_current(&self, rotation: Rotation) -> bool {
+        self.slots.iter().all(|s| Some(rotation) == s.last_sent)
+    }
+
+    /// Let every frontend do its upkeep.
+    pub fn poll(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.frontend.poll();
+        }
+    }
+
+    /// Add whatever any frontend needs to wake up for.
+    pub fn wake_on(&self, wakeup: &mut Wakeup) {
+        for slot in self.slots.iter() {
+            slot.frontend.wake_on(wakeup);
+        }
+    }
+
+    /// Send `rotation` to every frontend that hasn't had it yet.
+    ///
+    /// Errors are logged; one is only returned if it came from a frontend
+    /// that should quit on error.
+    pub fn send(&mut self, rotation: Rotation) -> SendResult {
+        for slot in self.slots.iter_mut() {
+            if Some(rotation) == slot.last_sent {
+                continue;
+            }
+            match slot.frontend.send(rotation) {
+                Ok(_)   => {
+                    if slot.failing {
+                        info!("{} is working again", slot.frontend);
+                        slot.failing = false;
+                    }
+                    slot.last_sent = Some(rotation);
+                },
+                Err(e)  => {
+                    if slot.quit_on_error {
+                        error!("Error sending rotation to {}! ({})", slot.frontend, e);
+                        return Err(e);
+                    } else if slot.failing {
+                        debug!("Still can't send rotation to {} ({})", slot.frontend, e);
+                    } else {
+                        error!("Error sending rotation to {}! ({}) Will keep trying.", slot.frontend, e);
+                        slot.failing = true;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell every frontend how the device is lying.
+    ///
+    /// Since it's only extra information, errors are just logged.
+    pub fn send_posture(&mut self, posture: Posture) {
+        for slot in self.slots.iter_mut() {
+            if let Err(e) = slot.frontend.send_posture(posture) {
+                error!("Error sending {} to {}! ({})", posture, slot.frontend, e);
+            }
+        }
+    }
+
+    /// Tell every frontend whether the rotation is locked.
+    ///
+    /// Like postures, errors are just logged.
+    pub fn send_lock(&mut self, locked: bool) {
+        for slot in self.slots.iter_mut() {
+            if let Err(e) = slot.frontend.send_lock(locked) {
+                error!("Error sending the rotation lock to {}! ({})", slot.frontend, e);
+            }
+        }
+    }
+}
+
+impl<F: Display> std::fmt::Display for Frontends<F> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, slot) in self.slots.iter().enumerate() {
+            if 0 != i { write!(fmt, ", ")?; }
+            write!(fmt, "{}", slot.frontend)?;
+        }
+        Ok(())
+    }
+}
+
 
 pub struct FileSender {
     path: PathBuf,
@@ -128,7 +440,16 @@ pub enum FrontendError {
     NotCompiled(&'static str),
     NoSuchFrontend(String),
     FileSender(IoError, PathBuf),
-    // X11(???),
+    /// Couldn't listen on the socket
+    Socket(IoError, PathBuf),
+    /// An option had a value that couldn't be used
+    BadOption(&'static str, String),
+    /// A required option wasn't set
+    MissingOption(&'static str),
+    #[cfg(feature = "x11")]
+    X11(x11::X11Error),
+    #[cfg(feature = "dbus")]
+    DBus(sensorproxy::DBusError),
 }
 
 impl Display for FrontendError {
@@ -143,6 +464,17 @@ impl Display for FrontendError {
             &FrontendError::FileSender(ref e, ref p)    => {
                 write!(fmt, "can't use file '{}' ({})", p.to_string_lossy(), e)
             },
+            &FrontendError::Socket(ref e, ref p)    => {
+                write!(fmt, "can't listen on socket '{}' ({})", p.to_string_lossy(), e)
+            },
+            &FrontendError::BadOption(o, ref v) => {
+                write!(fmt, "bad value '{}' for option {}", v, o)
+            },
+            &FrontendError::MissingOption(o)    => write!(fmt, "option {} is required", o),
+            #[cfg(feature = "x11")]
+            &FrontendError::X11(ref e)  => write!(fmt, "can't use X11 ({})", e),
+            #[cfg(feature = "dbus")]
+            &FrontendError::DBus(ref e) => write!(fmt, "can't export on D-Bus ({})", e),
         }
     }
 }
@@ -157,12 +489,22 @@ impl std::error::Error for FrontendError {
             &FrontendError::NotCompiled(_)  => None,
             &FrontendError::NoSuchFrontend(_)   => None,
             &FrontendError::FileSender(ref e, _)   => Some(e),
+            &FrontendError::Socket(ref e, _)    => Some(e),
+            &FrontendError::BadOption(_, _) => None,
+            &FrontendError::MissingOption(_)    => None,
+            #[cfg(feature = "x11")]
+            &FrontendError::X11(ref e)  => Some(e),
+            #[cfg(feature = "dbus")]
+            &FrontendError::DBus(ref e) => Some(e),
         }
     }
 }
 
 type InitResult<T> = Result<T, FrontendError>;
 
+/// Starts a frontend, given its name and options
+type InitFn<F> = fn(String, HashMap<String, String>) -> InitResult<F>;
+
 macro_rules! frinit {
     ( $tomatch:ident, $opts:ident: $( $name:expr, $init:ident );+ $(;)* ) => {
         match $tomatch.as_str() {
@@ -177,28 +519,281 @@ macro_rules! frinit {
         }
     }
 }
-/// Initialize a frontend
-pub fn init_frontend() -> Result<FrontendKind, i32> {
-    let (frontends, mut opts) = get_frontend_options();
-    for frontend in frontends {
-        let last_output = frinit!(frontend, opts:
-            // "x11", init_x11;
-            "file", init_file;
-            );
-        match last_output {
-            Ok(o)   => return Ok(o),
-            Err(e)  => warn!("Error initializing backend: {}", e),
+/// Initialize every group of frontends.
+///
+/// Fails only if none of them can be started.
+pub fn init_frontends() -> Result<Frontends, i32> {
+    start_frontends(get_frontend_options(), init_frontend).ok_or(ERR_NO_FRONTEND)
+}
+
+/// Start every group of frontends with `init`, or `None` if none of them
+/// start.
+fn start_frontends<F>(groups: Vec<Vec<Spec>>, init: InitFn<F>) -> Option<Frontends<F>> {
+    let mut slots = Vec::new();
+    for group in groups {
+        let names: Vec<String> = group.iter().map(|f| f.0.to_owned()).collect();
+        match init_group(group, init) {
+            Some(s) => slots.push(s),
+            None    => error!("Couldn't start any of {}", names.join("|")),
+        }
+    }
+    if slots.is_empty() {
+        return None;
+    }
+    Some(Frontends { slots })
+}
+
+/// Initialize the first frontend in a group of alternatives that works.
+fn init_group<F>(group: Vec<Spec>, init: InitFn<F>) -> Option<FrontendSlot<F>> {
+    for (frontend, fopts) in group {
+        let quit_on_error = match fopts.get("quit_on_error") {
+            Some(v) => match v.parse::<bool>() {
+                Ok(b)   => b,
+                Err(_)  => {
+                    warn!("Error initializing frontend: {}",
+                          FrontendError::BadOption("quit_on_error", v.to_owned()));
+                    continue;
+                },
+            },
+            None    => quit_on_rotation_send_error(),
+        };
+        match init(frontend, fopts) {
+            Ok(f)   => return Some(FrontendSlot {
+                frontend: f,
+                quit_on_error,
+                last_sent: None,
+                failing: false,
+            }),
+            Err(e)  => warn!("Error initializing frontend: {}", e),
         }
     }
-    return Err(ERR_NO_FRONTEND);
+    None
+}
+
+/// Initialize a single frontend.
+fn init_frontend(frontend: String, fopts: HashMap<String, String>) -> InitResult<FrontendKind> {
+    let mut opts = HashMap::new();
+    opts.insert(frontend.to_owned(), fopts);
+    frinit!(frontend, opts:
+        "x11", init_x11;
+        "file", init_file;
+        "socket", init_socket;
+        "exec", init_exec;
+        "dbus", init_dbus;
+        )
 }
 
 fn init_file(opts: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
-    let def = DEFAULT_SPINFILE.to_owned();
+    let def = arg_value("spinfile").unwrap_or_else(|| DEFAULT_SPINFILE.to_owned());
     Ok(FrontendKind::File(FileSender::init(
         PathBuf::from(parse_path(opts.get("path").unwrap_or(&def),false))
         )?))
 }
 
+fn init_socket(opts: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    let path = opts.get("path").map_or(socket::DEFAULT_SOCKET, |p| p.as_str());
+    let mode = match opts.get("mode") {
+        Some(m) => u32::from_str_radix(m, 8)
+            .map_err(|_| FrontendError::BadOption("mode", m.to_owned()))?,
+        None    => socket::DEFAULT_SOCKET_MODE,
+    };
+    let locks = match opts.get("locks") {
+        Some(l) => l.parse::<bool>()
+            .map_err(|_| FrontendError::BadOption("locks", l.to_owned()))?,
+        None    => false,
+    };
+    Ok(FrontendKind::Socket(SocketSender::init(
+        PathBuf::from(parse_path(path, false)), mode, locks)?))
+}
+
+fn init_exec(opts: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    let command = opts.get("command").ok_or(FrontendError::MissingOption("command"))?;
+    let timeout = match opts.get("timeout") {
+        Some(t) => t.parse::<u64>()
+            .map_err(|_| FrontendError::BadOption("timeout", t.to_owned()))?,
+        None    => exec::DEFAULT_EXEC_TIMEOUT,
+    };
+    let postures = match opts.get("postures") {
+        Some(p) => p.parse::<bool>()
+            .map_err(|_| FrontendError::BadOption("postures", p.to_owned()))?,
+        None    => false,
+    };
+    let locks = match opts.get("locks") {
+        Some(l) => l.parse::<bool>()
+            .map_err(|_| FrontendError::BadOption("locks", l.to_owned()))?,
+        None    => false,
+    };
+    Ok(FrontendKind::Exec(ExecSender::init(command, Duration::from_millis(timeout), postures, locks)?))
+}
+
+#[cfg(feature = "x11")]
+fn init_x11(opts: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    Ok(FrontendKind::X11(XSender::init(opts).map_err(FrontendError::X11)?))
+}
+#[cfg(not(feature = "x11"))]
+fn init_x11(_: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    Err(FrontendError::NotCompiled("x11"))
+}
+
+#[cfg(feature = "dbus")]
+fn init_dbus(opts: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    let bus = match opts.get("bus") {
+        Some(b) => b.parse::<sensorproxy::Bus>()
+            .map_err(|_| FrontendError::BadOption("bus", b.to_owned()))?,
+        None    => sensorproxy::Bus::System,
+    };
+    let name = opts.get("name").map_or(sensorproxy::DEFAULT_BUS_NAME, |n| n.as_str());
+    Ok(FrontendKind::DBus(DBusSender::init(bus, name.to_owned())
+                          .map_err(FrontendError::DBus)?))
+}
+#[cfg(not(feature = "dbus"))]
+fn init_dbus(_: &mut HashMap<String, String>) -> InitResult<FrontendKind> {
+    Err(FrontendError::NotCompiled("dbus"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    thread_local! {
+        /// The stub frontends that don't work at the moment
+        static BROKEN: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+        /// What each stub frontend has been sent
+        static SENT: RefCell<HashMap<String, Vec<Rotation>>> = RefCell::new(HashMap::new());
+    }
+
+    fn broken(name: &str) -> bool {
+        BROKEN.with(|b| b.borrow().contains(name))
+    }
+
+    fn set_broken(name: &str, is_broken: bool) {
+        BROKEN.with(|b| if is_broken {
+            b.borrow_mut().insert(name.to_owned());
+        } else {
+            b.borrow_mut().remove(name);
+        });
+    }
+
+    fn sent(name: &str) -> Vec<Rotation> {
+        SENT.with(|s| s.borrow().get(name).cloned().unwrap_or_default())
+    }
+
+    /// A frontend that remembers what it's sent, unless it's broken.
+    struct StubFrontend(String);
+
+    impl Frontend for StubFrontend {
+        fn send(&mut self, rotation: Rotation) -> SendResult {
+            if broken(&self.0) {
+                return Err(SendError::IoError(IoError::other("broken")));
+            }
+            SENT.with(|s| s.borrow_mut().entry(self.0.clone()).or_default().push(rotation));
+            Ok(())
+        }
+    }
+
+    impl Display for StubFrontend {
+        fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(fmt, "{}", self.0)
+        }
+    }
+
+    fn init_stub(name: String, _opts: HashMap<String, String>) -> InitResult<StubFrontend> {
+        if broken(&name) {
+            return Err(FrontendError::NoSuchFrontend(name));
+        }
+        Ok(StubFrontend(name))
+    }
+
+    /// Start stub frontends from a `--frontend` list.
+    fn start(frontends: &str) -> Option<Frontends<StubFrontend>> {
+        start_frontends(frontend_groups(frontends, &HashMap::new()), init_stub)
+    }
+
+    fn opts(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())).collect()
+    }
+
+    #[test]
+    fn parses_groups() {
+        let mut optmap = HashMap::new();
+        optmap.insert("file".to_owned(), opts(&[("path", "/b"), ("quit_on_error", "true")]));
+        optmap.insert("socket".to_owned(), opts(&[("locks", "true")]));
+        assert_eq!(vec![
+            vec![("file".to_owned(), opts(&[("path", "/a"), ("quit_on_error", "true")]))],
+            vec![("x11".to_owned(), opts(&[])),
+                 ("socket".to_owned(), opts(&[("mode", "600"), ("locks", "true")]))],
+            vec![("exec".to_owned(), opts(&[("command", "a;b|c")]))],
+        ], frontend_groups(r"file,path=/a; x11|socket,mode=600;exec,command=a\;b\|c;;", &optmap));
+    }
+
+    #[test]
+    fn fans_out_and_fails_over() {
+        set_broken("a", true);
+        let mut frontends = start("a|b;c;d|e").unwrap();
+        assert_eq!("b, c, d", frontends.to_string());
+
+        frontends.send(Rotation::Left).unwrap();
+        assert!(frontends.is_current(Rotation::Left));
+        for name in &["b", "c", "d"] {
+            assert_eq!(vec![Rotation::Left], sent(name));
+        }
+        assert!(sent("a").is_empty() && sent("e").is_empty());
+
+        set_broken("b", true);
+        set_broken("c", true);
+        assert!(start("a|b;c").is_none());
+    }
 
+    #[test]
+    fn quit_on_error_per_slot() {
+        let mut frontends = start("a,quit_on_error=true;b").unwrap();
+        set_broken("b", true);
+        assert!(frontends.send(Rotation::Left).is_ok());
+        set_broken("a", true);
+        assert!(frontends.send(Rotation::Right).is_err());
 
+        // A bad value skips that alternative.
+        set_broken("a", false);
+        let frontends = start("a,quit_on_error=maybe|c").unwrap();
+        assert_eq!("c", frontends.to_string());
+        assert!(!frontends.slots[0].quit_on_error);
+    }
+
+    #[test]
+    fn skips_slots_already_sent() {
+        let mut frontends = start("a;b").unwrap();
+        set_broken("b", true);
+        frontends.send(Rotation::Left).unwrap();
+        assert!(!frontends.is_current(Rotation::Left));
+
+        // Only the one that missed it gets it again.
+        set_broken("b", false);
+        frontends.send(Rotation::Left).unwrap();
+        assert!(frontends.is_current(Rotation::Left));
+        assert_eq!(vec![Rotation::Left], sent("a"));
+        assert_eq!(vec![Rotation::Left], sent("b"));
+
+        frontends.send(Rotation::Left).unwrap();
+        assert_eq!(vec![Rotation::Left], sent("a"));
+    }
+
+    #[test]
+    fn working_again() {
+        let mut frontends = start("a").unwrap();
+        set_broken("a", true);
+        frontends.send(Rotation::Left).unwrap();
+        assert!(frontends.slots[0].failing);
+        frontends.send(Rotation::Right).unwrap();
+        assert!(frontends.slots[0].failing);
+        assert_eq!(None, frontends.slots[0].last_sent);
+
+        set_broken("a", false);
+        frontends.send(Rotation::Right).unwrap();
+        assert!(!frontends.slots[0].failing);
+        assert_eq!(Some(Rotation::Right), frontends.slots[0].last_sent);
+        assert_eq!(vec![Rotation::Right], sent("a"));
+    }
+}
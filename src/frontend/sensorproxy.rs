@@ -0,0 +1,420 @@
+//! # sensorproxy
+//! A frontend that pretends to be iio-sensor-proxy on D-Bus, so desktops
+//! that already follow `net.hadess.SensorProxy` (GNOME, KDE, ...) rotate
+//! to spinnrd's orientation instead of reading the accelerometer
+//! themselves.
+
+use super::*;
+
+use std::os::unix::io::RawFd;
+use std::str::FromStr;
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::LocalConnection;
+use dbus::blocking::stdintf::org_freedesktop_dbus::{PropertiesPropertiesChanged, RequestNameReply};
+use dbus::channel::{BusType, Channel};
+use dbus::message::SignalArgs;
+use dbus::Path as DBusPath;
+use dbus_crossroads::{Crossroads, IfaceBuilder};
+
+/// The well-known name iio-sensor-proxy owns
+pub const DEFAULT_BUS_NAME: &str = "net.hadess.SensorProxy";
+
+/// The interface and object iio-sensor-proxy exports
+const INTERFACE: &str = "net.hadess.SensorProxy";
+const OBJECT_PATH: &str = "/net/hadess/SensorProxy";
+
+/// spinnrd's own interface on the same object, for what iio-sensor-proxy
+/// doesn't have
+const SPINNRD_INTERFACE: &str = "io.github.rantingpirate.Spinnrd";
+
+/// What `AccelerometerOrientation` is before the first rotation
+const UNDEFINED: &str = "undefined";
+
+/// Which bus to export the interface on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    System,
+    Session,
+}
+
+impl FromStr for Bus {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Bus, ()> {
+        match s.trim().to_lowercase().as_str() {
+            "system"    => Ok(Bus::System),
+            "session"   => Ok(Bus::Session),
+            _   => Err(()),
+        }
+    }
+}
+
+impl Display for Bus {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Bus::System     => write!(fmt, "system"),
+            Bus::Session    => write!(fmt, "session"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DBusError {
+    /// Couldn't connect to the bus
+    Connect(dbus::Error),
+    /// Couldn't ask for the bus name
+    RequestName(String, dbus::Error),
+    /// Something else (probably iio-sensor-proxy) owns the bus name
+    NameTaken(String),
+    /// The bus hung up
+    Disconnected,
+}
+
+impl Display for DBusError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DBusError::Connect(e)   => write!(fmt, "can't connect to D-Bus ({})", e),
+            DBusError::RequestName(n, e)    => {
+                write!(fmt, "can't request bus name {} ({})", n, e)
+            },
+            DBusError::NameTaken(n) => {
+                write!(fmt, "bus name {} is already owned (is iio-sensor-proxy running?)", n)
+            },
+            DBusError::Disconnected => write!(fmt, "disconnected from D-Bus"),
+        }
+    }
+}
+
+impl std::error::Error for DBusError {
+    fn description(&self) -> &str {
+        "D-Bus error"
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DBusError::Connect(e)   => Some(e),
+            DBusError::RequestName(_, e)    => Some(e),
+            _   => None,
+        }
+    }
+}
+
+/// The state behind the exported object
+struct ProxyState {
+    /// The current `AccelerometerOrientation`
+    orientation: &'static str,
+    /// The current `RotationLocked`
+    locked: bool,
+}
+
+/// Exports `net.hadess.SensorProxy` with spinnrd's rotation as the
+/// accelerometer orientation.
+///
+/// Only the accelerometer is "present"; the light and proximity parts of
+/// the interface are there so clients that look for them don't get errors.
+/// Claims don't change anything, since spinnrd is reading the accelerometer
+/// anyway. Whether the rotation is locked is the `RotationLocked` property
+/// of spinnrd's own interface on the same object.
+pub struct DBusSender {
+    bus: Bus,
+    name: String,
+    conn: LocalConnection,
+    cr: Crossroads,
+}
+
+impl DBusSender {
+    pub fn init(bus: Bus, name: String) -> Result<DBusSender, DBusError> {
+        let conn = connect(bus, &name)?;
+        let mut cr = Crossroads::new();
+        let iface = cr.register(INTERFACE, build_interface);
+        let lock_iface = cr.register(SPINNRD_INTERFACE, build_lock_interface);
+        cr.insert(OBJECT_PATH, &[iface, lock_iface],
+                  ProxyState { orientation: UNDEFINED, locked: false });
+        Ok(DBusSender { bus, name, conn, cr })
+    }
+
+    /// Handle every method call that's waiting, without blocking.
+    fn dispatch(&mut self) -> Result<(), DBusError> {
+        let channel = self.conn.channel();
+        channel.read_write(Some(Duration::from_millis(0)))
+            .map_err(|_| DBusError::Disconnected)?;
+        while let Some(msg) = channel.pop_message() {
+            // Anything that isn't a method call (like NameAcquired) is
+            // ignored.
+            let _ = self.cr.handle_message(msg, channel);
+        }
+        Ok(())
+    }
+
+    /// Connect again if the bus hung up.
+    fn reconnect(&mut self) -> Result<(), DBusError> {
+        if self.conn.channel().is_connected() {
+            return Ok(());
+        }
+        self.conn = connect(self.bus, &self.name)?;
+        info!("Reconnected to the {} bus as {}", self.bus, self.name);
+        Ok(())
+    }
+
+    pub fn rotate(&mut self, rotation: Rotation) -> Result<(), DBusError> {
+        self.reconnect()?;
+        let orientation = orientation(rotation);
+        let path = DBusPath::from(OBJECT_PATH);
+        if let Some(state) = self.cr.data_mut::<ProxyState>(&path) {
+            state.orientation = orientation;
+        }
+        self.changed(INTERFACE, "AccelerometerOrientation", Box::new(orientation.to_owned()))
+    }
+
+    pub fn set_locked(&mut self, locked: bool) -> Result<(), DBusError> {
+        self.reconnect()?;
+        let path = DBusPath::from(OBJECT_PATH);
+        match self.cr.data_mut::<ProxyState>(&path) {
+            Some(ref state) if locked == state.locked   => return Ok(()),
+            Some(state) => state.locked = locked,
+            None    => {},
+        }
+        self.changed(SPINNRD_INTERFACE, "RotationLocked", Box::new(locked))
+    }
+
+    /// Tell everyone listening that a property changed.
+    fn changed(&self, interface: &str, property: &str, value: Box<dyn RefArg>)
+        -> Result<(), DBusError>
+    {
+        let mut changed = PropMap::new();
+        changed.insert(property.to_owned(), Variant(value));
+        let signal = PropertiesPropertiesChanged {
+            interface_name: interface.to_owned(),
+            changed_properties: changed,
+            invalidated_properties: Vec::new(),
+        };
+        let channel = self.conn.channel();
+        channel.send(signal.to_emit_message(&DBusPath::from(OBJECT_PATH)))
+            .map_err(|_| DBusError::Disconnected)?;
+        channel.flush();
+        Ok(())
+    }
+}
+
+impl Frontend for DBusSender {
+    fn send(&mut self, rotation: Rotation) -> SendResult {
+        self.rotate(rotation).map_err(SendError::DBus)
+    }
+
+    fn send_lock(&mut self, locked: bool) -> SendResult {
+        self.set_locked(locked).map_err(SendError::DBus)
+    }
+
+    fn poll(&mut self) {
+        if let Err(e) = self.dispatch() {
+            // Sending reconnects, so don't make a fuss until then.
+            debug!("Can't handle D-Bus calls ({})", e);
+        }
+    }
+
+    fn wake_on(&self, wakeup: &mut Wakeup) {
+        wakeup.fd(watch_fd(&self.conn));
+    }
+}
+
+impl Display for DBusSender {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "DBusSender as {} on the {} bus", self.name, self.bus)
+    }
+}
+
+/// Connect to the bus and take the name.
+fn connect(bus: Bus, name: &str) -> Result<LocalConnection, DBusError> {
+    let mut channel = Channel::get_private(match bus {
+        Bus::System     => BusType::System,
+        Bus::Session    => BusType::Session,
+    }).map_err(DBusError::Connect)?;
+    // So there's a file descriptor for the main loop to wait on
+    channel.set_watch_enabled(true);
+    let conn = LocalConnection::from(channel);
+    match conn.request_name(name, false, false, true) {
+        Ok(RequestNameReply::PrimaryOwner)
+            | Ok(RequestNameReply::AlreadyOwner)    => Ok(conn),
+        Ok(_)   => Err(DBusError::NameTaken(name.to_owned())),
+        Err(e)  => Err(DBusError::RequestName(name.to_owned(), e)),
+    }
+}
+
+/// The file descriptor that's readable when there's something to handle.
+fn watch_fd(conn: &LocalConnection) -> RawFd {
+    conn.channel().watch().fd
+}
+
+/// The orientation iio-sensor-proxy would report for a rotation.
+///
+/// iio-sensor-proxy names the edge of the device that's pointing up, which
+/// is the side the top of the screen has to turn towards.
+fn orientation(rotation: Rotation) -> &'static str {
+    match rotation {
+        Rotation::Normal    => "normal",
+        Rotation::Left      => "left-up",
+        Rotation::Right     => "right-up",
+        Rotation::Inverted  => "bottom-up",
+    }
+}
+
+fn build_interface(b: &mut IfaceBuilder<ProxyState>) {
+    b.property("HasAccelerometer").get(|_, _| Ok(true)).emits_changed_const();
+    b.property("AccelerometerOrientation")
+        .get(|_, state: &mut ProxyState| Ok(state.orientation.to_owned()));
+    b.property("HasAmbientLight").get(|_, _| Ok(false)).emits_changed_const();
+    b.property("LightLevelUnit").get(|_, _| Ok("lux".to_owned())).emits_changed_const();
+    b.property("LightLevel").get(|_, _| Ok(0f64)).emits_changed_const();
+    b.property("HasProximity").get(|_, _| Ok(false)).emits_changed_const();
+    b.property("ProximityNear").get(|_, _| Ok(false)).emits_changed_const();
+
+    b.method("ClaimAccelerometer", (), (), |ctx, _, _: ()| {
+        debug!("{} claimed the accelerometer",
+               ctx.message().sender().map_or("someone".to_owned(), |s| s.to_string()));
+        Ok(())
+    });
+    b.method("ReleaseAccelerometer", (), (), |ctx, _, _: ()| {
+        debug!("{} released the accelerometer",
+               ctx.message().sender().map_or("someone".to_owned(), |s| s.to_string()));
+        Ok(())
+    });
+    for sensor in &["Light", "Proximity"] {
+        b.method(format!("Claim{}", sensor), (), (), |_, _, _: ()| Ok(()));
+        b.method(format!("Release{}", sensor), (), (), |_, _, _: ()| Ok(()));
+    }
+}
+
+fn build_lock_interface(b: &mut IfaceBuilder<ProxyState>) {
+    b.property("RotationLocked").get(|_, state: &mut ProxyState| Ok(state.locked));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+    use testbus::session_bus;
+
+    enum Event {
+        Rotate(Rotation),
+        Lock(bool),
+    }
+
+    /// Run a `DBusSender` on its own thread (so blocking calls to it get
+    /// answered), sending it whatever comes down the channel.
+    fn serve(name: &'static str) -> mpsc::Sender<Event> {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut sender = DBusSender::init(Bus::Session, name.to_owned()).unwrap();
+            ready_tx.send(()).unwrap();
+            loop {
+                sender.poll();
+                match rx.recv_timeout(Duration::from_millis(5)) {
+                    Ok(Event::Rotate(r))    => sender.send(r).unwrap(),
+                    Ok(Event::Lock(l))  => sender.send_lock(l).unwrap(),
+                    Err(mpsc::RecvTimeoutError::Timeout)    => {},
+                    Err(mpsc::RecvTimeoutError::Disconnected)   => break,
+                }
+            }
+        });
+        ready_rx.recv().unwrap();
+        tx
+    }
+
+    #[test]
+    fn looks_like_sensorproxy() {
+        if !session_bus() { return }
+        const NAME: &str = "net.hadess.SensorProxy.FrontendTest";
+        let rotate = serve(NAME);
+        let client = LocalConnection::new_session().unwrap();
+        let proxy = client.with_proxy(NAME, OBJECT_PATH, Duration::from_secs(1));
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let c = changes.clone();
+        client.add_match(PropertiesPropertiesChanged::match_rule(Some(&NAME.into()),
+                                                                 Some(&OBJECT_PATH.into()))
+                         .static_clone(),
+                         move |p: PropertiesPropertiesChanged, _, _| {
+            if let Some(o) = p.changed_properties.get("AccelerometerOrientation") {
+                c.borrow_mut().push(o.as_str().unwrap().to_owned());
+            }
+            true
+        }).unwrap();
+
+        assert!(proxy.get::<bool>(INTERFACE, "HasAccelerometer").unwrap());
+        proxy.method_call::<(), _, _, _>(INTERFACE, "ClaimAccelerometer", ()).unwrap();
+        assert_eq!(UNDEFINED, proxy.get::<String>(INTERFACE, "AccelerometerOrientation").unwrap());
+
+        let sent = [(Rotation::Left, "left-up"), (Rotation::Inverted, "bottom-up"),
+                    (Rotation::Right, "right-up"), (Rotation::Normal, "normal")];
+        for &(rotation, _) in &sent {
+            rotate.send(Event::Rotate(rotation)).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while changes.borrow().len() < sent.len() && Instant::now() < deadline {
+            client.process(Duration::from_millis(50)).unwrap();
+        }
+        let expected: Vec<String> = sent.iter().map(|s| s.1.to_owned()).collect();
+        assert_eq!(expected, *changes.borrow());
+        assert_eq!("normal", proxy.get::<String>(INTERFACE, "AccelerometerOrientation").unwrap());
+        proxy.method_call::<(), _, _, _>(INTERFACE, "ReleaseAccelerometer", ()).unwrap();
+    }
+
+    #[test]
+    fn reports_the_lock() {
+        if !session_bus() { return }
+        const NAME: &str = "net.hadess.SensorProxy.LockTest";
+        let lock = serve(NAME);
+        let client = LocalConnection::new_session().unwrap();
+        let proxy = client.with_proxy(NAME, OBJECT_PATH, Duration::from_secs(1));
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let c = changes.clone();
+        client.add_match(PropertiesPropertiesChanged::match_rule(Some(&NAME.into()),
+                                                                 Some(&OBJECT_PATH.into()))
+                         .static_clone(),
+                         move |p: PropertiesPropertiesChanged, _, _| {
+            if let Some(l) = p.changed_properties.get("RotationLocked") {
+                assert_eq!(SPINNRD_INTERFACE, p.interface_name);
+                c.borrow_mut().push(l.as_i64().unwrap() != 0);
+            }
+            true
+        }).unwrap();
+
+        assert!(!proxy.get::<bool>(SPINNRD_INTERFACE, "RotationLocked").unwrap());
+        // Telling it again what it already knows doesn't say anything.
+        for &locked in &[true, true, false] {
+            lock.send(Event::Lock(locked)).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while changes.borrow().len() < 2 && Instant::now() < deadline {
+            client.process(Duration::from_millis(50)).unwrap();
+        }
+        client.process(Duration::from_millis(100)).unwrap();
+        assert_eq!(vec![true, false], *changes.borrow());
+        lock.send(Event::Lock(true)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !proxy.get::<bool>(SPINNRD_INTERFACE, "RotationLocked").unwrap() {
+            assert!(Instant::now() < deadline, "never locked");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn wont_share_name() {
+        if !session_bus() { return }
+        const NAME: &str = "net.hadess.SensorProxy.NameTest";
+        let _first = serve(NAME);
+        match DBusSender::init(Bus::Session, NAME.to_owned()) {
+            Err(DBusError::NameTaken(n))    => assert_eq!(NAME, n),
+            Err(e)  => panic!("wrong error: {}", e),
+            Ok(_)   => panic!("took a name that was already owned"),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! # socket
+//! A frontend that broadcasts rotations to every client of a Unix domain
+//! socket, one line per rotation (and, if asked, per lock or unlock).
+
+use super::*;
+
+use std::fs::Permissions;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+/// The default socket
+pub const DEFAULT_SOCKET: &str = "%d/spinnrd.sock";
+
+/// The default permissions of the socket (clients need write permission to
+/// connect)
+pub const DEFAULT_SOCKET_MODE: u32 = 0o666;
+
+/// Sends rotations to everything connected to a socket.
+///
+/// New clients are sent the current rotation as soon as they connect. With
+/// `locks`, they're also sent `locked` or `unlocked` whenever that changes
+/// (and when they connect).
+pub struct SocketSender {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<UnixStream>,
+    /// The last rotation sent, for new clients
+    current: Option<Rotation>,
+    /// Whether to send whether the rotation's locked as well
+    locks: bool,
+    /// Whether the rotation's locked, once it's been said
+    locked: Option<bool>,
+}
+
+impl SocketSender {
+    pub fn init(path: PathBuf, mode: u32, locks: bool) -> InitResult<SocketSender> {
+        let listener = remove_stale_socket(&path)
+            .and_then(|_| bind(&path, mode))
+            .map_err(|e| FrontendError::Socket(e, path.clone()))?;
+        Ok(SocketSender {
+            path,
+            listener,
+            clients: Vec::new(),
+            current: None,
+            locks,
+            locked: None,
+        })
+    }
+
+    /// Accept every client that's waiting, and tell them the current
+    /// rotation (and lock).
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((client, _)) => {
+                    if let Err(e) = client.set_nonblocking(true) {
+                        warn!("Dropping client of {} ({})", self.path.to_string_lossy(), e);
+                        continue;
+                    }
+                    let mut greeting = Vec::new();
+                    greeting.extend(self.current.map(|r| r.to_string()));
+                    greeting.extend(self.locked.map(|l| lock_line(l).to_owned()));
+                    if let Err(e) = greeting.iter().try_for_each(|l| write_line(&client, l)) {
+                        debug!("Dropping client of {} ({})", self.path.to_string_lossy(), e);
+                        continue;
+                    }
+                    debug!("New client of {}", self.path.to_string_lossy());
+                    self.clients.push(client);
+                },
+                Err(ref e) if IoErrorKind::WouldBlock == e.kind() => break,
+                Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+                Err(e)  => {
+                    warn!("Couldn't accept client of {} ({})", self.path.to_string_lossy(), e);
+                    break;
+                },
+            }
+        }
+    }
+
+    pub fn to_string_lossy(&self) -> std::borrow::Cow<'_, str> {
+        self.path.to_string_lossy()
+    }
+
+    /// Send a line to every client, dropping any that can't take it.
+    fn broadcast(&mut self, line: &str) {
+        let path = &self.path;
+        self.clients.retain(|c| match write_line(c, line) {
+            Ok(_)   => true,
+            Err(e)  => {
+                debug!("Dropping client of {} ({})", path.to_string_lossy(), e);
+                false
+            },
+        });
+    }
+}
+
+impl Frontend for SocketSender {
+    fn send(&mut self, rotation: Rotation) -> SendResult {
+        self.current = Some(rotation);
+        self.broadcast(&rotation.to_string());
+        Ok(())
+    }
+
+    fn send_lock(&mut self, locked: bool) -> SendResult {
+        if ! self.locks || Some(locked) == self.locked {
+            return Ok(());
+        }
+        self.locked = Some(locked);
+        self.broadcast(lock_line(locked));
+        Ok(())
+    }
+
+    fn poll(&mut self) {
+        // Clients aren't expected to say anything, so anything readable is
+        // either ignored or a hangup.
+        let mut buf = [0u8; 64];
+        let path = &self.path;
+        self.clients.retain(|c| {
+            let alive = is_connected(c, &mut buf);
+            if ! alive {
+                debug!("Client of {} hung up", path.to_string_lossy());
+            }
+            alive
+        });
+        self.accept();
+    }
+
+    fn wake_on(&self, wakeup: &mut Wakeup) {
+        wakeup.fd(self.listener.as_raw_fd());
+        for client in self.clients.iter() {
+            wakeup.fd(client.as_raw_fd());
+        }
+    }
+}
+
+impl Drop for SocketSender {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Couldn't remove {} ({})", self.path.to_string_lossy(), e);
+        }
+    }
+}
+
+/// Listen on a socket with the given permissions.
+fn bind(path: &Path, mode: u32) -> Result<UnixListener, IoError> {
+    // Created with the right permissions, so nobody else can connect before
+    // they're set.
+    let umask = unsafe { libc::umask(!mode & 0o777) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    let listener = listener?;
+    listener.set_nonblocking(true)?;
+    std::fs::set_permissions(path, Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// What's sent when the rotation's locked or unlocked
+fn lock_line(locked: bool) -> &'static str {
+    if locked { "locked" } else { "unlocked" }
+}
+
+/// Write a line, without waiting.
+///
+/// A client that isn't reading fast enough to take a whole line is treated
+/// as dead.
+fn write_line(mut client: &UnixStream, line: &str) -> Result<(), IoError> {
+    let line = format!("{}\n", line);
+    match client.write(line.as_bytes()) {
+        Ok(n) if n == line.len()    => Ok(()),
+        Ok(_)   => Err(IoError::new(IoErrorKind::WriteZero, "client isn't keeping up")),
+        Err(e)  => Err(e),
+    }
+}
+
+/// Whether a client is still connected, discarding anything it's sent.
+fn is_connected(mut client: &UnixStream, buf: &mut [u8]) -> bool {
+    loop {
+        match client.read(buf) {
+            Ok(0)   => return false,
+            Ok(_)   => continue,
+            Err(ref e) if IoErrorKind::WouldBlock == e.kind() => return true,
+            Err(ref e) if IoErrorKind::Interrupted == e.kind() => continue,
+            Err(_)  => return false,
+        }
+    }
+}
+
+/// Remove a socket left behind by a spinnrd that didn't exit cleanly.
+///
+/// Sockets something's still listening on, and anything that isn't a
+/// socket, are left alone (so binding fails).
+pub fn remove_stale_socket(path: &Path) -> Result<(), IoError> {
+    match std::fs::symlink_metadata(path) {
+        Ok(ref m) if m.file_type().is_socket()  => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(IoError::new(IoErrorKind::AddrInUse,
+                                        "something is already listening on it"));
+            }
+            debug!("Removing stale socket {}", path.to_string_lossy());
+            std::fs::remove_file(path)
+        },
+        _   => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use testutil::TempDir;
+
+    fn read_lines(client: &UnixStream, n: usize) -> Vec<String> {
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(client);
+        (0..n).map(|_| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            line.trim_end().to_owned()
+        }).collect()
+    }
+
+    #[test]
+    fn sends_locks_when_asked() {
+        let dir = TempDir::new("socket");
+        let path = dir.join("spinnrd.sock");
+        let mut sender = SocketSender::init(path.clone(), DEFAULT_SOCKET_MODE, true).unwrap();
+        sender.send(Rotation::Left).unwrap();
+        sender.send_lock(true).unwrap();
+        let early = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        assert_eq!(vec!["left", "locked"], read_lines(&early, 2));
+
+        // Saying the same thing again doesn't send anything.
+        sender.send_lock(true).unwrap();
+        sender.send_lock(false).unwrap();
+        sender.send(Rotation::Right).unwrap();
+        assert_eq!(vec!["unlocked", "right"], read_lines(&early, 2));
+    }
+
+    #[test]
+    fn only_rotations_by_default() {
+        let dir = TempDir::new("socket");
+        let path = dir.join("spinnrd.sock");
+        let mut sender = SocketSender::init(path.clone(), DEFAULT_SOCKET_MODE, false).unwrap();
+        let client = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        sender.send_lock(true).unwrap();
+        sender.send(Rotation::Inverted).unwrap();
+        assert_eq!(vec!["inverted"], read_lines(&client, 1));
+    }
+
+    #[test]
+    fn binds_with_mode() {
+        let dir = TempDir::new("socket");
+        let path = dir.join("spinnrd.sock");
+        let _sender = SocketSender::init(path.clone(), 0o600, false).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn drops_clients_that_hang_up() {
+        let dir = TempDir::new("socket");
+        let path = dir.join("spinnrd.sock");
+        let mut sender = SocketSender::init(path.clone(), DEFAULT_SOCKET_MODE, false).unwrap();
+        let stays = UnixStream::connect(&path).unwrap();
+        let leaves = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        assert_eq!(2, sender.clients.len());
+
+        // Noticed when polling...
+        drop(leaves);
+        sender.poll();
+        assert_eq!(1, sender.clients.len());
+
+        // ...or when sending.
+        let leaves = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        assert_eq!(2, sender.clients.len());
+        drop(leaves);
+        sender.send(Rotation::Left).unwrap();
+        assert_eq!(1, sender.clients.len());
+        assert_eq!(vec!["left"], read_lines(&stays, 1));
+    }
+
+    #[test]
+    fn drops_clients_that_stop_reading() {
+        let dir = TempDir::new("socket");
+        let path = dir.join("spinnrd.sock");
+        let mut sender = SocketSender::init(path.clone(), DEFAULT_SOCKET_MODE, false).unwrap();
+        let _stuck = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        assert_eq!(1, sender.clients.len());
+
+        // Once its buffer fills up, it's dropped rather than waited for.
+        let start = Instant::now();
+        let mut sends = 0;
+        while !sender.clients.is_empty() {
+            sender.send(if 0 == sends % 2 { Rotation::Left } else { Rotation::Right }).unwrap();
+            sends += 1;
+            assert!(sends < 10_000_000, "client never dropped");
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "sending blocked");
+
+        // And a new client still gets the rotation.
+        let client = UnixStream::connect(&path).unwrap();
+        sender.poll();
+        let last = if 0 == sends % 2 { "right" } else { "left" };
+        assert_eq!(vec![last], read_lines(&client, 1));
+    }
+}
@@ -0,0 +1,483 @@
+//! # x11
+//! Rotates X screens directly, using RandR for the outputs and XInput for
+//! the touchscreens, instead of leaving it to `spinnr.sh`.
+
+use super::Rotation;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fmt::Result as FmtResult;
+
+use x11rb::CURRENT_TIME;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::{ConnectError, ConnectionError, ReplyError};
+use x11rb::protocol::randr::{self, ConnectionExt as RandrExt};
+use x11rb::protocol::xinput::{self, ConnectionExt as XInputExt};
+use x11rb::protocol::xproto::{self, ConnectionExt as XprotoExt};
+use x11rb::rust_connection::RustConnection;
+
+/// The XInput property that maps touches onto the screen
+pub const CTM_PROPERTY: &str = "Coordinate Transformation Matrix";
+/// XI2's XIAllDevices
+const ALL_DEVICES: xinput::DeviceId = 0;
+
+/// The touchscreen transformation matrix (row-major) for each rotation.
+///
+/// The same as the `rotmap` table in `spinnr.sh`.
+pub fn ctm(rotation: Rotation) -> [f32; 9] {
+    match rotation {
+        Rotation::Normal    => [ 1.,  0.,  0.,   0.,  1.,  0.,   0.,  0.,  1.],
+        Rotation::Left      => [ 0., -1.,  1.,   1.,  0.,  0.,   0.,  0.,  1.],
+        Rotation::Inverted  => [-1.,  0.,  1.,   0., -1.,  1.,   0.,  0.,  1.],
+        Rotation::Right     => [ 0.,  1.,  0.,  -1.,  0.,  1.,   0.,  0.,  1.],
+    }
+}
+
+/// The RandR rotation matching `rotation` (the same one `xrandr --rotate`
+/// uses).
+fn randr_rotation(rotation: Rotation) -> randr::Rotation {
+    match rotation {
+        Rotation::Normal    => randr::Rotation::ROTATE0,
+        Rotation::Left      => randr::Rotation::ROTATE90,
+        Rotation::Inverted  => randr::Rotation::ROTATE180,
+        Rotation::Right     => randr::Rotation::ROTATE270,
+    }
+}
+
+/// Split a comma-separated option value into its (trimmed, non-empty)
+/// parts.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|s| s.trim())
+        .filter(|s| ! s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+#[derive(Debug)]
+/// Represents an error talking to an X server
+pub enum X11Error {
+    Connect(ConnectError),
+    Connection(ConnectionError),
+    Reply(ReplyError),
+    /// The X server doesn't have RandR 1.2 or later
+    NoRandr,
+    /// There's no output by that name
+    NoSuchOutput(String),
+    /// No output was given, and none could be found
+    NoOutput,
+    /// The output can't be rotated that way
+    Unsupported(String, Rotation),
+    /// The rotated screen would need to be bigger than the maximum size
+    TooBig(u32, u32),
+    /// The server refused to change the output's configuration
+    SetConfig(String, randr::SetConfig),
+    /// Something went wrong with a particular display
+    OnDisplay(String, Box<X11Error>),
+}
+
+impl Display for X11Error {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            X11Error::Connect(e)   => write!(fmt, "can't connect ({})", e),
+            X11Error::Connection(e)    => write!(fmt, "connection error ({})", e),
+            X11Error::Reply(e) => write!(fmt, "request failed ({})", e),
+            X11Error::NoRandr  => write!(fmt, "RandR 1.2 isn't supported"),
+            X11Error::NoSuchOutput(o)  => write!(fmt, "there's no output '{}'", o),
+            X11Error::NoOutput => write!(fmt, "couldn't find a connected output"),
+            X11Error::Unsupported(o, r)    => {
+                write!(fmt, "output '{}' can't be rotated {}", o, r)
+            },
+            X11Error::TooBig(w, h) => {
+                write!(fmt, "the screen can't be resized to {}x{}", w, h)
+            },
+            X11Error::SetConfig(o, s)  => {
+                write!(fmt, "couldn't configure output '{}' ({:?})", o, s)
+            },
+            X11Error::OnDisplay(d, e)  => write!(fmt, "display {}: {}", d, e),
+        }
+    }
+}
+
+impl std::error::Error for X11Error {
+    fn description(&self) -> &str {
+        "error rotating X display"
+    }
+
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            X11Error::Connect(e)   => Some(e),
+            X11Error::Connection(e)    => Some(e),
+            X11Error::Reply(e) => Some(e),
+            X11Error::OnDisplay(_, e)  => Some(e.as_ref()),
+            _   => None,
+        }
+    }
+}
+
+impl From<ConnectionError> for X11Error {
+    fn from(e: ConnectionError) -> X11Error {
+        X11Error::Connection(e)
+    }
+}
+
+impl From<ReplyError> for X11Error {
+    fn from(e: ReplyError) -> X11Error {
+        X11Error::Reply(e)
+    }
+}
+
+type XResult<T> = Result<T, X11Error>;
+
+/// An open connection to an X display
+struct XConn {
+    conn: RustConnection,
+    root: xproto::Window,
+    /// Millimetres per pixel, to keep the DPI when resizing the screen
+    mm_per_px: (f64, f64),
+    /// Whether the server has XI2 (for the touchscreens)
+    has_xi2: bool,
+    ctm_atom: xproto::Atom,
+    float_atom: xproto::Atom,
+}
+
+impl XConn {
+    fn connect(name: Option<&str>) -> XResult<XConn> {
+        let (conn, screen) = RustConnection::connect(name)
+            .map_err(X11Error::Connect)?;
+        if conn.extension_information(randr::X11_EXTENSION_NAME)?.is_none() {
+            return Err(X11Error::NoRandr);
+        }
+        let version = conn.randr_query_version(1, 5)?.reply()?;
+        if (version.major_version, version.minor_version) < (1, 2) {
+            return Err(X11Error::NoRandr);
+        }
+        let has_xi2 = match conn.extension_information(xinput::X11_EXTENSION_NAME)? {
+            Some(_) => conn.xinput_xi_query_version(2, 2)?.reply()?.major_version >= 2,
+            None    => false,
+        };
+        if ! has_xi2 {
+            warn!("{} doesn't have XInput 2; not rotating touchscreens",
+                  name.unwrap_or("$DISPLAY"));
+        }
+        let ctm_atom = conn.intern_atom(false, CTM_PROPERTY.as_bytes())?.reply()?.atom;
+        let float_atom = conn.intern_atom(false, b"FLOAT")?.reply()?.atom;
+        let (root, mm_per_px) = {
+            let s = &conn.setup().roots[screen];
+            (s.root, (
+                f64::from(s.width_in_millimeters) / f64::from(s.width_in_pixels),
+                f64::from(s.height_in_millimeters) / f64::from(s.height_in_pixels),
+            ))
+        };
+        Ok(XConn { conn, root, mm_per_px, has_xi2, ctm_atom, float_atom })
+    }
+
+    /// The names and CRTCs of the outputs to rotate.
+    ///
+    /// If `names` is empty, that's the primary output if it's on, or else
+    /// the first connected output that's on.
+    fn find_outputs(&self, res: &randr::GetScreenResourcesCurrentReply, names: &[String])
+        -> XResult<Vec<(String, randr::Crtc)>>
+    {
+        let mut infos = Vec::with_capacity(res.outputs.len());
+        for &output in &res.outputs {
+            let info = self.conn.randr_get_output_info(output, res.config_timestamp)?
+                .reply()?;
+            infos.push((output, String::from_utf8_lossy(&info.name).into_owned(), info));
+        }
+        if names.is_empty() {
+            let primary = self.conn.randr_get_output_primary(self.root)?.reply()?.output;
+            let usable = |info: &randr::GetOutputInfoReply| {
+                randr::Connection::CONNECTED == info.connection && 0 != info.crtc
+            };
+            return infos.iter()
+                .find(|(o, _, i)| primary == *o && usable(i))
+                .or_else(|| infos.iter().find(|(_, _, i)| usable(i)))
+                .map(|(_, name, i)| vec![(name.to_owned(), i.crtc)])
+                .ok_or(X11Error::NoOutput);
+        }
+        let mut found = Vec::with_capacity(names.len());
+        for name in names {
+            match infos.iter().find(|(_, n, _)| n == name) {
+                Some((_, _, i)) if 0 != i.crtc => found.push((name.to_owned(), i.crtc)),
+                Some(_) => debug!("Output {} is off; not rotating it", name),
+                None    => return Err(X11Error::NoSuchOutput(name.to_owned())),
+            }
+        }
+        Ok(found)
+    }
+
+    /// Rotate the given outputs, then the touchscreens.
+    fn rotate(&self, rotation: Rotation, outputs: &[String], touchscreens: &Option<Vec<String>>)
+        -> XResult<()>
+    {
+        let res = self.conn.randr_get_screen_resources_current(self.root)?.reply()?;
+        for (name, crtc) in self.find_outputs(&res, outputs)? {
+            self.conn.grab_server()?;
+            let result = self.rotate_crtc(&res, &name, crtc, rotation);
+            self.conn.ungrab_server()?;
+            result?;
+        }
+        self.rotate_touchscreens(rotation, touchscreens)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    /// Rotate a CRTC, resizing the screen to fit.
+    ///
+    /// Like `xrandr`, this doesn't move any other CRTCs out of the way.
+    fn rotate_crtc(&self, res: &randr::GetScreenResourcesCurrentReply, name: &str,
+                   crtc: randr::Crtc, rotation: Rotation) -> XResult<()>
+    {
+        let info = self.conn.randr_get_crtc_info(crtc, res.config_timestamp)?.reply()?;
+        let target = randr_rotation(rotation);
+        let new = target | (info.rotation & (randr::Rotation::REFLECT_X | randr::Rotation::REFLECT_Y));
+        if new == info.rotation {
+            return Ok(());
+        }
+        if ! info.rotations.contains(target) {
+            return Err(X11Error::Unsupported(name.to_owned(), rotation));
+        }
+        let (w, h) = match res.modes.iter().find(|m| m.id == info.mode) {
+            Some(m) if target == randr::Rotation::ROTATE90
+                || target == randr::Rotation::ROTATE270 => (m.height, m.width),
+            Some(m) => (m.width, m.height),
+            None    => (info.width, info.height),
+        };
+
+        // The smallest screen that holds every CRTC once this one's rotated
+        let mut width = i32::from(info.x) + i32::from(w);
+        let mut height = i32::from(info.y) + i32::from(h);
+        for &other in res.crtcs.iter().filter(|&&c| c != crtc) {
+            let o = self.conn.randr_get_crtc_info(other, res.config_timestamp)?.reply()?;
+            if 0 != o.mode {
+                width = width.max(i32::from(o.x) + i32::from(o.width));
+                height = height.max(i32::from(o.y) + i32::from(o.height));
+            }
+        }
+        let range = self.conn.randr_get_screen_size_range(self.root)?.reply()?;
+        let width = width.max(i32::from(range.min_width)) as u32;
+        let height = height.max(i32::from(range.min_height)) as u32;
+        if width > u32::from(range.max_width) || height > u32::from(range.max_height) {
+            return Err(X11Error::TooBig(width, height));
+        }
+
+        let current = self.conn.get_geometry(self.root)?.reply()?;
+        if (width, height) != (u32::from(current.width), u32::from(current.height)) {
+            // The CRTC has to fit in the screen at all times, so turn it off
+            // if it won't fit while the screen is resized.
+            if i32::from(info.x) + i32::from(info.width) > width as i32
+                || i32::from(info.y) + i32::from(info.height) > height as i32
+            {
+                self.set_crtc(res, name, crtc, 0, 0, 0, randr::Rotation::ROTATE0, &[])?;
+            }
+            self.conn.randr_set_screen_size(
+                self.root, width as u16, height as u16,
+                (f64::from(width) * self.mm_per_px.0).round() as u32,
+                (f64::from(height) * self.mm_per_px.1).round() as u32,
+                )?.check()?;
+        }
+        self.set_crtc(res, name, crtc, info.x, info.y, info.mode, new, &info.outputs)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn set_crtc(&self, res: &randr::GetScreenResourcesCurrentReply, name: &str,
+                crtc: randr::Crtc, x: i16, y: i16, mode: randr::Mode,
+                rotation: randr::Rotation, outputs: &[randr::Output]) -> XResult<()>
+    {
+        let status = self.conn.randr_set_crtc_config(
+            crtc, CURRENT_TIME, res.config_timestamp, x, y, mode, rotation, outputs
+            )?.reply()?.status;
+        if randr::SetConfig::SUCCESS != status {
+            return Err(X11Error::SetConfig(name.to_owned(), status));
+        }
+        Ok(())
+    }
+
+    /// Set the transformation matrix of every touchscreen.
+    ///
+    /// If `names` is `None`, that's every direct touch device; otherwise
+    /// it's every pointer whose name contains one of `names` (ignoring
+    /// case).
+    fn rotate_touchscreens(&self, rotation: Rotation, names: &Option<Vec<String>>)
+        -> XResult<()>
+    {
+        if ! self.has_xi2 {
+            return Ok(());
+        }
+        let names = names.as_ref()
+            .map(|v| v.iter().map(|n| n.to_lowercase()).collect::<Vec<_>>());
+        let matrix = xinput::XIChangePropertyAux::Data32(
+            ctm(rotation).iter().map(|f| f.to_bits()).collect());
+        let devices = self.conn.xinput_xi_query_device(ALL_DEVICES)?.reply()?.infos;
+        for dev in devices {
+            if xinput::DeviceType::SLAVE_POINTER != dev.type_
+                && xinput::DeviceType::FLOATING_SLAVE != dev.type_
+            {
+                continue;
+            }
+            let is_touchscreen = match names {
+                Some(ref names) => {
+                    let name = String::from_utf8_lossy(&dev.name).to_lowercase();
+                    names.iter().any(|n| name.contains(n.as_str()))
+                },
+                None    => dev.classes.iter().any(|c| match c.data {
+                    xinput::DeviceClassData::Touch(ref t)   => {
+                        xinput::TouchMode::DIRECT == t.mode
+                    },
+                    _   => false,
+                }),
+            };
+            if is_touchscreen {
+                self.conn.xinput_xi_change_property(
+                    dev.deviceid, xproto::PropMode::REPLACE, self.ctm_atom,
+                    self.float_atom, 9, &matrix)?.check()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A display to rotate, and its connection (if it's connected)
+struct XDisplay {
+    /// The display name (`None` for $DISPLAY)
+    name: Option<String>,
+    conn: Option<XConn>,
+}
+
+impl XDisplay {
+    fn label(&self) -> String {
+        self.name.clone()
+            .or_else(|| env::var("DISPLAY").ok())
+            .unwrap_or_else(|| "$DISPLAY".to_owned())
+    }
+
+    /// Connect to the display, if it isn't already connected.
+    fn connect(&mut self, xauthority: &Option<String>) -> XResult<&XConn> {
+        if self.conn.is_none() {
+            // x11rb only looks for the cookie in $XAUTHORITY
+            if let Some(ref path) = *xauthority {
+                env::set_var("XAUTHORITY", path);
+            }
+            self.conn = Some(XConn::connect(self.name.as_deref())?);
+        }
+        Ok(self.conn.as_ref().unwrap())
+    }
+}
+
+/// Sends rotations straight to one or more X displays
+pub struct XSender {
+    displays: Vec<XDisplay>,
+    /// The outputs to rotate (empty to autodetect)
+    outputs: Vec<String>,
+    /// The touchscreens to rotate (`None` to autodetect)
+    touchscreens: Option<Vec<String>>,
+    xauthority: Option<String>,
+}
+
+impl XSender {
+    /// Connect to every display in `opts` and check its outputs exist.
+    pub fn init(opts: &HashMap<String, String>) -> XResult<XSender> {
+        let names = opts.get("display").map(|d| split_list(d)).unwrap_or_default();
+        let mut sender = XSender {
+            displays: if names.is_empty() {
+                vec![XDisplay { name: None, conn: None }]
+            } else {
+                names.into_iter().map(|n| XDisplay { name: Some(n), conn: None }).collect()
+            },
+            outputs: opts.get("output").map(|o| split_list(o)).unwrap_or_default(),
+            touchscreens: opts.get("touchscreen").map(|t| split_list(t)),
+            xauthority: opts.get("xauthority").cloned(),
+        };
+        let (xauthority, outputs) = (&sender.xauthority, &sender.outputs);
+        for display in sender.displays.iter_mut() {
+            let label = display.label();
+            let checked = display.connect(xauthority).and_then(|c| {
+                let res = c.conn.randr_get_screen_resources_current(c.root)?.reply()?;
+                c.find_outputs(&res, outputs)
+            });
+            match checked {
+                Ok(outputs) => debug!("Rotating {} on {}",
+                    outputs.iter().map(|o| o.0.as_str()).collect::<Vec<_>>().join(", "),
+                    label),
+                Err(e)  => return Err(X11Error::OnDisplay(label, Box::new(e))),
+            }
+        }
+        Ok(sender)
+    }
+
+    /// Rotate every display.
+    ///
+    /// Displays that fail are disconnected (to be reconnected next time),
+    /// and the first error is returned after trying all of them.
+    pub fn rotate(&mut self, rotation: Rotation) -> XResult<()> {
+        let (xauthority, outputs, touchscreens) =
+            (&self.xauthority, &self.outputs, &self.touchscreens);
+        let mut first_err = None;
+        for display in self.displays.iter_mut() {
+            let result = display.connect(xauthority)
+                .and_then(|c| c.rotate(rotation, outputs, touchscreens));
+            if let Err(e) = result {
+                display.conn = None;
+                let e = X11Error::OnDisplay(display.label(), Box::new(e));
+                if first_err.is_none() {
+                    first_err = Some(e);
+                } else {
+                    debug!("Error rotating X display: {}", e);
+                }
+            }
+        }
+        first_err.map_or(Ok(()), Err)
+    }
+}
+
+impl Display for XSender {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let labels: Vec<String> = self.displays.iter().map(|d| d.label()).collect();
+        write!(fmt, "X11 on {}", labels.join(", "))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [Rotation; 4] =
+        [Rotation::Normal, Rotation::Left, Rotation::Inverted, Rotation::Right];
+
+    /// The matrix for `name` in `spinnr.sh`'s `rotmap`.
+    fn rotmap(name: &str) -> Vec<f32> {
+        let script = include_str!("../../spinnr.sh");
+        let start = format!("rotmap[{}]=\"", name);
+        let from = script.find(&start).expect("missing from rotmap") + start.len();
+        let len = script[from..].find('"').unwrap();
+        script[from..from + len].split_whitespace()
+            .filter(|s| "\\" != *s)
+            .map(|s| s.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn ctm_matches_spinnr_sh() {
+        for &rotation in &ROTATIONS {
+            assert_eq!(rotmap(&rotation.to_string()), ctm(rotation).to_vec(), "for {}", rotation);
+        }
+    }
+
+    #[test]
+    fn ctm_keeps_touches_on_screen() {
+        // The corners of the touchscreen have to land on the corners of the
+        // screen, turning the same way RandR does (anticlockwise for left).
+        let apply = |m: [f32; 9], (x, y): (f32, f32)| (m[0] * x + m[1] * y + m[2],
+                                                        m[3] * x + m[4] * y + m[5]);
+        let top_left = [(0., 0.), (1., 0.), (1., 1.), (0., 1.)];
+        for (i, &rotation) in ROTATIONS.iter().enumerate() {
+            let m = ctm(rotation);
+            assert_eq!([0., 0., 1.], m[6..]);
+            assert_eq!(top_left[i], apply(m, (0., 0.)), "for {}", rotation);
+            assert_eq!(top_left[(i + 2) % 4], apply(m, (1., 1.)), "for {}", rotation);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! # lock
+//! The rotation lock, which keeps the screen where it is while the device
+//! is turned (without stopping spinnrd).
+//!
+//! The lock lives in a file (`--lock-file`), so it lasts across restarts
+//! and anything that can write there can lock the screen. While it's
+//! locked, the file holds the rotation it's locked at; an empty one locks
+//! it wherever it is (or ends up first), as does one that isn't `--allowed`.
+
+use super::*;
+
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockState {
+    Unlocked,
+    /// Locked at a rotation, or wherever it ends up next if `None`
+    Locked(Option<Rotation>),
+}
+
+impl Display for LockState {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            LockState::Unlocked => write!(f, "unlocked"),
+            LockState::Locked(Some(r))  => write!(f, "locked at {}", r),
+            LockState::Locked(None) => write!(f, "locked"),
+        }
+    }
+}
+
+/// The rotation lock, kept in sync with its file.
+pub struct RotationLock {
+    path: PathBuf,
+    state: LockState,
+    /// The rotations it can be locked at
+    allowed: Vec<Rotation>,
+    /// Changes to the file, if it can be watched (otherwise it's read
+    /// every time it's checked)
+    watch: Option<FileWatch>,
+}
+
+impl RotationLock {
+    /// Start keeping track of the lock in the file at `path`, which can
+    /// lock it at any of `allowed`.
+    pub fn open(path: PathBuf, allowed: &[Rotation]) -> RotationLock {
+        let watch = FileWatch::new(&path).map_err(|e| {
+            warn!("Can't watch {} for changes ({}); checking it every time instead",
+                  path.display(), e)
+        }).ok();
+        let mut lock = RotationLock {
+            path,
+            state: LockState::Unlocked,
+            allowed: allowed.to_vec(),
+            watch,
+        };
+        lock.state = lock.read();
+        lock
+    }
+
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+
+    pub fn is_locked(&self) -> bool {
+        LockState::Unlocked != self.state
+    }
+
+    /// The rotation it's locked at, if it's locked at one.
+    pub fn rotation(&self) -> Option<Rotation> {
+        match self.state {
+            LockState::Locked(r)    => r,
+            LockState::Unlocked => None,
+        }
+    }
+
+    /// Lock at `rotation` (or wherever it ends up next), and save it.
+    /// Returns whether anything changed.
+    pub fn lock(&mut self, rotation: Option<Rotation>) -> bool {
+        self.set(LockState::Locked(rotation))
+    }
+
+    /// Unlock, and save it. Returns whether anything changed.
+    pub fn unlock(&mut self) -> bool {
+        self.set(LockState::Unlocked)
+    }
+
+    /// Catch up with any changes to the file. Returns whether the lock
+    /// changed.
+    pub fn refresh(&mut self) -> bool {
+        if let Some(ref mut w) = self.watch {
+            match w.changed() {
+                Ok(true)    => (),
+                Ok(false)   => return false,
+                Err(e)  => {
+                    warn!("Can't watch {} for changes any more ({})", self.path.display(), e);
+                    self.watch = None;
+                },
+            }
+        }
+        let state = self.read();
+        if state == self.state {
+            return false;
+        }
+        self.state = state;
+        true
+    }
+
+    /// Wake up when the file changes.
+    pub fn wake_on(&self, wakeup: &mut Wakeup) {
+        if let Some(ref w) = self.watch {
+            wakeup.fd(w.as_raw_fd());
+        }
+    }
+
+    fn set(&mut self, state: LockState) -> bool {
+        if state == self.state {
+            return false;
+        }
+        self.state = state;
+        let saved = match state {
+            LockState::Unlocked => fs::remove_file(&self.path).or_else(|e| match e.kind() {
+                IoErrorKind::NotFound   => Ok(()),
+                _   => Err(e),
+            }),
+            LockState::Locked(r)    => fs::write(&self.path, r.map_or(String::new(), |r| format!("{}\n", r))),
+        };
+        if let Err(e) = saved {
+            warn!("Couldn't save the rotation lock to {} ({})", self.path.display(), e);
+        }
+        true
+    }
+
+    /// What the file says the lock is.
+    fn read(&self) -> LockState {
+        match fs::read_to_string(&self.path) {
+            Ok(s)   => match s.trim() {
+                ""  => LockState::Locked(None),
+                r   => match r.parse::<Rotation>() {
+                    Ok(r) if self.allowed.contains(&r)  => LockState::Locked(Some(r)),
+                    Ok(r)   => {
+                        warn!("{} in {} isn't allowed; locking wherever it is",
+                              r, self.path.display());
+                        LockState::Locked(None)
+                    },
+                    Err(e)  => {
+                        warn!("Can't read the rotation in {} ({}); locking wherever it is",
+                              self.path.display(), e);
+                        LockState::Locked(None)
+                    },
+                },
+            },
+            Err(ref e) if IoErrorKind::NotFound == e.kind()    => LockState::Unlocked,
+            Err(e)  => {
+                warn!("Couldn't read {} ({}); leaving the lock as it was", self.path.display(), e);
+                self.state
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::TempDir;
+
+    const ALL: [Rotation; 4] = [Rotation::Normal, Rotation::Left, Rotation::Inverted, Rotation::Right];
+
+    #[test]
+    fn lock_lasts() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("spinnrd.lock");
+        let mut lock = RotationLock::open(path.clone(), &ALL);
+        assert_eq!(LockState::Unlocked, lock.state());
+        assert!(lock.lock(Some(Rotation::Left)));
+        assert!(!lock.lock(Some(Rotation::Left)));
+        assert_eq!("left\n", fs::read_to_string(&path).unwrap());
+        assert_eq!(LockState::Locked(Some(Rotation::Left)), RotationLock::open(path.clone(), &ALL).state());
+
+        assert!(lock.lock(None));
+        assert_eq!(LockState::Locked(None), RotationLock::open(path.clone(), &ALL).state());
+
+        assert!(lock.unlock());
+        assert!(!path.exists());
+        assert_eq!(LockState::Unlocked, RotationLock::open(path.clone(), &ALL).state());
+    }
+
+    #[test]
+    fn empty_or_garbage_locks_wherever_it_is() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("spinnrd.lock");
+        fs::write(&path, "").unwrap();
+        assert_eq!(LockState::Locked(None), RotationLock::open(path.clone(), &ALL).state());
+        fs::write(&path, " \n").unwrap();
+        assert_eq!(LockState::Locked(None), RotationLock::open(path.clone(), &ALL).state());
+        fs::write(&path, "sideways\n").unwrap();
+        assert_eq!(LockState::Locked(None), RotationLock::open(path.clone(), &ALL).state());
+        fs::write(&path, " inverted \n").unwrap();
+        assert_eq!(LockState::Locked(Some(Rotation::Inverted)),
+                   RotationLock::open(path.clone(), &ALL).state());
+        let upright = [Rotation::Normal, Rotation::Left, Rotation::Right];
+        assert_eq!(LockState::Locked(None), RotationLock::open(path.clone(), &upright).state());
+    }
+
+    #[test]
+    fn refresh_follows_the_file() {
+        let dir = TempDir::new("lock");
+        let path = dir.join("spinnrd.lock");
+        let mut lock = RotationLock::open(path.clone(), &ALL);
+        assert!(!lock.refresh());
+
+        fs::write(&path, "right\n").unwrap();
+        assert!(lock.refresh());
+        assert_eq!(Some(Rotation::Right), lock.rotation());
+        assert!(!lock.refresh());
+
+        fs::write(&path, "").unwrap();
+        assert!(lock.refresh());
+        assert_eq!(LockState::Locked(None), lock.state());
+
+        fs::remove_file(&path).unwrap();
+        assert!(lock.refresh());
+        assert!(!lock.is_locked());
+
+        // Without a watch, it's read every time instead.
+        lock.watch = None;
+        fs::write(&path, "normal\n").unwrap();
+        assert!(lock.refresh());
+        assert_eq!(Some(Rotation::Normal), lock.rotation());
+    }
+}
@@ -109,10 +109,11 @@ pub type LogInitResult = Result<LogLocation, LoggingError>;
 
 /// Globally initialize the logger.
 pub fn init_logger() -> LogInitResult {
-    let logfile = CLI_ARGS.value_of("logfile").unwrap_or(DEFAULT_LOG_FILE);
+    let logfile = arg_value("logfile").unwrap_or_else(|| DEFAULT_LOG_FILE.to_owned());
+    let logfile = logfile.as_str();
 
-    let loglvl = match CLI_ARGS.value_of("loglvl") {
-        Some(s) => s.parse().map_err(|e| LoggingError::LogLevel(e,s.to_owned()))?,
+    let loglvl = match arg_value("loglvl") {
+        Some(s) => s.parse().map_err(|e| LoggingError::LogLevel(e,s.clone()))?,
         None    => DEFAULT_LOG_LEVEL,
     };
 
@@ -159,7 +160,7 @@ fn init_splatnix_syslog(level: LevelFilter) -> LogInitResult {
     syslog::init(
         get_syslog_facility(),
         level, 
-        Some("spinnrd")
+        Some(env!("CARGO_BIN_NAME"))
         )
         .map_err(|e| LoggingError::Syslog(e))
         .map(|_| LogLocation::Syslog)
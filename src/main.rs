@@ -7,21 +7,30 @@
 extern crate daemonize;
 extern crate simplelog;
 extern crate chrono;
-extern crate signal;
 extern crate syslog;
 // extern crate errno;
 extern crate regex;
 extern crate clap;
 extern crate libc;
 #[macro_use] extern crate log;
+extern crate toml;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
 
 #[cfg(feature = "sysd")]
 extern crate systemd;
 
-// For fs-accel
-#[cfg(feature = "fsaccel")]
+// For fs-accel and evdev-accel
+#[cfg(any(feature = "fsaccel", feature = "evdevaccel"))]
 extern crate glob;
 
+#[cfg(feature = "x11")]
+extern crate x11rb;
+#[cfg(any(feature = "dbus", feature = "sensorproxy"))]
+extern crate dbus;
+#[cfg(feature = "dbus")]
+extern crate dbus_crossroads;
+
 
 macro_rules! qprintln {
     ( $($args:tt)* ) => {
@@ -38,8 +47,22 @@ macro_rules! qprinterr {
 mod logging;
 mod frontend;
 mod backend;
-#[cfg(any(feature = "fsaccel", feature = "iioaccel"))]
+mod quirks;
+mod config;
+mod paths;
+mod reactor;
+mod pollrate;
+mod rotation;
+mod lock;
+#[allow(dead_code)] // read_response is for spinnrctl
+mod protocol;
+mod control;
+#[cfg(any(feature = "fsaccel", feature = "iioaccel", feature = "evdevaccel"))]
 mod accel;
+#[cfg(all(test, any(feature = "dbus", feature = "sensorproxy")))]
+mod testbus;
+#[cfg(test)]
+mod testutil;
 #[allow(dead_code)]
 mod metadata {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -49,15 +72,21 @@ mod metadata {
 use frontend::*;
 use backend::*;
 use logging::*;
+use config::{arg_value,arg_flag};
+use paths::*;
+use reactor::*;
+use pollrate::PollRate;
+pub use rotation::*;
+use lock::*;
+use protocol::DEFAULT_CONTROL_SOCKET;
+use control::*;
 
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
-use std::thread::sleep;
-use std::thread;
-use std::sync::mpsc;
+use std::sync::RwLock;
 use std::fs::{File,remove_file,OpenOptions};
 // use std::ffi::CStr;
-// use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 // #[allow(unused_imports)] // for File.write()
 // use std::io::Write;
 use std::path::{PathBuf};
@@ -70,15 +99,8 @@ use std::fmt::Result as FmtResult;
 // use c_fixed_string::CFixedStr;
 use daemonize::Daemonize;
 use clap::{Arg,ArgMatches};
-use signal::trap::Trap;
-use signal::Signal;
-use chrono::{DateTime, Utc, Local};
-// Apparantly I need this for some of the things I'm doing, even though it 
-// never gets used explicitly.
-#[allow(unused_imports)]
-use chrono::TimeZone;
+use regex::Regex;
 // use errno::{errno,Errno};
-use regex::{Regex,Captures};
 // use libc::{uid_t,gid_t,getuid,getgid};
 use libc::{geteuid,getegid};
 // use libc::{getpwuid_r,getgrgid_r};
@@ -90,18 +112,25 @@ use libc::{geteuid,getegid};
 // pub const F_RDLCK: ::libc::c_short = 1;
 
 /// The default interval between accelerometer polls (in ms)
-/// Note that this must stay in the same units as the hysteresis!
 const DEFAULT_PERIOD: u32   = 150;
 const DEFAULT_PERIOD_STR: &str  = "150";
 
+/// How fast the filtered acceleration has to be changing (in m/s^2 per
+/// second) to switch from the slow interval to the fast one
+const DEFAULT_MOTION_THRESHOLD: f64 = 1.0;
+const DEFAULT_MOTION_THRESHOLD_STR: &str = "1.0";
+
+/// How little the filtered acceleration can change with a reading (in
+/// m/s^2) for the filter to have caught up with the device
+const SETTLED_MOTION: f64 = 0.01;
+
 /// Multiply by the period to get nanoseconds
 const PERIOD_NS_MULT: u32   = 1000000;
 
 /// Divide the period by this to get seconds
 const PERIOD_SEC_DIV: u32   = 1000;
 
-/// The default amount of time we're filtering over (in ms)
-/// Note that this must stay in the same units as the period!
+/// The default time constant of the low-pass filter (in ms)
 const DEFAULT_HYSTERESIS: u32   = 1000;
 const DEFAULT_HYSTERESIS_STR: &str  = "1000";
 
@@ -119,18 +148,21 @@ const DELAY_SEC_DIV: u32   = 1000;
 const DEFAULT_SENSITIVITY: f64 = 5.0;
 const DEFAULT_SENSITIVITY_STR: &str = "5.0";
 
+/// The rotations spinnrd can rotate to by default
+const DEFAULT_ALLOWED: &str = "normal,left,inverted,right";
+
+/// The rotation that's normal by default
+const DEFAULT_NATURAL: &str = "normal";
+
 /// The default pid file
 const DEFAULT_PID_FILE: &'static str = "%d/spinnrd.pid";
 
+/// The default rotation lock file
+const DEFAULT_LOCK_FILE: &str = "%d/spinnrd.lock";
+
 // /// The backup pid file
 // const BACKUP_PID_FILE: &'static str = "/tmp/spinnrd.pid";
 
-/// The default spinfile
-const DEFAULT_SPINFILE: &'static str = "%d/spinnrd.spin";
-
-/// The default working directory
-const DEFAULT_WORKING_DIRECTORY: &'static str = "/run/spinnrd";
-
 /// The backup working directory
 const BACKUP_WORKING_DIRECTORY: &str = "/tmp";
 
@@ -142,6 +174,11 @@ const DEFAULT_FRONTEND: &'static str = "file";
 
 /// The default backend options
 const DEFAULT_BACKEND_OPTS: &'static str = "";
+
+/// The default time between attempts to go back to a more preferred
+/// backend (in ms)
+const DEFAULT_BACKEND_RETRY: u32    = 5000;
+const DEFAULT_BACKEND_RETRY_STR: &str   = "5000";
 /// The default backend(s)
 // #[cfg(feature = "fsaccel")]
 const DEFAULT_BACKEND: &'static str = "fsaccel";
@@ -165,19 +202,15 @@ const LOG_FAIL_FILE: &'static str = "/tmp/spinnrd.%t.logfail";
 const DEFAULT_QUIT_ON_ROTATION_SEND_ERR: bool = false;
 // this gets logged and it shouldn't really happy anyway.
 
-///# Formatting Arguments
-///`strftime` string for basic ISO 8601
-const STRF_8601_BASIC: &'static str = "%Y%m%dT%H%M%S%z";
-
-///`strftime` string for basic ISO 8601, with nanoseconds
-const STRF_8601_BASIC_NS: &'static str = "%Y%m%dT%H%M%S.%f%z";
-
 /// Error indicating no frontend
 const ERR_NO_FRONTEND: i32 = -1314;
 
 /// Error indicating no backend
 const ERR_NO_ORIENTATOR: i32 = -1313;
 
+/// Error indicating a config file couldn't be loaded
+const ERR_BAD_CONFIG: i32 = 3;
+
 lazy_static!{
     static ref VERSION: String = format!("{} ({})", metadata::PKG_VERSION, metadata::FEATURES_STR);
     static ref AFTER_HELP_STR: String = format!("FILENAME FORMATTING
@@ -202,6 +235,29 @@ Filenames accept a few variables that will be expanded as follows:
         https://docs.rs/chrono/{}/chrono/format/strftime/index.html for 
         details). Use '%}}' to embed a '}}' in the format string.
 
+SIGNALS
+SIGHUP re-reads the config files and restarts the backend and frontend 
+with them; if the new config doesn't work, the old one is kept. Logging, 
+daemonizing, and working directory settings only change on restart. 
+SIGINT and SIGTERM exit.
+
+OPTION VALUES
+Commas, semicolons, pipes, and backslashes in backend and frontend option 
+values must be escaped with a backslash (e.g. 
+'mount_matrix=0\\, 1\\, 0\\; ...').
+
+UNITS
+The accelerometer backends expect readings, once scaled, to be in m/s^2, 
+like shake_tolerance and --motion-threshold (and the kalman filter's q 
+and r). With a scale or defscale that gives other units, every reading 
+looks like the device is being shaken, so scale those to match (or set 
+shake_tolerance=0).
+
+FRONTENDS
+Every frontend in a ';'-separated --frontend list is used at once. Each 
+one can be a '|'-separated list of alternatives, in which case the first 
+that starts is used (e.g. 'file,path=%d/a.spin;x11|file,path=%d/b.spin').
+
 BACKEND OPTIONS
 The available backend options are as follows:{}
 
@@ -213,11 +269,23 @@ The available frontend options are as follows:{}
 // the part where we define the command line arguments
 lazy_static!{
     /// The command line arguments
-    static ref CLI_ARGS: ArgMatches<'static> = clap::App::new("Spinnr")
+    static ref CLI_ARGS: ArgMatches<'static> = cli_app().get_matches();
+}
+
+/// The command line arguments spinnrd takes
+fn cli_app() -> clap::App<'static, 'static> {
+    clap::App::new("Spinnr")
 
         .version((*VERSION).as_str())
         .author("James Wescott <james@wescottdesign.com>")
         .about("Parses accelerometer data into device rotation")
+        .arg(Arg::with_name("config")
+             .short("c")
+             .long("config")
+             .value_name("CONFIG")
+             .help("Read options from CONFIG instead of the usual config files.")
+             .long_help("By default, options are read from /etc/spinnrd/config.toml, then from $XDG_CONFIG_HOME/spinnrd/config.toml. Options given on the command line override those from config files.")
+            )
         .arg(Arg::with_name("quiet")
              .short("q")
              .long("quiet")
@@ -231,11 +299,26 @@ lazy_static!{
              .value_name("PERIOD")
              .default_value(DEFAULT_PERIOD_STR)
             )
+        .arg(Arg::with_name("slow_period")
+             .long("slow-interval")
+             .validator(validate_u32)
+             .help("Poll every SLOW_PERIOD milliseconds while the device is still")
+             .long_help("Poll every SLOW_PERIOD milliseconds while the device is still, and every --interval milliseconds once it starts moving. Without this, the interval never changes.")
+             .value_name("SLOW_PERIOD")
+            )
+        .arg(Arg::with_name("motion_threshold")
+             .long("motion-threshold")
+             .validator(validate_f64)
+             .help("How fast the acceleration has to change (in m/s^2 per second) for the device to be moving")
+             .value_name("THRESHOLD")
+             .default_value(DEFAULT_MOTION_THRESHOLD_STR)
+            )
         .arg(Arg::with_name("hysteresis")
              .long("hysteresis")
              .short("H")
              .validator(validate_u32)
              .help("How long to average the accelerometer inputs over, in milliseconds")
+             .long_help("The time constant of the low-pass filter on accelerometer inputs, in milliseconds: how long it takes to get about 63% of the way to a new reading, however often they're read.")
              .value_name("HYSTERESIS")
              .default_value(DEFAULT_HYSTERESIS_STR)
             )
@@ -243,7 +326,7 @@ lazy_static!{
              .long("sensitivity")
              .short("s")
              .validator(validate_f64)
-             .help("The higher this is, the flatter we'll detect a rotation")
+             .help("The higher this is, the flatter we'll detect a rotation (with the legacy classifier)")
              .value_name("SENSITIVITY")
              .default_value(DEFAULT_SENSITIVITY_STR)
              )
@@ -265,6 +348,7 @@ lazy_static!{
              .long("log-file")
              .number_of_values(1)
              .value_name("LOGFILE")
+             .validator(validate_nonempty)
              .default_value(DEFAULT_LOG_FILE)
              .help("Location of file to log to. 'systemd', 'system', and 'syslog' log to system log. Uses filename formatting.")
              .long_help("'systemd' will log to systemd journal. 'system' will log to the systemd journal if available, or the system log otherwise. 'syslog' will log to the system log.")
@@ -277,6 +361,27 @@ lazy_static!{
              .help("Location of the file to write the current orientation to.")
              .long_help("This should be on a RAM-backed filesystem, where possible.")
              )
+        .arg(Arg::with_name("lockfile")
+             .long("lock-file")
+             .value_name("LOCKFILE")
+             .default_value(DEFAULT_LOCK_FILE)
+             .help("Location of the rotation lock file. Uses filename formatting.")
+             .long_help("Location of the rotation lock file. Uses filename formatting. While it exists, the rotation is locked at the one it holds (or, if it's empty, wherever it is). SIGUSR1 locks it and SIGUSR2 unlocks it.")
+             )
+        .arg(Arg::with_name("control_socket")
+             .long("control-socket")
+             .value_name("SOCKET")
+             .default_value(DEFAULT_CONTROL_SOCKET)
+             .help("Where to listen for spinnrctl. Uses filename formatting.")
+             .long_help("Where to listen for spinnrctl (see spinnrctl --help). Uses filename formatting. Set it empty to not listen at all.")
+             )
+        .arg(Arg::with_name("control_mode")
+             .long("control-mode")
+             .value_name("MODE")
+             .validator(validate_mode)
+             .default_value(DEFAULT_CONTROL_MODE)
+             .help("The permissions of the control socket, in octal (anyone who can write to it can control spinnrd).")
+             )
         .arg(Arg::with_name("nopidfile")
              .long("no-pid-file")
              .help("Don't make a pid file")
@@ -294,12 +399,35 @@ lazy_static!{
              .help("Wait for orientation to be stable for DELAY milliseconds before rotating display.")
              .default_value(DEFAULT_DELAY_STR)
             )
+        .arg(Arg::with_name("allowed")
+             .long("allowed")
+             .value_name("ROTATIONS")
+             .validator(validate_rotations)
+             .help("Only ever rotate to these rotations (separated by commas)")
+             .long_help("Only ever rotate to these rotations (separated by commas), e.g. normal,left,right to never turn upside down. When the device is turned to any other, the screen stays as it was. These are the rotations after --natural is taken into account.")
+             .default_value(DEFAULT_ALLOWED)
+            )
+        .arg(Arg::with_name("natural")
+             .long("natural")
+             .value_name("ROTATION")
+             .validator(validate_rotation)
+             .help("The rotation that's detected when the device is the right way up for its screen")
+             .long_help("The rotation that's detected when the device is held the right way up for its screen (e.g. left for some portrait panels in landscape devices). Every detected rotation is shifted so this one becomes normal.")
+             .default_value(DEFAULT_NATURAL)
+            )
         .arg(Arg::with_name("backend")
              .long("backend")
              .value_name("BACKEND[[,OPT=VALUE]...][;BACKEND[[,OPT=VALUE]...]]...")
-             .value_delimiter(";")
              .help("Choose which backend(s) to get data from and set options")
+             .long_help("Backends are listed most preferred first. If one stops working, the next that works is used instead.")
              )
+        .arg(Arg::with_name("backend_retry")
+             .long("backend-retry")
+             .value_name("RETRY")
+             .validator(validate_u32)
+             .help("How often to try going back to a more preferred backend, in milliseconds")
+             .default_value(DEFAULT_BACKEND_RETRY_STR)
+            )
         .arg(Arg::with_name("backend_opts")
              .long("backend-options")
              .value_name("BACKEND[,[OPT]...]")
@@ -309,9 +437,8 @@ lazy_static!{
              )
         .arg(Arg::with_name("frontend")
              .long("frontend")
-             .value_name("FRONTEND[[,OPT=VALUE]...][;FRONTEND[[,OPT=VALUE]...]]")
-             .value_delimiter(";")
-             .help("Choose which frontend(s) to get data from and set options")
+             .value_name("FRONTEND[[,OPT=VALUE]...][|FRONTEND...][;FRONTEND...]")
+             .help("Choose which frontend(s) to send rotations to and set options")
              )
         .arg(Arg::with_name("frontend_opts")
              .long("frontend-options")
@@ -320,6 +447,22 @@ lazy_static!{
              .number_of_values(1)
              .help("Set options for various frontends without changing which frontend(s) to use.")
              )
+        .arg(Arg::with_name("dump_quirks")
+             .long("dump-quirks")
+             .help("Show which device quirks match this device, then exit.")
+            )
+        .arg(Arg::with_name("quirks_dir")
+             .long("quirks-dir")
+             .value_name("DIR")
+             .default_value(quirks::DEFAULT_QUIRKS_DIR)
+             .help("Directory of extra device quirk (*.toml) files.")
+            )
+        .arg(Arg::with_name("dmi_root")
+             .long("dmi-root")
+             .value_name("DIR")
+             .default_value(quirks::DEFAULT_DMI_ROOT)
+             .help("Directory to read the DMI identifiers quirks are matched against from.")
+            )
         .arg(Arg::with_name("loglvl")
              .long("log-level")
              .value_name("LOG_LEVEL")
@@ -329,7 +472,6 @@ lazy_static!{
              )
         //TODO: add --log-fmt
         .after_help((*AFTER_HELP_STR).as_str())
-        .get_matches();
 }
 
 fn chrono_ver() -> &'static str {
@@ -342,10 +484,21 @@ fn chrono_ver() -> &'static str {
 }
 
 lazy_static! {
-    static ref SENSITIVITY: f64 = get_f64_arg_val("sensitivity").unwrap_or(DEFAULT_SENSITIVITY);
-    static ref IS_QUIET: bool = CLI_ARGS.is_present("quiet");
+    /// The current sensitivity (can change when the config is reloaded)
+    static ref SENSITIVITY: RwLock<f64> = RwLock::new(DEFAULT_SENSITIVITY);
+    static ref IS_QUIET: bool = arg_flag("quiet");
+}
+
+
+/// Get the current sensitivity.
+pub fn sensitivity() -> f64 {
+    *SENSITIVITY.read().unwrap_or_else(|e| e.into_inner())
 }
 
+/// Change the sensitivity.
+fn set_sensitivity(s: f64) {
+    *SENSITIVITY.write().unwrap_or_else(|e| e.into_inner()) = s;
+}
 
 fn main() {
     // lets us exit with status - important for running under systemd, etc.
@@ -354,6 +507,17 @@ fn main() {
 
 /// The actual main body of the program
 fn mainprog() -> i32 {
+    // There's no logger yet, so errors can only go to stderr.
+    if let Err(e) = config::load_config() {
+        eprintln!("{}", e);
+        return ERR_BAD_CONFIG;
+    }
+
+    if CLI_ARGS.is_present("dump_quirks") {
+        print!("{}", quirks::dump_quirks());
+        return 0;
+    }
+
     match init_logger() {
         Ok(l)   => {
             qprintln!("Logging initialized to {}", l);
@@ -387,23 +551,9 @@ fn mainprog() -> i32 {
     } // if is_daemon()
 
 
-    match init_frontend() {
-        Ok(frontend)    => {
-            let hyst = get_u32_arg_val("hysteresis").unwrap_or(DEFAULT_HYSTERESIS);
-            let period = get_u32_arg_val("period").unwrap_or(DEFAULT_PERIOD);
-            let delay = get_u32_arg_val("delay").unwrap_or(DEFAULT_DELAY);
-            // a_now = m * (measurement - a_last)
-            // where m is the amount of time we're low-pass filtering over
-            // times the frequency with which we're polling
-            // (AKA the time we're filtering over divided by the period)
-            match init_orientator(period as f64 / (hyst as f64)) {
-                Ok(orientator) => {
-                    rval = runloop(frontend, orientator, period, delay);
-                },
-                Err(e)  => {
-                    rval = e;
-                },
-            }
+    match Spinner::init() {
+        Ok(spinner) => {
+            rval = runloop(spinner);
         },
         Err(e)  => {
             rval = e;
@@ -416,172 +566,579 @@ fn mainprog() -> i32 {
     return rval;
 }
 
-fn runloop(
-    mut frontend: FrontendKind,
-    mut orient: OrientatorKind,
-    period: u32,
-    delay: u32
-    ) -> i32
-{
-    let (handle, sigrx) = init_sigtrap(&[Signal::SIGHUP,Signal::SIGINT,Signal::SIGTERM]);
-
-    // period is in ms, so multiply by 10^6 to get ns
-    let period = Duration::new(
-        (period / PERIOD_SEC_DIV) as u64,
-        (period % PERIOD_SEC_DIV) * PERIOD_NS_MULT);
-    let delay = Duration::new(
-        (delay / DELAY_SEC_DIV) as u64,
-        (delay % DELAY_SEC_DIV) * DELAY_NS_MULT);
-
-    let mut orientation: Option<Rotation>;
+/// Everything that gets rebuilt when the config is reloaded
+struct Spinner {
+    frontends: Frontends,
+    orient: Backends,
+    /// How often to poll the orientator
+    rate: PollRate,
+    delay: Duration,
+    /// The rotations that can be sent
+    allowed: Vec<Rotation>,
+    /// The detected rotation that's sent as normal
+    natural: Rotation,
+}
+
+impl Spinner {
+    /// Set everything up from the current config and command line.
+    fn init() -> Result<Spinner, i32> {
+        set_sensitivity(get_f64_arg_val("sensitivity").unwrap_or(DEFAULT_SENSITIVITY));
+        let frontends = init_frontends()?;
+        let hyst = get_u32_arg_val("hysteresis").unwrap_or(DEFAULT_HYSTERESIS);
+        let period = get_u32_arg_val("period").unwrap_or(DEFAULT_PERIOD);
+        let delay = get_u32_arg_val("delay").unwrap_or(DEFAULT_DELAY);
+        // period is in ms, so multiply by 10^6 to get ns
+        let period = Duration::new(
+            (period / PERIOD_SEC_DIV) as u64,
+            (period % PERIOD_SEC_DIV) * PERIOD_NS_MULT);
+        let hysteresis = Duration::from_millis(hyst as u64);
+        let slow_period = get_u32_arg_val("slow_period")
+            .filter(|&p| 0 < p)
+            .map(|p| Duration::from_millis(p as u64));
+        let allowed = arg_value("allowed").map_or(Ok(Vec::new()), |a| parse_rotations(&a))
+            .map_err(|e| {
+                error!("Bad --allowed: {}", e);
+                ERR_BAD_CONFIG
+            })?;
+        let natural = arg_value("natural").map_or(Ok(Rotation::Normal), |n| n.parse())
+            .map_err(|e| {
+                error!("Bad --natural: {}", e);
+                ERR_BAD_CONFIG
+            })?;
+        let orient = init_orientator(hysteresis)?;
+        let rate = PollRate::new(period, slow_period,
+                                 get_f64_arg_val("motion_threshold").unwrap_or(DEFAULT_MOTION_THRESHOLD),
+                                 hysteresis);
+        if rate.is_adaptive() && orient.is_running() && orient.motion().is_none() {
+            warn!("Backend {} can't tell when the device is moving, so --slow-interval does nothing",
+                  orient.active_name());
+        }
+        Ok(Spinner {
+            frontends,
+            orient,
+            rate,
+            delay: Duration::new(
+                (delay / DELAY_SEC_DIV) as u64,
+                (delay % DELAY_SEC_DIV) * DELAY_NS_MULT),
+            allowed,
+            natural,
+        })
+    }
+
+    /// Change a setting (until the config is reloaded).
+    fn set(&mut self, setting: Setting) {
+        info!("Setting {}", setting);
+        match setting {
+            Setting::Delay(d)   => self.delay = d,
+            Setting::Sensitivity(s) => set_sensitivity(s),
+            Setting::Period(p)  => self.rate.set_period(p),
+        }
+    }
+
+    /// The rotation to send for `detected`: shifted by `--natural`, or
+    /// nothing if it isn't `--allowed` (so the screen stays as it was).
+    fn map_rotation(&self, detected: Option<Rotation>) -> Option<Rotation> {
+        let detected = detected?;
+        let rotation = rotation::map_rotation(detected, self.natural, &self.allowed);
+        if rotation.is_none() {
+            trace!("Ignoring {} (not allowed)", detected.relative_to(self.natural));
+        }
+        rotation
+    }
+
+    /// Whether the last reading hadn't settled yet: the filter was still
+    /// catching up, or the device had just been shaken.
+    ///
+    /// Orientators that are only read when they have something new need
+    /// reading anyway until it has, since a device that's been put down
+    /// says nothing more (the input core drops repeated values).
+    fn settling(&self) -> bool {
+        self.orient.shaken() || self.orient.motion().is_some_and(|m| m > SETTLED_MOTION)
+    }
+
+    /// Speed polling up if the device is moving, or slow it down if it's
+    /// been still for a while (with `--slow-interval`), going by a reading
+    /// at `now`, `elapsed` after the last one.
+    fn adapt(&mut self, elapsed: Duration, now: Instant) {
+        if let Some(motion) = self.orient.motion() {
+            self.rate.update(motion, elapsed, now);
+        }
+    }
+
+    /// Re-read the config files and rebuild from them.
+    ///
+    /// If the new config can't be read or used, it's logged and the old
+    /// one is kept. Only fails if the old config can't be used any more
+    /// either.
+    fn reload(self) -> Result<Spinner, i32> {
+        let old_config = config::with_config(Clone::clone);
+        match config::load_config() {
+            Ok(files)   => info!("Loaded config from {:?}", files),
+            Err(e)  => {
+                error!("Keeping the old config: {}", e);
+                return Ok(self);
+            },
+        }
+        // The old frontends and orientator have to go before the new ones
+        // are made, since some backends (like iioaccel) can only be opened
+        // once.
+        drop(self);
+        Spinner::init().or_else(|_| {
+            error!("Couldn't start with the new config; going back to the old one");
+            config::set_config(old_config);
+            Spinner::init()
+        })
+    }
+}
+
+fn runloop(mut spinner: Spinner) -> i32 {
+    let mut signals = match init_signals(&[libc::SIGHUP, libc::SIGINT, libc::SIGTERM,
+                                             libc::SIGUSR1, libc::SIGUSR2]) {
+        Ok(s)   => s,
+        Err(e)  => {
+            error!("Couldn't start handling signals! ({}) Aborting!", e);
+            return 17;
+        },
+    };
+
+    let mut wakeup = Wakeup::new();
+    let mut orientation: Option<Rotation> = None;
     let mut last_written: Option<Rotation> = None;
     let mut last_change: Option<Rotation> = None;
     let mut last_change_time = Instant::now();
+    // Postures go through the same delay as rotations
+    let mut posture: Option<Posture> = None;
+    let mut posture_since = Instant::now();
+    let mut last_posture: Option<Posture> = None;
+    // When the orientator is next read (or, if it has an event fd, when it
+    // can next be read).
+    let mut next_read = Instant::now();
+    let mut last_read = Instant::now();
+    // Whether to read the orientator even if it has nothing new (so new
+    // ones get read once to find out where we are).
+    let mut read_now = true;
+    let mut lock = RotationLock::open(get_lock_file(), &spinner.allowed);
+    if lock.is_locked() {
+        info!("Rotation is {}", lock.state());
+    }
+    spinner.frontends.send_lock(lock.is_locked());
+    let mut control = open_control();
+    // A rotation asked for over the control socket, and what was detected
+    // when it was (it's kept until something else is).
+    let mut forced: Option<(Option<Rotation>, Rotation)> = None;
+    let mut paused = false;
+    let started = Instant::now();
 
     let mut rval = 0;
     info!("Spinning...");
     'mainloop: loop {
-        match sigrx.try_recv() {
-            Ok(s)   => {
-                warn!("Recieved {:?}, closing...", s);
-                break 'mainloop
-            },
-            Err(mpsc::TryRecvError::Empty)  => {},
-            Err(mpsc::TryRecvError::Disconnected)   => {
-                error!("Signal handler died unexpectedly! Aborting!");
-                rval = 17;
-                break 'mainloop
-            },
-        } // match sigrx.try_recv()
-
-        orientation = orient.orientation();
-        if orientation.is_some() {
-            trace!("Orientation is {}", orientation.unwrap());
-            if last_change != orientation {
-                last_change = orientation;
-                last_change_time = Instant::now();
-            } else {
-                if last_change != last_written && last_change_time.elapsed() >= delay {
-                    info!("Writing {} to {}", orientation.unwrap(), frontend);
-                    // `unwrap` is safe here because we've already checked 
-                    // that orientation isn't None.
-                    match frontend.send(orientation.unwrap()) {
-                        Ok(_)   => { last_written = orientation; },
+        loop {
+            match signals.next() {
+                Ok(Some(libc::SIGHUP))  => {
+                    info!("Recieved SIGHUP, reloading...");
+                    spinner = match spinner.reload() {
+                        Ok(s)   => s,
                         Err(e)  => {
-                            error!("Error sending rotation! ({})", e);
-                            if quit_on_rotation_send_error() {
-                                rval = 4;
-                                break 'mainloop
-                            }
+                            error!("Nothing left to run with! Aborting!");
+                            rval = e;
+                            break 'mainloop
+                        },
+                    };
+                    read_now = true;
+                    // The new frontends haven't been told where we are yet.
+                    if let Some(r) = last_written {
+                        if spinner.frontends.send(r).is_err() {
+                            rval = 4;
+                            break 'mainloop
                         }
                     }
-                } // if last_change_time.elapsed() >= delay
-            } // if last_change != orientation
-        } // if orientation.is_some()
-        sleep(period);
+                    if let Some(p) = last_posture {
+                        spinner.frontends.send_posture(p);
+                    }
+                    // The lock file might have moved, too.
+                    let old_lock = lock.state();
+                    lock = RotationLock::open(get_lock_file(), &spinner.allowed);
+                    if lock.state() != old_lock {
+                        info!("Rotation is {}", lock.state());
+                    }
+                    spinner.frontends.send_lock(lock.is_locked());
+                    // Closed first, so it can be bound again.
+                    drop(control.take());
+                    control = open_control();
+                },
+                Ok(Some(libc::SIGUSR1)) => if lock.lock(last_written) {
+                    lock_changed(&lock, &mut spinner.frontends);
+                },
+                Ok(Some(libc::SIGUSR2)) => if lock.unlock() {
+                    lock_changed(&lock, &mut spinner.frontends);
+                },
+                Ok(Some(s)) => {
+                    warn!("Recieved {}, closing...", signal_name(s));
+                    break 'mainloop
+                },
+                Ok(None)    => break,
+                Err(e)  => {
+                    error!("Couldn't read signals! ({}) Aborting!", e);
+                    rval = 17;
+                    break 'mainloop
+                },
+            }
+        } // loop over signals
+        if lock.refresh() {
+            lock_changed(&lock, &mut spinner.frontends);
+        }
+        if let Some(ref mut control) = control {
+            control.serve(|request| match request {
+                // Nothing else can be sent, so nothing else can be locked
+                // at (or rotated to) either.
+                Request::Lock(Some(r)) | Request::Rotate(r) if !spinner.allowed.contains(&r)   => {
+                    Err(format!("{} isn't allowed", r))
+                },
+                Request::Status => {
+                    let show = |r: Option<Rotation>| r.map_or("none".to_owned(), |r| r.to_string());
+                    let mut status = vec![
+                        ("rotation", show(last_written)),
+                        ("detected", show(orientation)),
+                        ("posture", last_posture.map_or("unknown".to_owned(), |p| p.to_string())),
+                        ("lock", lock.state().to_string()),
+                        ("paused", paused.to_string()),
+                        ("backend", spinner.orient.active_name()),
+                        ("frontends", spinner.frontends.to_string()),
+                    ];
+                    status.extend(spinner.orient.readings());
+                    status.extend(vec![
+                        ("delay", format!("{}ms", spinner.delay.as_millis())),
+                        ("period", format!("{}ms", spinner.rate.period().as_millis())),
+                        ("interval", format!("{}ms", spinner.rate.interval().as_millis())),
+                        ("sensitivity", sensitivity().to_string()),
+                        ("uptime", format!("{}s", started.elapsed().as_secs())),
+                    ]);
+                    Ok(status.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+                },
+                Request::Lock(r)    => {
+                    if lock.lock(r.or(last_written)) {
+                        lock_changed(&lock, &mut spinner.frontends);
+                    }
+                    Ok(vec![("lock".to_owned(), lock.state().to_string())])
+                },
+                Request::Unlock => {
+                    if lock.unlock() {
+                        lock_changed(&lock, &mut spinner.frontends);
+                    }
+                    Ok(vec![("lock".to_owned(), lock.state().to_string())])
+                },
+                Request::Set(setting)   => {
+                    spinner.set(setting);
+                    Ok(Vec::new())
+                },
+                Request::Rotate(r)  => {
+                    info!("Asked to rotate to {}", r);
+                    // Locked, it moves the lock instead (or it'd go
+                    // nowhere).
+                    if lock.is_locked() {
+                        if lock.lock(Some(r)) {
+                            lock_changed(&lock, &mut spinner.frontends);
+                        }
+                    } else {
+                        forced = Some((orientation, r));
+                    }
+                    Ok(Vec::new())
+                },
+                Request::Pause  => {
+                    if !paused {
+                        info!("Pausing");
+                        paused = true;
+                    }
+                    Ok(Vec::new())
+                },
+                Request::Resume => {
+                    if paused {
+                        info!("Resuming");
+                        paused = false;
+                        read_now = true;
+                    }
+                    Ok(Vec::new())
+                },
+            });
+        }
+
+        let now = Instant::now();
+        let due = match spinner.orient.event_fd() {
+            Some(fd)    => wakeup.is_ready(fd) || (now >= next_read && spinner.settling()),
+            None    => now >= next_read,
+        };
+        if !paused && (due || read_now) {
+            read_now = false;
+            let detected = spinner.orient.orientation().unwrap_or_else(|e| {
+                error!("Couldn't get orientation! ({})", e);
+                None
+            });
+            orientation = spinner.map_rotation(detected);
+            if let Some((at, _)) = forced {
+                if orientation.is_some() && orientation != at {
+                    debug!("Turned since being asked to rotate; following the device again");
+                    forced = None;
+                }
+            }
+            spinner.adapt(now - last_read, now);
+            last_read = now;
+            next_read = now + spinner.rate.interval();
+            let p = spinner.orient.posture();
+            if p != posture {
+                posture = p;
+                posture_since = now;
+            }
+            if let Some(o) = orientation {
+                trace!("Orientation is {}", o);
+                if last_change != orientation {
+                    last_change = orientation;
+                    last_change_time = now;
+                }
+            }
+        }
+        // Locked wherever it is, it stays there from now on (or from the
+        // first rotation written, if there isn't one yet).
+        if LockState::Locked(None) == lock.state() && last_written.is_some() {
+            lock.lock(last_written);
+        }
+        // This is checked on every wakeup (not just new readings), so a
+        // change still gets written once the delay is up even if the
+        // orientator has nothing new to say. While the rotation's locked,
+        // it goes straight to the one it's locked at instead (and likewise
+        // one asked for over the control socket).
+        let target = lock.rotation().or(forced.map(|f| f.1)).or_else(|| orientation.filter(|_| {
+            last_change == orientation && last_change_time.elapsed() >= spinner.delay
+        }));
+        if let Some(o) = target {
+            if !spinner.frontends.is_current(o) {
+                if Some(o) != last_written {
+                    info!("Writing {} to {}", o, spinner.frontends);
+                    last_written = Some(o);
+                }
+                // Frontends that failed last time get retried here
+                // until they catch up.
+                if spinner.frontends.send(o).is_err() {
+                    rval = 4;
+                    break 'mainloop
+                }
+            }
+        }
+        if let Some(p) = posture {
+            if Some(p) != last_posture && posture_since.elapsed() >= spinner.delay {
+                info!("Device is {}", p);
+                spinner.frontends.send_posture(p);
+                last_posture = posture;
+            }
+        }
+        spinner.frontends.poll();
+
+        wakeup = Wakeup::new();
+        wakeup.fd(signals.as_raw_fd());
+        if !paused {
+            match spinner.orient.event_fd() {
+                Some(fd) if Instant::now() >= next_read => wakeup.fd(fd),
+                _   => wakeup.at(next_read),
+            }
+            // Until it's settled, it's read every period whether or not it
+            // has anything new.
+            if spinner.settling() {
+                wakeup.at(next_read);
+            }
+            if let Some(retry) = spinner.orient.next_retry() {
+                wakeup.at(retry);
+            }
+        }
+        if let Some(r) = lock.rotation().or(forced.map(|f| f.1)) {
+            if !spinner.frontends.is_current(r) {
+                // Some frontend is failing; retry it next period.
+                wakeup.after(spinner.rate.period());
+            }
+        } else if let Some(o) = orientation {
+            if last_change == orientation && !spinner.frontends.is_current(o) {
+                let commit = last_change_time + spinner.delay;
+                if commit > Instant::now() {
+                    wakeup.at(commit);
+                } else {
+                    // Some frontend is failing; retry it next period.
+                    wakeup.after(spinner.rate.period());
+                }
+            }
+        }
+        if posture.is_some() && posture != last_posture {
+            wakeup.at(posture_since + spinner.delay);
+        }
+        lock.wake_on(&mut wakeup);
+        if let Some(ref control) = control {
+            control.wake_on(&mut wakeup);
+        }
+        spinner.frontends.wake_on(&mut wakeup);
+        if let Err(e) = wakeup.wait() {
+            error!("Couldn't wait for anything! ({}) Aborting!", e);
+            rval = 17;
+            break 'mainloop
+        }
     } // 'mainloop: loop
-    // unwrapping because it should rejoin nicely
-    // and it doesn't matter TOO much if it panics.
-    handle.join().unwrap();
     return rval;
 }
 
 
+/// Open the control socket (unless it's been turned off).
+///
+/// spinnrd carries on without it if it can't be opened.
+fn open_control() -> Option<ControlSocket> {
+    let path = arg_value("control_socket").unwrap_or_else(|| DEFAULT_CONTROL_SOCKET.to_owned());
+    if path.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(parse_path(&path, false));
+    let mode = arg_value("control_mode").unwrap_or_else(|| DEFAULT_CONTROL_MODE.to_owned());
+    let mode = match u32::from_str_radix(&mode, 8) {
+        Ok(m)   => m,
+        Err(_)  => {
+            error!("Bad --control-mode '{}'; not opening the control socket", mode);
+            return None;
+        },
+    };
+    match ControlSocket::bind(path.clone(), mode) {
+        Ok(c)   => {
+            debug!("Listening for control requests on {}", path.display());
+            Some(c)
+        },
+        Err(e)  => {
+            warn!("Couldn't open the control socket {} ({}); carrying on without it",
+                  path.display(), e);
+            None
+        },
+    }
+}
+
+/// Log a change to the rotation lock, and tell the frontends.
+fn lock_changed(lock: &RotationLock, frontends: &mut Frontends) {
+    info!("Rotation is {}", lock.state());
+    frontends.send_lock(lock.is_locked());
+}
+
 /// Returns true if we are to daemonize
 #[inline]
 fn is_daemon() -> bool {
-    CLI_ARGS.is_present("daemonize")
+    arg_flag("daemonize")
 }
 
-/// Initializes the signal handler
-fn init_sigtrap(sigs: &[Signal]) -> (thread::JoinHandle<()>, mpsc::Receiver<Signal>) {
-    debug!("initializing signal trap...");
-    let mut sigtrap = Trap::trap(sigs);
-    let (tx, rx) = mpsc::sync_channel::<Signal>(1);
-    let handle = thread::spawn(move || tx.send(sigtrap.next().unwrap()).unwrap());
-    (handle, rx)
+/// Starts receiving signals through a file descriptor
+///
+/// The signals are blocked until it's dropped.
+fn init_signals(sigs: &[libc::c_int]) -> Result<SignalFd, IoError> {
+    debug!("initializing signal fd...");
+    SignalFd::new(sigs)
 }
 
-/// Get frontend options from command line
-fn get_frontend_options() -> (Vec<String>, HashMap<String, HashMap<String, String>>) {
-    lazy_static! {
-        static ref FRONTEND_RE: Regex = Regex::new(r"^(?x)
-        (?P<frontend>\w+)
-        (?P<options>,
-            (?:
-                (?:[^;]*[^;\\])?
-                (?:\\\\)*
-                \\;
-            )*
-            [^;]+
-        )?").unwrap();
-    }
-    let mut frontlist = Vec::new();
-    let mut optmap = HashMap::new();
-    let frontends = CLI_ARGS.value_of("frontend").unwrap_or(DEFAULT_FRONTEND);
-    let frontend_options = CLI_ARGS.value_of("frontend_opts").unwrap_or(DEFAULT_FRONTEND_OPTS);
-    for caps in FRONTEND_RE.captures_iter(frontend_options) {
-        let frontend = &caps["frontend"];
-        if ! optmap.contains_key(frontend) {
-            optmap.insert(frontend.to_owned(), HashMap::new());
+/// A frontend (or backend) and its options
+type Spec = (String, HashMap<String, String>);
+
+/// Get the frontends to use (and their options) from the config file and
+/// command line.
+///
+/// Every `;`-separated group is used at once; within a group, the first of
+/// the `|`-separated alternatives that works is used. Options given with a
+/// frontend apply only to it, on top of any set for that kind of frontend
+/// with `--frontend-options` or in the config file.
+fn get_frontend_options() -> Vec<Vec<Spec>> {
+    let mut optmap = config::with_config(|c| c.frontend_options());
+    let frontend_options: Vec<&str> = CLI_ARGS.values_of("frontend_opts")
+        .map(|v| v.collect())
+        .unwrap_or_else(|| vec![DEFAULT_FRONTEND_OPTS]);
+    for entry in frontend_options.into_iter().flat_map(|o| split_unescaped(o, ';')) {
+        if let Some((frontend, opts)) = parse_spec(entry) {
+            optmap.entry(frontend).or_default().extend(opts);
         }
-        parse_options(
-            &caps.name("options").map(|m| m.as_str()).unwrap_or(""),
-            optmap.get_mut(frontend).unwrap());
-    }
-    for caps in FRONTEND_RE.captures_iter(frontends) {
-        let frontend = &caps["frontend"];
-        if ! optmap.contains_key(frontend) {
-            optmap.insert(frontend.to_owned(), HashMap::new());
+    }
+    let frontends = arg_value("frontend").unwrap_or_else(|| DEFAULT_FRONTEND.to_owned());
+    frontend_groups(&frontends, &optmap)
+}
+
+/// Split a `--frontend` list into its groups of alternatives, giving each
+/// frontend the options in `optmap` for its kind, plus any given with it.
+fn frontend_groups(frontends: &str, optmap: &HashMap<String, HashMap<String, String>>) -> Vec<Vec<Spec>> {
+    split_unescaped(frontends, ';').into_iter()
+        .map(|group| split_unescaped(group, '|').into_iter()
+             .filter_map(parse_spec)
+             .map(|(frontend, inline)| {
+                 let mut opts = optmap.get(&frontend).cloned().unwrap_or_default();
+                 opts.extend(inline);
+                 (frontend, opts)
+             })
+             .collect::<Vec<Spec>>())
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+/// Split `s` at every `sep` that isn't escaped with a backslash.
+fn split_unescaped(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if '\\' == c {
+            escaped = true;
+        } else if sep == c {
+            parts.push(&s[start..i]);
+            start = i + c.len_utf8();
         }
-        frontlist.push(frontend.to_owned());
-        parse_options(
-            &caps.name("options").map(|m| m.as_str()).unwrap_or(""),
-            optmap.get_mut(frontend).unwrap());
+    }
+    parts.push(&s[start..]);
+    parts
+}
 
+/// Parse a single `NAME[,OPT=VALUE]...`.
+fn parse_spec(spec: &str) -> Option<Spec> {
+    let spec = spec.trim();
+    let end = spec.find(',').unwrap_or(spec.len());
+    let name = &spec[..end];
+    if name.is_empty() {
+        return None;
+    }
+    if ! name.chars().all(|c| c.is_alphanumeric() || '_' == c) {
+        warn!("Ignoring '{}': '{}' isn't a valid name", spec, name);
+        return None;
     }
-    return (frontlist,optmap)
+    let mut opts = HashMap::new();
+    parse_options(&spec[end..], &mut opts);
+    Some((name.to_owned(), opts))
 }
 
-/// Get backend options from command line
-fn get_backend_options() -> (Vec<String>, HashMap<String, HashMap<String, String>>) {
-    lazy_static! {
-        static ref BACKEND_RE: Regex = Regex::new(r"^(?x)
-        (?P<backend>\w+)
-        (?P<options>,
-            (?:
-                (?:[^;]*[^;\\])?
-                (?:\\\\)*
-                \\;
-            )*
-            [^;]+
-        )?").unwrap();
-    }
-    let mut backlist = Vec::new();
-    let mut optmap = HashMap::new();
-    let backends = CLI_ARGS.value_of("backend").unwrap_or(DEFAULT_BACKEND);
-    let backend_options = CLI_ARGS.value_of("backend_opts").unwrap_or(DEFAULT_BACKEND_OPTS);
-    for caps in BACKEND_RE.captures_iter(backend_options) {
-        let backend = &caps["backend"];
-        if ! optmap.contains_key(backend) {
-            optmap.insert(backend.to_owned(), HashMap::new());
-        }
-        parse_options(
-            &caps.name("options").map(|m| m.as_str()).unwrap_or(""),
-            optmap.get_mut(backend).unwrap());
-    }
-    for caps in BACKEND_RE.captures_iter(backends) {
-        let backend = &caps["backend"];
-        if ! optmap.contains_key(backend) {
-            optmap.insert(backend.to_owned(), HashMap::new());
+/// Get the backends to try (and their options) from quirks, the config
+/// file, and the command line, most preferred first.
+///
+/// Options given with a backend apply only to it, on top of any set for
+/// that kind of backend by quirks, the config file, or
+/// `--backend-options` (in that order).
+fn get_backend_options() -> Vec<Spec> {
+    // Quirks go in first, then the config file, so anything on the command
+    // line overrides them.
+    let mut optmap = quirks::quirk_backend_options(&quirks::get_dmi_root(),
+                                                    &quirks::get_quirks_dir());
+    for (backend, opts) in config::with_config(|c| c.backend_options()) {
+        optmap.entry(backend).or_default().extend(opts);
+    }
+    let backend_options: Vec<&str> = CLI_ARGS.values_of("backend_opts")
+        .map(|v| v.collect())
+        .unwrap_or_else(|| vec![DEFAULT_BACKEND_OPTS]);
+    for entry in backend_options.into_iter().flat_map(|o| split_unescaped(o, ';')) {
+        if let Some((backend, opts)) = parse_spec(entry) {
+            optmap.entry(backend).or_default().extend(opts);
         }
-        backlist.push(backend.to_owned());
-        parse_options(
-            &caps.name("options").map(|m| m.as_str()).unwrap_or(""),
-            optmap.get_mut(backend).unwrap());
-
     }
-    return (backlist,optmap)
+    let backends = arg_value("backend").unwrap_or_else(|| DEFAULT_BACKEND.to_owned());
+    split_unescaped(&backends, ';').into_iter()
+        .filter_map(parse_spec)
+        .map(|(backend, inline)| {
+            let mut opts = optmap.get(&backend).cloned().unwrap_or_default();
+            opts.extend(inline);
+            (backend, opts)
+        })
+        .collect()
 }
 
 fn parse_options<'s>(optstr: &'s str, optmap: &mut HashMap<String, String>) {
@@ -601,64 +1158,85 @@ fn parse_options<'s>(optstr: &'s str, optmap: &mut HashMap<String, String>) {
         [^,;]+)").unwrap();
     }
     for caps in OPT_RE.captures_iter(optstr) {
-        //FIXME: Un-escape commas and semicolons
-        optmap.insert((&caps["name"]).to_owned(), (&caps["value"]).to_owned());
+        optmap.insert((&caps["name"]).to_owned(), unescape_option(&caps["value"]));
 
     }
 }
 
-/// Something that can give the device's orientation.
-pub trait Orientator {
-    /// Returns the current orientation, if it can figure it out.
-    fn orientation(&mut self) -> Option<Rotation>;
+/// Un-escape the commas, semicolons, pipes, and backslashes in an option
+/// value.
+fn unescape_option(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if '\\' != c {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(e) if ',' == e || ';' == e || '|' == e || '\\' == e => unescaped.push(e),
+            Some(e) => { unescaped.push(c); unescaped.push(e); },
+            None    => unescaped.push(c),
+        }
+    }
+    unescaped
 }
 
+/// The orientation, if it could be figured out, or why it couldn't be read
+pub type OrientationResult = Result<Option<Rotation>, IoError>;
 
-#[derive(Debug,PartialEq,Clone,Copy)]
-pub enum Rotation {
-    Normal,
-    Left,
-    Inverted,
-    Right,
-}
-use self::Rotation::*;
+/// Something that can give the device's orientation.
+pub trait Orientator {
+    /// Returns the current orientation, if it can figure it out, or an
+    /// error if reading it failed.
+    fn orientation(&mut self) -> OrientationResult;
+
+    /// A file descriptor that's readable when there's a new reading, for
+    /// orientators that don't need polling. `None` means they're read
+    /// every period.
+    fn event_fd(&self) -> Option<RawFd> {
+        None
+    }
 
-// pub struct RotParseErr (
-#[derive(Debug)]
-pub enum RotParseErrKind {
-    TooShort,
-    TooLong,
-    NoMatch,
-}
+    /// How much the (filtered) acceleration changed with the last reading,
+    /// in m/s^2. `None` if the orientator doesn't know, in which case it's
+    /// always read at the fast rate.
+    fn motion(&self) -> Option<f64> {
+        None
+    }
 
-impl Default for Rotation {
-    fn default() -> Rotation {
-        Rotation::Normal
+    /// Whether the device was lying flat at the last reading, if the
+    /// orientator can tell.
+    fn posture(&self) -> Option<Posture> {
+        None
     }
-}
 
-impl Display for Rotation {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        match self {
-            &Normal => write!(f, "normal"),
-            &Left   => write!(f, "left"),
-            &Inverted   => write!(f, "inverted"),
-            &Right  => write!(f, "right"),
-        }
+    /// Whether readings are being ignored because the device was just
+    /// shaken (so it has to be read again to find out when it's settled).
+    fn shaken(&self) -> bool {
+        false
     }
-}
 
-lazy_static! {
-    static ref NOW_UTC: DateTime<Utc> = Utc::now();
-    static ref NOW_LOCAL: DateTime<Local> = Local::now();
+    /// The last readings it went by, labelled, for `status` on the control
+    /// socket.
+    fn readings(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
 }
 
+
 /// Gets the path to the pid file.
 #[inline]
 fn get_pid_file() -> PathBuf {
     get_path("pidfile", DEFAULT_PID_FILE, false)
 }
 
+/// Gets the path to the rotation lock file.
+#[inline]
+fn get_lock_file() -> PathBuf {
+    get_path("lockfile", DEFAULT_LOCK_FILE, false)
+}
+
 /// Gets the user spinnrd should run as
 fn get_user() -> daemonize::User {
     CLI_ARGS.value_of("user")
@@ -697,92 +1275,8 @@ fn get_group() -> daemonize::Group {
  * }
  */
 
-macro_rules! timef {
-    ( $now:ident, $str:expr, $($func:ident),* ) => {
-        timef!(@inner $now, $str, $($func),*$(,)*)
-    };
-    ( $now:ident $func:ident ) => {
-        timef!(@inner $now, "{}", $func)
-    };
-    ( $now:ident $f1:ident $f2:ident ) => {
-        timef!(@inner $now, "{}.{}", $f1, $f2)
-    };
-    ( $now:ident: $str:expr ) => {
-        format!("{}",$now.format($str))
-    };
-    ( @inner $now:ident, $str:expr, $($func:ident),* ) => {
-        format!($str, $($now.$func()),*)
-    };
-}
-
-fn parse_path(input: &str, isdir: bool) -> String {
-    lazy_static! {
-        static ref PATH_RE: Regex = Regex::new(r"(?x)
-        % (
-            # The underscore-able basic matches
-            _?[eEtT] |
-            # The other basic matches
-            [dx%] |
-            # A custom format string
-            ([fF]) \{
-                # The format string
-                (
-                    # Allow %} to print close brace
-                    (?:
-                        # Any number of non-close-brace characters (lazy)
-                        [^\}]*?
-                        # An optional (printed) close brace
-                        (?:
-                            # First, a non-'%' character
-                            [^%]
-                            # Any number of printed '%'s
-                            (?:%{2})*
-                            # Then '%}'
-                            %\}
-                        # Optionally
-                        )?
-                    # Repeat any number of times and there you go!
-                    )*
-                )
-            \}
-        )").unwrap();
-        static ref BRACE_RE: Regex = Regex::new(r"((?:%{2})*)%\}").unwrap();
-    }
-    PATH_RE.replace_all(input, |caps: &Captures| {
-        match &caps[1] {
-            "e"  => timef!(NOW_UTC timestamp),
-            "_e" => timef!(NOW_UTC timestamp_millis),
-    
-            "E"  => timef!(NOW_UTC timestamp timestamp_subsec_nanos),
-            "_E"  => timef!(NOW_UTC timestamp timestamp_subsec_millis),
-            "d"  => {
-                if isdir {"%d".to_owned()}
-                else { (*WORKING_DIR).to_string_lossy().into_owned() }
-            },
-            "t"  => timef!(NOW_LOCAL: STRF_8601_BASIC),
-            "_t" => timef!(NOW_LOCAL: STRF_8601_BASIC_NS),
-            "T"  => timef!(NOW_UTC: STRF_8601_BASIC),
-            "_T" => timef!(NOW_UTC: STRF_8601_BASIC_NS),
-            "x"  => std::env::var("XDG_RUNTIME_DIR")
-                .unwrap_or_else(|_| "/tmp".to_owned()),
-            x    => {
-                //not sure this'll work, but it conveys the gist
-                if caps.len() > 3 && 0 < (&caps[2]).len() {
-                    let fstr = BRACE_RE.replace_all(&caps[3], "${1}}").to_owned();
-                    match &caps[2] {
-                        "f" => format!("{}",NOW_LOCAL.format(&fstr)),
-                        "F" => format!("{}",NOW_UTC.format(&fstr)),
-                        _   => String::new(),
-                    }
-                } else {
-                    format!("%{}", x)
-                }
-            },
-        }
-    }).into_owned()
-}
 fn get_path(name: &str, default: &str, isdir: bool) -> PathBuf {
-    PathBuf::from(parse_path(CLI_ARGS.value_of(name).unwrap_or(default), isdir))
+    PathBuf::from(parse_path(&arg_value(name).unwrap_or_else(|| default.to_owned()), isdir))
 }
 
 lazy_static! {
@@ -823,10 +1317,10 @@ fn get_working_dir() -> Result<PathBuf, IoError> {
 /// Get the list of orientators to try
 
 
-/// Get the u32 value of an argument to a command-line option.
+/// Get the u32 value of an option (see `config::arg_value`).
 /// Returns `None` if parsing fails.
 fn get_u32_arg_val(name: &str) -> Option<u32> {
-    if let Some(s) = CLI_ARGS.value_of(name) {
+    if let Some(s) = arg_value(name) {
         s.parse::<u32>().map_err(|e|
                                  warn!("Can't parse '{}' as a uint ({})!", s, e)
                                 )
@@ -834,10 +1328,10 @@ fn get_u32_arg_val(name: &str) -> Option<u32> {
     } else { None }
 }
 
-/// Get the f64 value of an argument to a command-line option.
+/// Get the f64 value of an option (see `config::arg_value`).
 /// Returns `None` if parsing fails.
 fn get_f64_arg_val(name: &str) -> Option<f64> {
-    if let Some(s) = CLI_ARGS.value_of(name) {
+    if let Some(s) = arg_value(name) {
         s.parse::<f64>().map_err(|e|
                                  warn!("Can't parse '{}' as a float ({})!", s, e)
                                 )
@@ -863,8 +1357,35 @@ fn validate_f64(v: String) -> Result<(), String> {
     }
 }
 
+/// Parse a list of rotations separated by commas.
+fn parse_rotations(v: &str) -> Result<Vec<Rotation>, String> {
+    v.split(',')
+        .map(|r| r.parse::<Rotation>().map_err(|e| format!("{} ('{}')", e, r.trim())))
+        .collect()
+}
+
+/// Check that an argument is a list of rotations
+fn validate_rotations(v: String) -> Result<(), String> {
+    parse_rotations(&v).map(|_| ())
+}
+
+/// Check that an argument is an octal file mode
+fn validate_mode(v: String) -> Result<(), String> {
+    u32::from_str_radix(&v, 8).map(|_| ()).map_err(|_| format!("Try using an octal mode, like 660, not {}.", v))
+}
+
+/// Check that an argument isn't empty
+fn validate_nonempty(v: String) -> Result<(), String> {
+    if v.is_empty() { Err("it can't be empty".to_owned()) } else { Ok(()) }
+}
+
+/// Check that an argument is a rotation
+fn validate_rotation(v: String) -> Result<(), String> {
+    v.parse::<Rotation>().map(|_| ()).map_err(|e| format!("{} ('{}')", e, v))
+}
+
 /// Returns true if we should quit if an error occurs
-/// when sending rotation.
+/// when sending rotation (for frontends without a `quit_on_error` option).
 fn quit_on_rotation_send_error() -> bool {
     //TODO: addopt //addopt means add command line option
     DEFAULT_QUIT_ON_ROTATION_SEND_ERR
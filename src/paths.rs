@@ -0,0 +1,122 @@
+//! # paths
+//! Filename formatting, shared by spinnrd and its clients.
+//!
+//! Expects the crate root to provide `WORKING_DIR`, the directory `%d`
+//! expands to.
+
+use super::WORKING_DIR;
+
+use chrono::{DateTime, Utc, Local};
+// Apparantly I need this for some of the things I'm doing, even though it 
+// never gets used explicitly.
+#[allow(unused_imports)]
+use chrono::TimeZone;
+use regex::{Regex,Captures};
+
+/// The default spinfile
+pub const DEFAULT_SPINFILE: &'static str = "%d/spinnrd.spin";
+
+/// The default working directory
+pub const DEFAULT_WORKING_DIRECTORY: &'static str = "/run/spinnrd";
+
+///# Formatting Arguments
+///`strftime` string for basic ISO 8601
+const STRF_8601_BASIC: &'static str = "%Y%m%dT%H%M%S%z";
+
+///`strftime` string for basic ISO 8601, with nanoseconds
+const STRF_8601_BASIC_NS: &'static str = "%Y%m%dT%H%M%S.%f%z";
+
+lazy_static! {
+    static ref NOW_UTC: DateTime<Utc> = Utc::now();
+    static ref NOW_LOCAL: DateTime<Local> = Local::now();
+}
+
+macro_rules! timef {
+    ( $now:ident, $str:expr, $($func:ident),* ) => {
+        timef!(@inner $now, $str, $($func),*$(,)*)
+    };
+    ( $now:ident $func:ident ) => {
+        timef!(@inner $now, "{}", $func)
+    };
+    ( $now:ident $f1:ident $f2:ident ) => {
+        timef!(@inner $now, "{}.{}", $f1, $f2)
+    };
+    ( $now:ident: $str:expr ) => {
+        format!("{}",$now.format($str))
+    };
+    ( @inner $now:ident, $str:expr, $($func:ident),* ) => {
+        format!($str, $($now.$func()),*)
+    };
+}
+
+/// Expand the `%` variables in a filename (see the FILENAME FORMATTING
+/// section of the help).
+///
+/// `%d` is left alone if `isdir` is set, since it's the working directory
+/// itself being parsed.
+pub fn parse_path(input: &str, isdir: bool) -> String {
+    lazy_static! {
+        static ref PATH_RE: Regex = Regex::new(r"(?x)
+        % (
+            # The underscore-able basic matches
+            _?[eEtT] |
+            # The other basic matches
+            [dx%] |
+            # A custom format string
+            ([fF]) \{
+                # The format string
+                (
+                    # Allow %} to print close brace
+                    (?:
+                        # Any number of non-close-brace characters (lazy)
+                        [^\}]*?
+                        # An optional (printed) close brace
+                        (?:
+                            # First, a non-'%' character
+                            [^%]
+                            # Any number of printed '%'s
+                            (?:%{2})*
+                            # Then '%}'
+                            %\}
+                        # Optionally
+                        )?
+                    # Repeat any number of times and there you go!
+                    )*
+                )
+            \}
+        )").unwrap();
+        static ref BRACE_RE: Regex = Regex::new(r"((?:%{2})*)%\}").unwrap();
+    }
+    PATH_RE.replace_all(input, |caps: &Captures| {
+        match &caps[1] {
+            "e"  => timef!(NOW_UTC timestamp),
+            "_e" => timef!(NOW_UTC timestamp_millis),
+    
+            "E"  => timef!(NOW_UTC timestamp timestamp_subsec_nanos),
+            "_E"  => timef!(NOW_UTC timestamp timestamp_subsec_millis),
+            "d"  => {
+                if isdir {"%d".to_owned()}
+                else { (*WORKING_DIR).to_string_lossy().into_owned() }
+            },
+            "t"  => timef!(NOW_LOCAL: STRF_8601_BASIC),
+            "_t" => timef!(NOW_LOCAL: STRF_8601_BASIC_NS),
+            "T"  => timef!(NOW_UTC: STRF_8601_BASIC),
+            "_T" => timef!(NOW_UTC: STRF_8601_BASIC_NS),
+            "x"  => std::env::var("XDG_RUNTIME_DIR")
+                .unwrap_or_else(|_| "/tmp".to_owned()),
+            x    => {
+                //not sure this'll work, but it conveys the gist
+                if caps.len() > 3 && 0 < (&caps[2]).len() {
+                    let fstr = BRACE_RE.replace_all(&caps[3], "${1}}").to_owned();
+                    match &caps[2] {
+                        "f" => format!("{}",NOW_LOCAL.format(&fstr)),
+                        "F" => format!("{}",NOW_UTC.format(&fstr)),
+                        _   => String::new(),
+                    }
+                } else {
+                    format!("%{}", x)
+                }
+            },
+        }
+    }).into_owned()
+}
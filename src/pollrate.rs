@@ -0,0 +1,153 @@
+//! # pollrate
+//! How often the orientator is polled: every `--interval` while the device
+//! is moving, and every `--slow-interval` once it's been still for
+//! `--hysteresis`.
+
+use std::time::{Duration, Instant};
+
+/// Decides the polling interval from how fast the acceleration is
+/// changing.
+pub struct PollRate {
+    /// The interval to poll at while the device is moving
+    period: Duration,
+    /// The interval to poll at while it's still, if it changes at all
+    slow_period: Option<Duration>,
+    /// How fast the acceleration has to change (in m/s^2 per second) to
+    /// count as moving
+    threshold: f64,
+    /// How long the device has to be still to slow down
+    hysteresis: Duration,
+    /// The interval being polled at now
+    interval: Duration,
+    /// When the device was last moving
+    last_motion: Instant,
+}
+
+impl PollRate {
+    pub fn new(period: Duration, slow_period: Option<Duration>, threshold: f64,
+               hysteresis: Duration) -> PollRate
+    {
+        PollRate {
+            period,
+            slow_period,
+            threshold,
+            hysteresis,
+            interval: period,
+            last_motion: Instant::now(),
+        }
+    }
+
+    /// The interval to poll at while the device is moving.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The interval being polled at now.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Whether the interval changes with how much the device is moving
+    /// (with `--slow-interval`).
+    pub fn is_adaptive(&self) -> bool {
+        self.slow_period.is_some()
+    }
+
+    /// Change the interval to poll at while the device is moving.
+    pub fn set_period(&mut self, period: Duration) {
+        // Polling slowly can wait until it next speeds up.
+        if self.interval == self.period {
+            self.interval = period;
+        }
+        self.period = period;
+    }
+
+    /// Go by a reading at `at`, `elapsed` after the last one, where the
+    /// acceleration changed by `motion` (in m/s^2). Returns the interval to
+    /// poll at from now on.
+    pub fn update(&mut self, motion: f64, elapsed: Duration, at: Instant) -> Duration {
+        let slow = match self.slow_period {
+            Some(s) => s,
+            None    => return self.interval,
+        };
+        if elapsed == Duration::from_secs(0) {
+            return self.interval;
+        }
+        // Per second, so the threshold doesn't depend on the interval
+        let motion = motion / elapsed.as_secs_f64();
+        let still_for = at.saturating_duration_since(self.last_motion);
+        if motion > self.threshold {
+            self.last_motion = at;
+            if self.interval != self.period {
+                debug!("Moving ({:.2} m/s^2 per second); polling every {}ms",
+                       motion, self.period.as_millis());
+                self.interval = self.period;
+            }
+        } else if self.interval != slow && still_for >= self.hysteresis {
+            debug!("Still for {}ms; polling every {}ms",
+                   still_for.as_millis(), slow.as_millis());
+            self.interval = slow;
+        }
+        self.interval
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(100);
+    const SLOW: Duration = Duration::from_millis(1000);
+
+    /// The intervals `rate` picks for `trace` (of ms and how much the
+    /// acceleration changed since the reading before).
+    fn run(rate: &mut PollRate, trace: &[(u64, f64)]) -> Vec<u64> {
+        let start = rate.last_motion;
+        let mut last = 0;
+        trace.iter()
+            .map(|&(t, motion)| {
+                let elapsed = Duration::from_millis(t - last);
+                last = t;
+                rate.update(motion, elapsed, start + Duration::from_millis(t)).as_millis() as u64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slows_down_when_still() {
+        let mut rate = PollRate::new(PERIOD, Some(SLOW), 1.0, Duration::from_millis(500));
+        // Moving (2 m/s^2 per second), then still for long enough to slow
+        // down, then picked up again.
+        let trace = [(100, 0.2), (200, 0.2), (300, 0.01), (400, 0.0), (500, 0.05),
+                     (600, 0.0), (700, 0.0), (1700, 1.5), (1800, 0.3), (1900, 0.0)];
+        assert_eq!(vec![100, 100, 100, 100, 100, 100, 1000, 100, 100, 100],
+                   run(&mut rate, &trace));
+    }
+
+    #[test]
+    fn motion_is_per_second() {
+        let mut rate = PollRate::new(PERIOD, Some(SLOW), 1.0, Duration::from_millis(0));
+        // The same change is fast over a short interval, but slow over a
+        // long one.
+        assert_eq!(vec![100, 1000, 100, 1000],
+                   run(&mut rate, &[(50, 0.1), (1050, 0.1), (1100, 0.1), (2100, 0.5)]));
+    }
+
+    #[test]
+    fn fixed_without_slow_period() {
+        let mut rate = PollRate::new(PERIOD, None, 1.0, Duration::from_millis(0));
+        assert_eq!(vec![100, 100, 100], run(&mut rate, &[(100, 0.0), (5000, 0.0), (5100, 3.0)]));
+    }
+
+    #[test]
+    fn new_period_waits_while_slow() {
+        let mut rate = PollRate::new(PERIOD, Some(SLOW), 1.0, Duration::from_millis(0));
+        assert_eq!(vec![1000], run(&mut rate, &[(100, 0.0)]));
+        rate.set_period(Duration::from_millis(50));
+        assert_eq!(SLOW, rate.interval());
+        assert_eq!(vec![50], run(&mut rate, &[(200, 1.0)]));
+        rate.set_period(Duration::from_millis(200));
+        assert_eq!(Duration::from_millis(200), rate.interval());
+    }
+}
@@ -0,0 +1,92 @@
+//! # protocol
+//! How spinnrd's control socket is spoken to, shared by spinnrd and
+//! spinnrctl.
+//!
+//! A request is one line: a command and its arguments, separated by
+//! whitespace (e.g. `set delay 500`). The response starts with a line that's
+//! `ok`, or `error` and what went wrong, followed by any number of
+//! `key: value` lines and then an empty line. A client can send as many
+//! requests as it likes over one connection.
+
+use std::io::BufRead;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+
+/// The default control socket
+pub const DEFAULT_CONTROL_SOCKET: &str = "%d/spinnrd.ctl";
+
+/// A response: the keys and values it reports, or what went wrong
+pub type Response = Result<Vec<(String, String)>, String>;
+
+/// Write out a response, including the empty line that ends it.
+pub fn format_response(response: &Response) -> String {
+    match response {
+        Ok(values)  => {
+            let mut out = "ok\n".to_owned();
+            for (key, value) in values {
+                out.push_str(&format!("{}: {}\n", key, value));
+            }
+            out + "\n"
+        },
+        // Keeping it to one line, so it can't end the response early
+        Err(e)  => format!("error {}\n\n", e.replace('\n', " ")),
+    }
+}
+
+/// Read a response (up to and including the empty line that ends it).
+pub fn read_response<R: BufRead>(reader: &mut R) -> Result<Response, IoError> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if 0 == reader.read_line(&mut line)? {
+            return Err(IoError::new(IoErrorKind::UnexpectedEof, "spinnrd hung up"));
+        }
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_owned());
+    }
+    let mut lines = lines.into_iter();
+    match lines.next() {
+        Some(ref s) if "ok" == s    => Ok(Ok(lines.map(|l| match l.find(": ") {
+            Some(i) => (l[..i].to_owned(), l[i + 2..].to_owned()),
+            None    => (l, String::new()),
+        }).collect())),
+        Some(ref s) if s.starts_with("error")   => Ok(Err(s["error".len()..].trim().to_owned())),
+        _   => Err(IoError::new(IoErrorKind::InvalidData, "not a response")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_round_trip() {
+        let responses: Vec<Response> = vec![
+            Ok(Vec::new()),
+            Ok(vec![("rotation".to_owned(), "left".to_owned()),
+                    ("lock".to_owned(), "locked at left".to_owned()),
+                    ("empty".to_owned(), String::new()),
+                    ("colons".to_owned(), "a: b: c".to_owned())]),
+            Err("no such command 'spin'".to_owned()),
+        ];
+        let mut stream = Vec::new();
+        for response in responses.iter() {
+            stream.extend(format_response(response).into_bytes());
+        }
+        let mut reader = &stream[..];
+        for response in responses.iter() {
+            assert_eq!(*response, read_response(&mut reader).unwrap());
+        }
+        assert_eq!(IoErrorKind::UnexpectedEof, read_response(&mut reader).unwrap_err().kind());
+
+        // Errors are kept to one line.
+        let multiline = format_response(&Err("one\ntwo".to_owned()));
+        assert_eq!(Err("one two".to_owned()), read_response(&mut multiline.as_bytes()).unwrap());
+        assert_eq!(IoErrorKind::InvalidData,
+                   read_response(&mut "hello\n\n".as_bytes()).unwrap_err().kind());
+    }
+}
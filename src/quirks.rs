@@ -0,0 +1,466 @@
+//! # quirks
+//! Per-device fixes (scale, sign, prefixes, mount matrices...), matched
+//! against the DMI identifiers in `/sys/class/dmi/id` and used to
+//! pre-populate the backend options.
+//!
+//! Quirks come from a built-in table and from any `*.toml` files in the
+//! quirks directory, which look like this:
+//!
+//! ```toml
+//! [[quirk]]
+//! name = "Some Tablet"
+//! sys_vendor = "Some Vendor"
+//! product_name = "ST-10*"
+//!
+//! [quirk.backend-options.fsaccel]
+//! mount_matrix = "0, 1, 0; -1, 0, 0; 0, 0, 1"
+//! ```
+//!
+//! The match fields accept `*` and `?` wildcards, every given field has to
+//! match, and a quirk without any match fields never matches. Quirks read
+//! later (built-in first, then the files in name order) override earlier
+//! ones.
+
+use super::*;
+
+use std::fs::read_dir;
+use std::io::Read;
+use std::path::Path;
+
+use config::OptionMap;
+
+/// The default directory DMI identifiers are read from
+pub const DEFAULT_DMI_ROOT: &str = "/sys/class/dmi/id";
+
+/// The default directory of user quirk files
+pub const DEFAULT_QUIRKS_DIR: &str = "/etc/spinnrd/quirks.d";
+
+/// The extension quirk files must have
+const QUIRK_FILE_EXT: &str = "toml";
+
+/// What to call the built-in table in messages
+const BUILTIN_SOURCE: &str = "<built-in>";
+
+/// The built-in quirks table.
+///
+/// These are the tablets whose accelerometers are mounted with their axes
+/// swapped or flipped and don't say so in a `mount_matrix`, as listed in
+/// systemd's `hwdb.d/60-sensor.hwdb`. The matrices are the same as the
+/// `ACCEL_MOUNT_MATRIX` values there, for every accelerometer backend.
+///
+/// hwdb entries also match the sensor's modalias (e.g. `acpi:BOSC0200*`);
+/// that's ignored here, since only the DMI identifiers are matched, so the
+/// matrix is used for whichever accelerometer the backend picks.
+const BUILTIN_QUIRKS: &str = r#"
+[[quirk]]
+name = "Asus Transformer Book T100TA"
+sys_vendor = "ASUSTeK*"
+product_name = "*T100TA*"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Asus Transformer Book T100CHI"
+sys_vendor = "ASUSTeK*"
+product_name = "*T100CHI*"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Asus Transformer Book T100HA"
+sys_vendor = "ASUSTeK*"
+product_name = "*T100HA*"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Asus Transformer Book Flip TP300L*"
+sys_vendor = "ASUSTeK*"
+product_name = "*TP300L?*"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, 1, 0; 1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Cube iWork8 Air"
+sys_vendor = "cube"
+product_name = "i1-TF"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; -1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Lenovo Ideapad Miix 320"
+sys_vendor = "LENOVO*"
+product_name = "80XF"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Lenovo Yoga 300-11IBR"
+sys_vendor = "LENOVO"
+product_name = "80M1"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "1, 0, 0; 0, -1, 0; 0, 0, 1" }
+
+[[quirk]]
+name = "Trekstor SurfTab twin 10.1"
+sys_vendor = "TrekStor"
+product_name = "SurfTab twin 10.1"
+
+[quirk.backend-options]
+fsaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+fsaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+iioaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+evdevaccel_raw = { mount_matrix = "0, -1, 0; 1, 0, 0; 0, 0, 1" }
+"#;
+
+/// The DMI identifiers quirks can be matched against.
+#[derive(Debug, Default)]
+pub struct Dmi {
+    pub sys_vendor: Option<String>,
+    pub product_name: Option<String>,
+    pub board_name: Option<String>,
+}
+
+impl Dmi {
+    /// Read the DMI identifiers from `root` (normally `/sys/class/dmi/id`).
+    pub fn from_dir(root: &Path) -> Dmi {
+        let read = |name: &str| {
+            let mut s = String::new();
+            match File::open(root.join(name)).and_then(|mut f| f.read_to_string(&mut s)) {
+                Ok(_)   => Some(s.trim().to_owned()),
+                Err(e)  => {
+                    debug!("Couldn't read DMI {} from {}: {}", name, root.to_string_lossy(), e);
+                    None
+                },
+            }
+        };
+        Dmi {
+            sys_vendor: read("sys_vendor"),
+            product_name: read("product_name"),
+            board_name: read("board_name"),
+        }
+    }
+}
+
+impl Display for Dmi {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let none = "<unknown>".to_owned();
+        write!(f, "sys_vendor: {}\nproduct_name: {}\nboard_name: {}",
+               self.sys_vendor.as_ref().unwrap_or(&none),
+               self.product_name.as_ref().unwrap_or(&none),
+               self.board_name.as_ref().unwrap_or(&none))
+    }
+}
+
+/// A device quirk.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Quirk {
+    pub name: String,
+    pub sys_vendor: Option<String>,
+    pub product_name: Option<String>,
+    pub board_name: Option<String>,
+    #[serde(rename = "backend-options", default)]
+    pub backend_options: OptionMap,
+}
+
+impl Quirk {
+    /// Whether this quirk applies to the device described by `dmi`.
+    pub fn matches(&self, dmi: &Dmi) -> bool {
+        let fields = [
+            (&self.sys_vendor, &dmi.sys_vendor),
+            (&self.product_name, &dmi.product_name),
+            (&self.board_name, &dmi.board_name),
+        ];
+        let mut any = false;
+        for (pattern, value) in fields.iter() {
+            if let Some(p) = pattern {
+                any = true;
+                match value {
+                    Some(v) if wildcard_match(p, v) => {},
+                    _   => return false,
+                }
+            }
+        }
+        any
+    }
+}
+
+/// The contents of a quirks file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct QuirkFile {
+    #[serde(default)]
+    quirk: Vec<Quirk>,
+}
+
+/// Match `value` against a pattern where `*` matches anything and `?`
+/// matches any single character.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let re = format!("^{}$", regex::escape(pattern)
+                     .replace(r"\*", ".*")
+                     .replace(r"\?", "."));
+    match Regex::new(&re) {
+        Ok(r)   => r.is_match(value),
+        Err(e)  => {
+            warn!("Bad quirk pattern '{}': {}", pattern, e);
+            false
+        },
+    }
+}
+
+/// A quirk and where it came from.
+pub struct SourcedQuirk {
+    pub source: String,
+    pub quirk: Quirk,
+}
+
+/// Parse the quirks in `contents`.
+fn parse_quirks(source: &str, contents: &str) -> Result<Vec<SourcedQuirk>, String> {
+    toml::from_str::<QuirkFile>(contents)
+        .map(|qf| qf.quirk.into_iter()
+             .map(|q| SourcedQuirk { source: source.to_owned(), quirk: q })
+             .collect())
+        .map_err(|e| format!("{}: {}", source, e))
+}
+
+/// Load the built-in quirks, then every quirks file in `dir`.
+///
+/// Files that can't be read or parsed are skipped; the reasons why are
+/// returned alongside the quirks that could be loaded.
+pub fn load_quirks(dir: &Path) -> (Vec<SourcedQuirk>, Vec<String>) {
+    let mut quirks = Vec::new();
+    let mut errors = Vec::new();
+    match parse_quirks(BUILTIN_SOURCE, BUILTIN_QUIRKS) {
+        Ok(q)   => quirks.extend(q),
+        Err(e)  => errors.push(e),
+    }
+    let mut files: Vec<PathBuf> = match read_dir(dir) {
+        Ok(entries) => entries.flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| QUIRK_FILE_EXT == e))
+            .collect(),
+        Err(e)  => {
+            debug!("Not loading quirks from {}: {}", dir.to_string_lossy(), e);
+            Vec::new()
+        },
+    };
+    files.sort();
+    for path in files {
+        let mut contents = String::new();
+        let parsed = File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| format!("{}: {}", path.to_string_lossy(), e))
+            .and_then(|_| parse_quirks(&path.to_string_lossy(), &contents));
+        match parsed {
+            Ok(q)   => quirks.extend(q),
+            Err(e)  => errors.push(e),
+        }
+    }
+    (quirks, errors)
+}
+
+/// The quirks that apply to this device.
+pub fn matching_quirks(dmi: &Dmi, quirks: Vec<SourcedQuirk>) -> Vec<SourcedQuirk> {
+    quirks.into_iter().filter(|q| q.quirk.matches(dmi)).collect()
+}
+
+/// Merge the backend options from `quirks`, later ones winning.
+pub fn merge_options(quirks: &[SourcedQuirk]) -> HashMap<String, HashMap<String, String>> {
+    let mut optmap: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for q in quirks {
+        for (backend, opts) in q.quirk.backend_options.iter() {
+            let bopts = optmap.entry(backend.to_owned()).or_default();
+            for (name, value) in opts {
+                bopts.insert(name.to_owned(), value.to_string());
+            }
+        }
+    }
+    optmap
+}
+
+/// Gets the directory DMI identifiers are read from.
+pub fn get_dmi_root() -> PathBuf {
+    PathBuf::from(arg_value("dmi_root").unwrap_or_else(|| DEFAULT_DMI_ROOT.to_owned()))
+}
+
+/// Gets the directory user quirk files are read from.
+pub fn get_quirks_dir() -> PathBuf {
+    PathBuf::from(arg_value("quirks_dir").unwrap_or_else(|| DEFAULT_QUIRKS_DIR.to_owned()))
+}
+
+/// The backend options from every quirk (built-in or in `quirks_dir`)
+/// matching the device described in `dmi_root`.
+pub fn quirk_backend_options(dmi_root: &Path, quirks_dir: &Path)
+    -> HashMap<String, HashMap<String, String>>
+{
+    let dmi = Dmi::from_dir(dmi_root);
+    let (quirks, errors) = load_quirks(quirks_dir);
+    for e in errors {
+        warn!("Ignoring quirks file {}", e);
+    }
+    let matched = matching_quirks(&dmi, quirks);
+    for q in matched.iter() {
+        info!("Applying quirk '{}' (from {})", q.quirk.name, q.source);
+    }
+    merge_options(&matched)
+}
+
+/// Describe the DMI identifiers, the matching quirks, and the options
+/// they result in (for `--dump-quirks`).
+pub fn dump_quirks() -> String {
+    let dmi_root = get_dmi_root();
+    let quirks_dir = get_quirks_dir();
+    let dmi = Dmi::from_dir(&dmi_root);
+    let (quirks, errors) = load_quirks(&quirks_dir);
+    let total = quirks.len();
+    let matched = matching_quirks(&dmi, quirks);
+
+    let mut out = format!("DMI identifiers (from {}):\n{}\n\n",
+                          dmi_root.to_string_lossy(), dmi);
+    if !errors.is_empty() {
+        out += "Ignored quirks files:\n";
+        for e in errors {
+            out += &format!("    {}\n", e.trim_end().replace('\n', "\n    "));
+        }
+        out += "\n";
+    }
+    out += &format!("{} of {} quirks (built-in and from {}) match:\n",
+                    matched.len(), total, quirks_dir.to_string_lossy());
+    for q in matched.iter() {
+        out += &format!("    {} (from {})\n", q.quirk.name, q.source);
+    }
+    out += "\nResulting backend options:\n";
+    let mut merged: Vec<(String, HashMap<String, String>)> = merge_options(&matched).into_iter().collect();
+    merged.sort_by(|a, b| a.0.cmp(&b.0));
+    for (backend, opts) in merged {
+        let mut opts: Vec<(String, String)> = opts.into_iter().collect();
+        opts.sort();
+        out += &format!("    {}:\n", backend);
+        for (name, value) in opts {
+            out += &format!("        {} = {}\n", name, value);
+        }
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use testutil::TempDir;
+
+    /// The backends the built-in quirks are for
+    const ACCEL_BACKENDS: [&str; 6] = ["fsaccel", "fsaccel_raw", "iioaccel", "iioaccel_raw",
+                                       "evdevaccel", "evdevaccel_raw"];
+
+    /// Make a fake `/sys/class/dmi/id` in `dir`.
+    fn dmi_fixture(dir: &Path, sys_vendor: &str, product_name: &str) -> PathBuf {
+        let root = dir.join("dmi");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("sys_vendor"), format!("{}\n", sys_vendor)).unwrap();
+        fs::write(root.join("product_name"), format!("{}\n", product_name)).unwrap();
+        root
+    }
+
+    #[test]
+    fn builtin_quirks_parse() {
+        let quirks = parse_quirks(BUILTIN_SOURCE, BUILTIN_QUIRKS).unwrap();
+        assert!(!quirks.is_empty());
+        for q in quirks {
+            assert!(!q.quirk.backend_options.is_empty(), "{} does nothing", q.quirk.name);
+            for backend in &ACCEL_BACKENDS {
+                assert!(q.quirk.backend_options.contains_key(*backend),
+                        "{} doesn't cover {}", q.quirk.name, backend);
+            }
+        }
+    }
+
+    #[test]
+    fn applies_builtin_quirk() {
+        let dir = TempDir::new("quirks");
+        let dmi_root = dmi_fixture(dir.path(), "ASUSTeK COMPUTER INC.", "T100TA");
+        let opts = quirk_backend_options(&dmi_root, &dir.join("quirks.d"));
+        for backend in &ACCEL_BACKENDS {
+            assert_eq!(Some("0, -1, 0; -1, 0, 0; 0, 0, 1"),
+                       opts.get(*backend).and_then(|o| o.get("mount_matrix")).map(|m| m.as_str()),
+                       "for {}", backend);
+        }
+    }
+
+    #[test]
+    fn quirk_files_override_builtin() {
+        let dir = TempDir::new("quirks");
+        let dmi_root = dmi_fixture(dir.path(), "ASUSTeK COMPUTER INC.", "T100TA");
+        let quirks_dir = dir.join("quirks.d");
+        fs::create_dir_all(&quirks_dir).unwrap();
+        fs::write(quirks_dir.join("t100.toml"), r#"
+[[quirk]]
+name = "My T100"
+product_name = "T100*"
+
+[quirk.backend-options.fsaccel]
+mount_matrix = "1, 0, 0; 0, 1, 0; 0, 0, 1"
+scale = 0.5
+"#).unwrap();
+        fs::write(quirks_dir.join("ignored.txt"), "not a quirk").unwrap();
+        let opts = quirk_backend_options(&dmi_root, &quirks_dir);
+        let fsaccel = &opts["fsaccel"];
+        assert_eq!("1, 0, 0; 0, 1, 0; 0, 0, 1", fsaccel["mount_matrix"]);
+        assert_eq!("0.5", fsaccel["scale"]);
+        assert_eq!("0, -1, 0; -1, 0, 0; 0, 0, 1", opts["iioaccel"]["mount_matrix"]);
+    }
+
+    #[test]
+    fn no_quirks_for_other_devices() {
+        let dir = TempDir::new("quirks");
+        let dmi_root = dmi_fixture(dir.path(), "ASUSTeK COMPUTER INC.", "UX305CA");
+        let opts = quirk_backend_options(&dmi_root, &dir.join("quirks.d"));
+        assert!(opts.is_empty(), "got {:?}", opts);
+    }
+}
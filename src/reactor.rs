@@ -0,0 +1,247 @@
+//! # reactor
+//! What the main loop waits on between readings: file descriptors (sensors,
+//! sockets, signals) and deadlines (timers), all with one `poll(2)`.
+
+use libc;
+
+use std::ffi::CString;
+use std::io::Error as IoError;
+use std::io::ErrorKind as IoErrorKind;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Everything the main loop should wake up for.
+///
+/// It's built up fresh every time around the loop, by everything that
+/// wants a say.
+pub struct Wakeup {
+    pollfds: Vec<libc::pollfd>,
+    deadline: Option<Instant>,
+}
+
+impl Wakeup {
+    pub fn new() -> Wakeup {
+        Wakeup {
+            pollfds: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// Wake up when `fd` is readable (or hung up).
+    pub fn fd(&mut self, fd: RawFd) {
+        self.pollfds.push(libc::pollfd { fd, events: libc::POLLIN, revents: 0 });
+    }
+
+    /// Wake up by `when` at the latest.
+    pub fn at(&mut self, when: Instant) {
+        self.deadline = Some(self.deadline.map_or(when, |d| d.min(when)));
+    }
+
+    /// Wake up within `after` at the latest.
+    pub fn after(&mut self, after: Duration) {
+        self.at(Instant::now() + after);
+    }
+
+    /// Wait until one of the file descriptors is ready or the deadline
+    /// passes. With neither, waits until a signal interrupts it.
+    ///
+    /// Being interrupted by a signal isn't an error.
+    pub fn wait(&mut self) -> Result<(), IoError> {
+        let timeout = match self.deadline {
+            // Rounded up, so we don't wake up just before the deadline
+            Some(d) => {
+                let left = d.saturating_duration_since(Instant::now());
+                (left.as_micros().div_ceil(1000)).min(libc::c_int::MAX as u128) as libc::c_int
+            },
+            None    => -1,
+        };
+        let n = unsafe {
+            libc::poll(self.pollfds.as_mut_ptr(), self.pollfds.len() as libc::nfds_t, timeout)
+        };
+        if n < 0 {
+            let e = IoError::last_os_error();
+            if IoErrorKind::Interrupted != e.kind() {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `fd` was ready after the last `wait`.
+    pub fn is_ready(&self, fd: RawFd) -> bool {
+        self.pollfds.iter().any(|p| fd == p.fd && 0 != p.revents)
+    }
+}
+
+/// Signals, delivered through a file descriptor instead of a handler.
+///
+/// The signals are blocked for as long as this exists, so they're only
+/// seen when read from here.
+pub struct SignalFd {
+    fd: RawFd,
+    mask: libc::sigset_t,
+}
+
+impl SignalFd {
+    pub fn new(signals: &[libc::c_int]) -> Result<SignalFd, IoError> {
+        unsafe {
+            let mut mask: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut mask);
+            for &s in signals {
+                libc::sigaddset(&mut mask, s);
+            }
+            let e = libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
+            if 0 != e {
+                return Err(IoError::from_raw_os_error(e));
+            }
+            let fd = libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC);
+            if fd < 0 {
+                let e = IoError::last_os_error();
+                libc::pthread_sigmask(libc::SIG_UNBLOCK, &mask, std::ptr::null_mut());
+                return Err(e);
+            }
+            Ok(SignalFd { fd, mask })
+        }
+    }
+
+    /// The next signal that's come in, if any.
+    pub fn next(&mut self) -> Result<Option<libc::c_int>, IoError> {
+        let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+        loop {
+            let n = unsafe {
+                libc::read(self.fd, &mut info as *mut _ as *mut libc::c_void,
+                           mem::size_of::<libc::signalfd_siginfo>())
+            };
+            if n < 0 {
+                let e = IoError::last_os_error();
+                match e.kind() {
+                    IoErrorKind::WouldBlock => return Ok(None),
+                    IoErrorKind::Interrupted    => continue,
+                    _   => return Err(e),
+                }
+            }
+            return Ok(Some(info.ssi_signo as libc::c_int));
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for SignalFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+            libc::pthread_sigmask(libc::SIG_UNBLOCK, &self.mask, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Changes to a file (including it being made, deleted, or replaced),
+/// through inotify.
+///
+/// It's the directory that's watched, so the file doesn't need to exist.
+pub struct FileWatch {
+    fd: RawFd,
+    /// The file's name in the directory
+    name: Vec<u8>,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path) -> Result<FileWatch, IoError> {
+        let invalid = || IoError::new(IoErrorKind::InvalidInput,
+                                      format!("can't watch {}", path.display()));
+        let name = path.file_name().ok_or_else(invalid)?.as_bytes().to_owned();
+        let dir = match path.parent() {
+            Some(d) if ! d.as_os_str().is_empty()   => d,
+            _   => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes()).map_err(|_| invalid())?;
+        unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Err(IoError::last_os_error());
+            }
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_CREATE | libc::IN_DELETE
+                | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+            if libc::inotify_add_watch(fd, dir.as_ptr(), mask) < 0 {
+                let e = IoError::last_os_error();
+                libc::close(fd);
+                return Err(e);
+            }
+            Ok(FileWatch { fd, name })
+        }
+    }
+
+    /// Whether the file has changed since this was last called.
+    pub fn changed(&mut self) -> Result<bool, IoError> {
+        let header = mem::size_of::<libc::inotify_event>();
+        // Enough for plenty of events with the longest names
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let n = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if n < 0 {
+                let e = IoError::last_os_error();
+                match e.kind() {
+                    IoErrorKind::WouldBlock => return Ok(changed),
+                    IoErrorKind::Interrupted    => continue,
+                    _   => return Err(e),
+                }
+            }
+            let n = n as usize;
+            if 0 == n {
+                return Ok(changed);
+            }
+            let mut at = 0;
+            while at + header <= n {
+                let event: libc::inotify_event = unsafe {
+                    std::ptr::read_unaligned(buf[at..].as_ptr() as *const libc::inotify_event)
+                };
+                let len = event.len as usize;
+                // The name is padded out with NULs
+                let name = buf[at + header..(at + header + len).min(n)].split(|&b| 0 == b)
+                    .next().unwrap_or(&[]);
+                // If events were lost, the file might have changed.
+                if name == &self.name[..] || 0 != event.mask & libc::IN_Q_OVERFLOW {
+                    changed = true;
+                }
+                at += header + len;
+            }
+        }
+    }
+}
+
+impl AsRawFd for FileWatch {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for FileWatch {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// The name of a signal, for logging.
+pub fn signal_name(signal: libc::c_int) -> String {
+    match signal {
+        libc::SIGHUP    => "SIGHUP".to_owned(),
+        libc::SIGINT    => "SIGINT".to_owned(),
+        libc::SIGTERM   => "SIGTERM".to_owned(),
+        libc::SIGUSR1   => "SIGUSR1".to_owned(),
+        libc::SIGUSR2   => "SIGUSR2".to_owned(),
+        s   => format!("signal {}", s),
+    }
+}
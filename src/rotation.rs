@@ -0,0 +1,168 @@
+//! # rotation
+//! The screen rotations spinnrd sends, and how they're written and read.
+
+use std::fmt::{Display, Formatter};
+use std::fmt::Result as FmtResult;
+use std::str::FromStr;
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Rotation {
+    Normal,
+    Left,
+    Inverted,
+    Right,
+}
+use self::Rotation::*;
+
+/// Why a string couldn't be parsed as a `Rotation`
+#[derive(Debug)]
+pub enum RotParseErrKind {
+    TooShort,
+    TooLong,
+    NoMatch,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::Normal
+    }
+}
+
+impl Rotation {
+    /// How many quarter turns (in the order xrandr goes round) this is
+    /// from normal.
+    fn quarter_turns(self) -> u8 {
+        match self {
+            Normal  => 0,
+            Left    => 1,
+            Inverted    => 2,
+            Right   => 3,
+        }
+    }
+
+    fn from_quarter_turns(turns: u8) -> Rotation {
+        match turns % 4 {
+            0   => Normal,
+            1   => Left,
+            2   => Inverted,
+            _   => Right,
+        }
+    }
+
+    /// This rotation, counted from `natural` instead of from normal (so
+    /// `natural` itself becomes normal).
+    pub fn relative_to(self, natural: Rotation) -> Rotation {
+        Rotation::from_quarter_turns(4 + self.quarter_turns() - natural.quarter_turns())
+    }
+}
+
+/// The rotation to send when `detected` is: counted from `natural`, or
+/// nothing if that isn't one of `allowed` (so the screen stays as it was).
+pub fn map_rotation(detected: Rotation, natural: Rotation, allowed: &[Rotation]) -> Option<Rotation> {
+    Some(detected.relative_to(natural)).filter(|r| allowed.contains(r))
+}
+
+impl Display for Rotation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            &Normal => write!(f, "normal"),
+            &Left   => write!(f, "left"),
+            &Inverted   => write!(f, "inverted"),
+            &Right  => write!(f, "right"),
+        }
+    }
+}
+
+impl Display for RotParseErrKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            RotParseErrKind::TooShort   => write!(f, "rotation is empty"),
+            RotParseErrKind::TooLong    => write!(f, "too long to be a rotation"),
+            RotParseErrKind::NoMatch    => write!(f, "not a rotation"),
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = RotParseErrKind;
+
+    /// Parse a rotation as written by `Display` (surrounding whitespace
+    /// is ignored).
+    fn from_str(s: &str) -> Result<Rotation, RotParseErrKind> {
+        match s.trim() {
+            "normal"    => Ok(Normal),
+            "left"      => Ok(Left),
+            "inverted"  => Ok(Inverted),
+            "right"     => Ok(Right),
+            ""  => Err(RotParseErrKind::TooShort),
+            s if s.len() > "inverted".len() => Err(RotParseErrKind::TooLong),
+            _   => Err(RotParseErrKind::NoMatch),
+        }
+    }
+}
+
+/// Whether the device is lying flat, and which way up.
+///
+/// This is separate from the `Rotation`, which stays put while it's flat.
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Posture {
+    Upright,
+    /// Lying flat with the screen facing up
+    FaceUp,
+    /// Lying flat with the screen facing down
+    FaceDown,
+}
+
+impl Display for Posture {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Posture::Upright    => write!(f, "upright"),
+            Posture::FaceUp     => write!(f, "face-up"),
+            Posture::FaceDown   => write!(f, "face-down"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_to_natural() {
+        let table = [
+            // natural, detected, relative
+            (Normal,    Normal,     Normal),
+            (Normal,    Left,       Left),
+            (Normal,    Inverted,   Inverted),
+            (Normal,    Right,      Right),
+            (Left,      Normal,     Right),
+            (Left,      Left,       Normal),
+            (Left,      Inverted,   Left),
+            (Left,      Right,      Inverted),
+            (Inverted,  Normal,     Inverted),
+            (Inverted,  Left,       Right),
+            (Inverted,  Inverted,   Normal),
+            (Inverted,  Right,      Left),
+            (Right,     Normal,     Left),
+            (Right,     Left,       Inverted),
+            (Right,     Inverted,   Right),
+            (Right,     Right,      Normal),
+        ];
+        for &(natural, detected, relative) in table.iter() {
+            assert_eq!(relative, detected.relative_to(natural),
+                       "{} from {}", detected, natural);
+        }
+    }
+
+    #[test]
+    fn excluded_rotations_map_to_nothing() {
+        let allowed = [Normal, Left, Right];
+        assert_eq!(Some(Left), map_rotation(Left, Normal, &allowed));
+        assert_eq!(None, map_rotation(Inverted, Normal, &allowed));
+        // It's the rotation after --natural that has to be allowed.
+        assert_eq!(None, map_rotation(Normal, Inverted, &allowed));
+        assert_eq!(Some(Normal), map_rotation(Inverted, Inverted, &allowed));
+        assert_eq!(None, map_rotation(Left, Left, &[]));
+    }
+}
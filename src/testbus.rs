@@ -0,0 +1,70 @@
+//! # testbus
+//! A private D-Bus session bus for the tests that talk D-Bus, so they
+//! don't need (or disturb) a real one.
+//!
+//! libdbus only looks up the session bus address once, so every test in
+//! the process shares one `dbus-daemon`; tests keep out of each other's
+//! way by using their own bus names.
+
+use std::env;
+use std::io::{BufRead, BufReader};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+
+use libc;
+
+lazy_static! {
+    /// The address of the private bus, if it could be started
+    static ref ADDRESS: Option<String> = start_daemon();
+}
+
+/// Start a `dbus-daemon` that lives as long as the tests do.
+///
+/// It's killed when the thread that started it exits, so that thread never
+/// does.
+fn start_daemon() -> Option<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut command = Command::new("dbus-daemon");
+        command.args(["--session", "--print-address", "--nofork"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        unsafe {
+            command.pre_exec(|| {
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+                Ok(())
+            });
+        }
+        let mut daemon = match command.spawn() {
+            Ok(d)   => d,
+            Err(e)  => {
+                eprintln!("can't start dbus-daemon: {}", e);
+                let _ = tx.send(None);
+                return;
+            },
+        };
+        let mut address = String::new();
+        let read = BufReader::new(daemon.stdout.take().unwrap()).read_line(&mut address);
+        let _ = tx.send(read.ok().filter(|&n| n > 0).map(|_| address.trim().to_owned()));
+        loop {
+            thread::park();
+        }
+    });
+    let address = rx.recv().ok().flatten()?;
+    env::set_var("DBUS_SESSION_BUS_ADDRESS", &address);
+    Some(address)
+}
+
+/// Point the session bus at the private one, starting it if need be.
+///
+/// Returns `false` (and the test should be skipped) if there's no
+/// `dbus-daemon` to start.
+pub fn session_bus() -> bool {
+    if ADDRESS.is_none() {
+        eprintln!("skipping: no private session bus");
+        return false;
+    }
+    true
+}
@@ -0,0 +1,41 @@
+//! # testutil
+//! Helpers shared by the tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Tells apart the directories made by one process
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A scratch directory, removed (with everything in it) when it's dropped,
+/// so a test that fails doesn't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Make a fresh, empty directory named after `name`.
+    pub fn new(name: &str) -> TempDir {
+        let path = ::std::env::temp_dir().join(format!(
+                "spinnrd-{}-{}-{}", name, ::std::process::id(),
+                NEXT_DIR.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, p: P) -> PathBuf {
+        self.path.join(p)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}